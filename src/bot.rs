@@ -1,15 +1,91 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::distributions::{Alphanumeric, DistString};
+use chrono::{DateTime, Datelike, Months, NaiveDateTime, NaiveTime, Utc, Weekday};
 use teloxide::{
     dispatching::{
         dialogue::{InMemStorage, InMemStorageError},
         HandlerExt
-    }, prelude::*, utils::command::BotCommands
+    }, prelude::*, requests::Output, types::{
+        InlineKeyboardButton, InlineKeyboardMarkup, InlineQueryResult, InlineQueryResultArticle, InputFile, InputMessageContent,
+        InputMessageContentText, KeyboardButton, KeyboardMarkup, ParseMode
+    }, utils::{command::{BotCommands, ParseError}, html}
 };
 use thiserror::Error;
-use crate::db::{CategoryRow, DB};
+use tracing::instrument;
+use crate::currency;
+use crate::db::{self, CategoryDelta, CategoryRow, ParentUpdate, StatFilter, DB};
+use crate::export::{export_csv, CsvFormat};
+use crate::metrics::Metrics;
+use crate::ratelimit::RateLimiter;
+use crate::rates::{CachedRateProvider, HttpRateProvider, RateProvider, RATE_CACHE_TTL};
+use crate::recurring::Frequency;
+use crate::period::{
+    local_cycle_bounds, local_month_bounds, month_bounds, elapsed_fraction, parse_date, parse_period, week_bounds, PeriodError
+};
+use crate::report::{budget_runway, compare_to_average, describe_delta, ordinal, Runway, Confidence, format_date, goal_progress, percent_change, progress_bar, render_histogram, render_pivot, render_heatmap, render_tree, savings_rate, sparkline, split_evenly};
+use crate::settings::{flag, Lang, Setting, Settings};
 
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
 
+/// Command shortcuts, mirrors the `alias` attributes of `Command`
+const SHORTCUTS: &[(&str, &str)] = &[
+    ("listcategory", "lc"),
+    ("addcategory", "nc"),
+    ("updatecategory", "uc"),
+    ("addcost", "cost"),
+    ("removelastcost", "rm"),
+    ("statthismonth", "stm"),
+    ("statperiod", "sp"),
+    ("catmonth", "cm"),
+    ("catweek", "cw"),
+    ("statexcept", "se"),
+    ("setbudget", "sb"),
+    ("budget", "bud"),
+    ("settimezone", "tz"),
+    ("addalias", "aa"),
+];
+
+const SEND_MAX_ATTEMPTS: u32 = 4;
+const SEND_BASE_DELAY: Duration = Duration::from_millis(500);
+const SEND_MAX_DELAY: Duration = Duration::from_secs(8);
+/// In UTF-16 code units
+const MESSAGE_MAX_CHARS: usize = 4096;
+const KEYBOARD_COLUMNS: usize = 3;
+const DAILY_AVG_MONTHS: u32 = 3;
+const SPARKLINE_MONTHS: u32 = 12;
+const RECENT_COSTS: usize = 10;
+const PIVOT_MONTHS: u32 = 3;
+const MOVERS_TOP: usize = 3;
+const TYPICAL_DAY_WINDOW: i64 = 30;
+const HISTOGRAM_EDGES: [f64; 3] = [10.0, 50.0, 100.0];
+const RENAME_CALLBACK: &str = "rename:";
+const HISTORY_SIZE: i64 = 20;
+const API_TOKEN_LEN: usize = 32;
+const DUPLICATE_WINDOW_SECS: i64 = 10 * 60;
+const RECURRING_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const UNCATEGORIZED_ALIAS: &str = "uncategorized";
+const UNCATEGORIZED_NAME: &str = "Uncategorized";
+const MISC_ALIAS: &str = "misc";
+const MISC_NAME: &str = "Misc";
+
+
+impl From<ChatId> for db::ChatId {
+    fn from(chat_id: ChatId) -> Self {
+        db::ChatId(chat_id.0)
+    }
+}
+
+impl From<db::ChatId> for ChatId {
+    fn from(chat_id: db::ChatId) -> Self {
+        ChatId(chat_id.0)
+    }
+}
+
 
 #[derive(Clone, Default)]
 pub enum State {
@@ -32,6 +108,39 @@ pub enum State {
     },
     NewCostReceiveAmount {
         id: i64
+    },
+    ConfirmAction {
+        action: PendingAction
+    },
+    /// Portions of one purchase collected so far as `(category_id, amount)`
+    SplitCostReceivePortion {
+        total: f64,
+        portions: Vec<(i64, f64)>
+    }
+}
+
+/// Actions waiting for a "yes" from the user
+#[derive(Clone)]
+pub enum PendingAction {
+    ClearCosts,
+    DeleteRange { from: DateTime<Utc>, to: DateTime<Utc> },
+    ShiftDates { hours: i64 },
+    CreateCost(NewCost)
+}
+
+/// Cost held back until the user confirms it
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewCost {
+    category_id: i64,
+    amount: f64,
+    currency: Option<String>,
+    dt: Option<DateTime<Utc>>,
+    user_id: Option<i64>
+}
+
+impl NewCost {
+    fn new(category_id: i64, amount: f64) -> Self {
+        Self { category_id, amount, currency: None, dt: None, user_id: None }
     }
 }
 
@@ -42,104 +151,666 @@ pub enum BotError {
     #[error("db error: {0}")]
     DB(#[from] crate::db::DBError),
     #[error("inmem storage: {0}")]
-    InMemStorage(#[from] InMemStorageError)
+    InMemStorage(#[from] InMemStorageError),
+    #[error("message not sent after {0} attempts")]
+    RetriesExhausted(u32)
 }
 
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule="lowercase")]
 enum Command {
-    #[command(description="help")]
-    Help,
+    #[command(description="help (\"full\" for shortcuts)")]
+    Help { mode: String },
     #[command(description="Start the bot")]
     Start,
     #[command(description="List of categories", alias="lc")]
     ListCategory,
+    #[command(description="Move category to a place in the list (alias position)", parse_with="split")]
+    SetOrder { alias: String, pos: usize },
+    #[command(description="Category details and note (alias)")]
+    CatInfo { alias: String },
+    #[command(description="Set a category note, empty text clears it (alias text)", parse_with=parse_cat_info)]
+    SetCatInfo { alias: String, text: String },
+    #[command(description="Make a category a subcategory, \"none\" makes it top-level (child parent)", parse_with="split")]
+    SetParent { child: String, parent: String },
     #[command(description="New category", alias="nc")]
     AddCategory,
+    #[command(description="Several new categories (alias:name, alias:name, ...)")]
+    AddCategories { rest: String },
     #[command(description="Update category", alias="uc")]
     UpdateCategory,
-    #[command(description="Add cost (alias YYYY-MM-DD XX.XX)", alias="cost", parse_with="split")]
+    #[command(description="Add cost (alias YYYY-MM-DD [HH:MM] XX.XX)", alias="cost", parse_with=parse_add_cost)]
     AddCost { alias: String, date: String, amount: f64 },
+    #[command(description="Add cost, words in any order (alias XX.XX [YYYY-MM-DD])")]
+    Log { rest: String },
+    #[command(description="Add today's cost in a currency (alias XX.XX CODE)", parse_with="split")]
+    AddCostIn { alias: String, amount: f64, code: String },
+    #[command(description="Add today's cost to misc, categorize later (XX.XX)")]
+    Misc { amount: f64 },
+    #[command(description="Move all misc costs to a category (alias)")]
+    Recategorize { alias: String },
+    #[command(description="Split an amount evenly (XX.XX people)", parse_with="split")]
+    Split { amount: f64, people: u32 },
+    #[command(description="Log one purchase across several categories (XX.XX)")]
+    SplitCost { total: f64 },
+    #[command(description="Set amount logged by /logdefault, 0 clears it (alias XX.XX)", parse_with="split")]
+    SetDefaultAmount { alias: String, amount: f64 },
+    #[command(description="Add today's cost of the category default amount (alias)")]
+    LogDefault { alias: String },
     #[command(description="Remove last cost", alias="rm")]
     RemoveLastCost,
+    #[command(description="Last costs, 📎 marks an attached receipt")]
+    Recent,
+    #[command(description="Remove last N costs")]
+    Undo { n: i64 },
+    #[command(description="Likely duplicates: same category and amount within minutes")]
+    Dupes,
+    #[command(description="Remove a cost by its id from /dupes (id)")]
+    RemoveCost { id: i64 },
     #[command(description="Stat this month", alias="stm")]
     StatThisMonth,
+    #[command(description="Your own stat this month in a shared chat")]
+    MyStat,
+    #[command(description="Stat this month with subcategories counted under their parents")]
+    StatGrouped,
+    #[command(description="This month as a category tree with subtotals")]
+    Tree,
+    #[command(description="Share of each category in this month's spendings")]
+    Shares,
+    #[command(description="This month's stat as a receipt")]
+    Receipt,
+    #[command(description="Stat this month converted to a currency (CODE)")]
+    StatBase { code: String },
+    #[command(description="When tracking started")]
+    FirstCost,
+    #[command(description="Exact bounds of \"this month\" under current settings")]
+    Period,
+    #[command(description="Stat from a date until now (YYYY-MM-DD)")]
+    Since { date: String },
     #[command(description="Overall stat in period (YYYY-MM-DD YYYY-MM-DD)", alias="sp", parse_with="split")]
     StatPeriod { date_from: String, date_to: String }, 
+    #[command(description="Category stat this month (alias)", alias="cm")]
+    CatMonth { alias: String },
+    #[command(description="Category stat this week (alias)", alias="cw")]
+    CatWeek { alias: String },
+    #[command(description="Average daily spend in a category over 3 months (alias)")]
+    DailyAvg { alias: String },
+    #[command(description="Monthly totals of a category over the last year (alias)")]
+    Sparkline { alias: String },
+    #[command(description="Category by month table for the last 3 months")]
+    Pivot,
+    #[command(description="Today's spend vs your daily average over 30 days")]
+    TodayVsAvg,
+    #[command(description="Day with the highest spend this month")]
+    PeakDay,
+    #[command(description="Average monthly spend over the last N full months (N)")]
+    MovingAvg { window: u32 },
+    #[command(description="Costs this month by amount range ([edge edge ...])")]
+    Distribution { edges: String },
+    #[command(description="This month's pace vs last month")]
+    Trend,
+    #[command(description="Categories that changed the most since last month")]
+    Movers,
+    #[command(description="Projected total for this month")]
+    Forecast,
+    #[command(description="Categories used last month but not this month")]
+    Lapsed,
+    #[command(description="Most used category this month")]
+    MostUsed,
+    #[command(description="Daily spendings this month as a calendar")]
+    Heatmap,
+    #[command(description="Stat this month without a category (alias)", alias="se")]
+    StatExcept { alias: String },
+    #[command(description="Set monthly budget (alias XX.XX)", alias="sb", parse_with="split")]
+    SetBudget { alias: String, amount: f64 },
+    #[command(description="Set monthly budget as percent of income (alias XX)", parse_with="split")]
+    SetBudgetPercent { alias: String, percent: f64 },
+    #[command(description="Day a category budget runs out at the current pace (alias)")]
+    BudgetRunway { alias: String },
+    #[command(description="Budgets this month", alias="bud")]
+    Budget,
+    #[command(description="Delete all costs, keep categories")]
+    ClearCosts,
+    #[command(description="Delete costs in a period (YYYY-MM-DD YYYY-MM-DD)", parse_with="split")]
+    DeleteRange { from: String, to: String },
+    #[command(description="Move all cost times by hours, to fix costs logged before the timezone was set (hours)")]
+    ShiftDates { hours: i64 },
+    #[command(description="Stop logging plain messages, commands keep working")]
+    Mute,
+    #[command(description="Log plain messages again")]
+    Unmute,
+    #[command(description="Toggle recording commands for /history")]
+    Audit,
+    #[command(description="Last recorded commands")]
+    History,
+    #[command(description="Toggle whole amounts in summaries")]
+    RoundDisplay,
+    #[command(description="Toggle rounding costs up and saving the spare change")]
+    Roundup,
+    #[command(description="Spare change saved by rounding up")]
+    SpareChange,
+    #[command(description="Toggle reading whole numbers as cents (1250 is 12.50)")]
+    CentsInput,
+    #[command(description="Toggle refusing amounts without a category alias")]
+    StrictCategories,
+    #[command(description="Don't confirm successful adds")]
+    Quiet,
+    #[command(description="Confirm every add")]
+    Verbose,
+    #[command(description="Toggle logging bare amounts to Uncategorized")]
+    AutoUncategorized,
+    #[command(description="Set timezone (e.g. Europe/Berlin)", alias="tz")]
+    SetTimezone { tz: String },
+    #[command(description="Set date format (iso, en, ru)")]
+    SetLang { lang: String },
+    #[command(description="Send last week's summary on a weekday (mon..sun, off)")]
+    SetDigest { weekday: String },
+    #[command(description="Daily reminder to log spendings at a local time (HH:MM, off)")]
+    Remind { time: String },
+    #[command(description="Set monthly income (XX.XX)")]
+    SetIncome { amount: f64 },
+    #[command(description="Set day of month the budget cycle starts on (1-31)")]
+    SetCycleStart { day: u32 },
+    #[command(description="Ask before saving costs above the amount (0 to disable)")]
+    SetMaxTransaction { amount: f64 },
+    #[command(description="Savings this month")]
+    Savings,
+    #[command(description="Set savings goal (XX.XX YYYY-MM-DD)", parse_with="split")]
+    SetGoal { amount: f64, date: String },
+    #[command(description="Progress towards the savings goal")]
+    Goal,
+    #[command(description="Add extra alias to category (alias new_alias)", alias="aa", parse_with="split")]
+    AddAlias { existing: String, new: String },
+    #[command(description="Check if an alias is free (alias)")]
+    AliasFree { alias: String },
+    #[command(description="Show category first when picking one (alias)")]
+    Pin { alias: String },
+    #[command(description="Unpin category (alias)")]
+    Unpin { alias: String },
+    #[command(description="Archive category (alias)")]
+    Archive { alias: String },
+    #[command(description="Restore archived category (alias)")]
+    Unarchive { alias: String },
+    #[command(description="Move costs to another category and delete this one (from into)", parse_with="split")]
+    Retire { from: String, into: String },
+    #[command(description="Add recurring cost (alias XX.XX monthly|weekly day)", parse_with="split")]
+    AddRecurring { alias: String, amount: f64, frequency: String, day: u32 },
+    #[command(description="List recurring costs")]
+    Recurring,
+    #[command(description="Monthly amount committed to recurring costs")]
+    Committed,
+    #[command(description="Delete recurring cost (id)")]
+    DeleteRecurring { id: i64 },
+    #[command(description="Skip next occurrence of a recurring cost (id)")]
+    SkipRecurring { id: i64 },
+    #[command(description="Export category definitions as JSON")]
+    ExportCategories,
+    #[command(description="Import categories from exported JSON (json)")]
+    ImportCategories { json: String },
+    #[command(description="Copy categories to another chat (chat id)")]
+    CopyTo { chat_id: i64 },
+    #[command(description="New token for the JSON stats API, the old one stops working")]
+    ApiToken,
+    #[command(hide)]
+    Check,
+    #[command(description="Compare the stored lifetime total to your balance (XX.XX)")]
+    Reconcile { expected: f64 },
+    #[command(hide)]
+    Ping,
+    #[command(hide)]
+    Metrics,
+    #[command(hide)]
+    AdminStats,
+    #[command(description="Export costs as CSV ([delimiter] [decimal], e.g. \"; ,\")")]
+    Export { format: String },
+    #[cfg(feature = "pdf")]
+    #[command(description="This month's stat as a PDF")]
+    Pdf,
+    #[command(description="Compare two periods (YYYY-MM-DD x4)", parse_with="split")]
+    Diff { from1: String, to1: String, from2: String, to2: String },
+    #[command(description="Compare a category across two periods (alias YYYY-MM-DD x4)", parse_with="split")]
+    CatDiff { alias: String, from1: String, to1: String, from2: String, to2: String },
+}
+
+/// Delay before the next send attempt: Telegram's `retry_after` wins,
+/// otherwise exponential backoff capped at `SEND_MAX_DELAY`
+fn backoff_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    match retry_after {
+        Some(d) => d,
+        None => SEND_BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(SEND_MAX_DELAY)
+    }
+}
+
+async fn send_with_retry(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: impl Into<String>
+) -> Result<Message, BotError> {
+    retry_request(bot.send_message(chat_id, text.into())).await
 }
 
+/// Sends a prepared request, e.g. one with a keyboard or a parse mode,
+/// retrying like `send_with_retry`
+async fn retry_request<R>(request: R) -> Result<Output<R>, BotError>
+where
+    R: Request<Err = teloxide::RequestError>
+{
+    let mut attempt = 0;
+    loop {
+        let retry_after = match request.send_ref().await {
+            Ok(output) => return Ok(output),
+            Err(teloxide::RequestError::RetryAfter(secs)) => Some(secs.duration()),
+            Err(teloxide::RequestError::Network(_)) |
+            Err(teloxide::RequestError::InvalidJson { .. }) => None,
+            Err(e) => return Err(e.into())
+        };
+        attempt += 1;
+        if attempt >= SEND_MAX_ATTEMPTS {
+            return Err(BotError::RetriesExhausted(attempt));
+        }
+        tokio::time::sleep(backoff_delay(attempt - 1, retry_after)).await;
+    }
+}
+
+/// Telegram counts message length in UTF-16 code units
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Splits text into chunks of at most `limit` UTF-16 units, breaking on line boundaries.
+/// A single line longer than `limit` is cut into pieces
+fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for line in text.split('\n') {
+        let line_len = utf16_len(line);
+        let sep = if current.is_empty() { 0 } else { 1 };
+        if current_len + sep + line_len <= limit {
+            if sep == 1 {
+                current.push('\n');
+            }
+            current.push_str(line);
+            current_len += sep + line_len;
+            continue;
+        }
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        let mut pieces = vec![String::new()];
+        let mut piece_len = 0;
+        for c in line.chars() {
+            if piece_len > 0 && piece_len + c.len_utf16() > limit {
+                pieces.push(String::new());
+                piece_len = 0;
+            }
+            pieces.last_mut().unwrap().push(c);
+            piece_len += c.len_utf16();
+        }
+        current = pieces.pop().unwrap_or_default();
+        current_len = piece_len;
+        chunks.extend(pieces);
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+async fn send_long(bot: &Bot, chat_id: ChatId, text: impl Into<String>) -> Result<(), BotError> {
+    for chunk in split_message(&text.into(), MESSAGE_MAX_CHARS) {
+        send_with_retry(bot, chat_id, chunk).await?;
+    }
+    Ok(())
+}
+
+/// What the free-text handler decided to do with a message
+#[derive(Debug, PartialEq)]
+enum FreeTextAction {
+    Logged { id: i64 },
+    AskAmount { id: i64 },
+    AskAlias { amount: f64 },
+    /// Strict mode: the amount came without a known alias and is dropped
+    NeedAlias,
+    Confirm(NewCost),
+    Help,
+    Muted
+}
+
+/// Telegram user who sent the message, to tell people apart in group chats
+fn sender_id(msg: &Message) -> Option<i64> {
+    msg.from.as_ref().map(|user| user.id.0 as i64)
+}
+
+/// Cost entry words in any order: the amount, an optional `YYYY-MM-DD` date
+/// and the rest, one of which should be a category alias
+#[derive(Debug, PartialEq)]
+struct Entry<'a> {
+    amount: Option<f64>,
+    date: Option<DateTime<Utc>>,
+    words: Vec<&'a str>
+}
+
+/// With `integer_cents` an amount without a decimal point is read as cents
+fn parse_entry(text: &str, integer_cents: bool) -> Entry<'_> {
+    let mut entry = Entry { amount: None, date: None, words: Vec::new() };
+    for piece in text.split_whitespace() {
+        if let Ok(num) = piece.parse::<f64>() {
+            let bare = piece.bytes().all(|b| b.is_ascii_digit());
+            entry.amount = Some(if integer_cents && bare { num / 100.0 } else { num });
+        } else if let Some(dt) = parse_date(piece) {
+            entry.date = Some(dt);
+        } else {
+            entry.words.push(piece);
+        }
+    }
+    entry
+}
+
+/// Last of the words that is a category alias
+async fn find_alias(db: &DB, chat_id: ChatId, words: &[&str]) -> Result<Option<CategoryRow>, BotError> {
+    let mut found = None;
+    for word in words {
+        if let Some(cat) = db.get_category_by_alias(chat_id.into(), word.to_string()).await? {
+            found = Some(cat);
+        }
+    }
+    Ok(found)
+}
+
+/// Looks for an amount and a category alias among the message words
+/// and stores the cost when both are known
+#[instrument(skip_all, fields(chat_id = chat_id.0), err)]
+async fn handle_free_text(
+    db: &DB,
+    chat_id: ChatId,
+    user_id: Option<i64>,
+    text: &str,
+    settings: &Settings
+) -> Result<FreeTextAction, BotError> {
+    if settings.muted {
+        return Ok(FreeTextAction::Muted);
+    }
+    let entry = parse_entry(text, settings.integer_cents_input);
+    let amount = entry.amount;
+    let cat_id = find_alias(db, chat_id, &entry.words).await?.map(|cat| cat.id);
+    let action = match (amount, cat_id) {
+        (Some(amount), Some(cat_id)) if settings.exceeds_cap(amount) => {
+            FreeTextAction::Confirm(NewCost { user_id, dt: entry.date, ..NewCost::new(cat_id, amount) })
+        },
+        (Some(amount), Some(cat_id)) => {
+            let id = db.create_cost_by(cat_id, amount, None, entry.date, user_id).await?;
+            FreeTextAction::Logged { id }
+        },
+        (None, Some(cat_id)) => FreeTextAction::AskAmount { id: cat_id },
+        (Some(_), None) if settings.strict_categories => FreeTextAction::NeedAlias,
+        (Some(amount), None) if settings.auto_uncategorized => {
+            let cat_id = db.get_or_create_category(
+                chat_id.into(),
+                UNCATEGORIZED_ALIAS.to_string(),
+                UNCATEGORIZED_NAME.to_string()
+            ).await?;
+            if settings.exceeds_cap(amount) {
+                FreeTextAction::Confirm(NewCost { user_id, dt: entry.date, ..NewCost::new(cat_id, amount) })
+            } else {
+                let id = db.create_cost_by(cat_id, amount, None, entry.date, user_id).await?;
+                FreeTextAction::Logged { id }
+            }
+        },
+        (Some(amount), None) => FreeTextAction::AskAlias { amount },
+        _ => FreeTextAction::Help
+    };
+    Ok(action)
+}
+
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn msg_handler(
     bot: Bot,
     dialogue: MyDialogue,
     msg: Message,
-    db: DB
+    db: DB,
+    metrics: Arc<Metrics>
+) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    let result = handle_message(bot.clone(), dialogue, msg, db, metrics).await;
+    report_storage_error(&bot, chat_id, result).await
+}
+
+async fn handle_message(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    db: DB,
+    metrics: Arc<Metrics>
 ) -> Result<(), BotError> {
     let chat_id = msg.chat.id;
     if let Some(text) = msg.text() {
-        let mut amount = None;
-        let mut cat_id = None;
-        for piece in text.split_whitespace() {
-            if let Ok(num) = piece.parse::<f64>() {
-                amount = Some(num);
-            }
-            if let Some(cat) = db.get_category_by_alias(chat_id, piece.to_string()).await? {
-                cat_id = Some(cat.id);
-            }
-        }
-        match (amount, cat_id) {
-            (Some(amount), Some(cat_id)) => {
-                db.create_cost(cat_id, amount, None).await?;
-                bot.send_message(chat_id, "Added!").await?;
+        let settings = db.get_settings(chat_id.into()).await?;
+        match handle_free_text(&db, chat_id, sender_id(&msg), text, &settings).await? {
+            FreeTextAction::Logged { id } => {
+                db.set_cost_message(id, msg.id.0).await?;
+                metrics.cost_created();
+                confirm_added(&bot, chat_id, &settings, "Added!").await?;
             },
-            (None, Some(cat_id)) => {
-                bot.send_message(chat_id, "How much?").await?;
-                dialogue.update(State::NewCostReceiveAmount { id: cat_id }).await?;
+            FreeTextAction::AskAmount { id } => {
+                send_with_retry(&bot, chat_id, "How much?").await?;
+                dialogue.update(State::NewCostReceiveAmount { id }).await?;
             },
-            (Some(amount), None) => {
-                bot.send_message(chat_id, "Specify category alias").await?;
+            FreeTextAction::AskAlias { amount } => {
+                let cats = db.get_categories(chat_id.into()).await?;
+                retry_request(
+                    bot.send_message(chat_id, "Specify category alias").reply_markup(category_keyboard(&cats))
+                ).await?;
                 dialogue.update(State::NewCostReceiveAlias { amount }).await?;
-            }
-            _ => { 
-                bot.send_message(chat_id, "/help").await?;
-            }
+            },
+            FreeTextAction::NeedAlias => {
+                let cats = db.get_categories(chat_id.into()).await?;
+                let text = match cats.is_empty() {
+                    true => "Create a category first with /addcategory".to_string(),
+                    false => format!(
+                        "Write the amount with one of the aliases, e.g. \"{} 12.5\"\n{}",
+                        cats[0].category.alias,
+                        cats.iter().map(|c| c.to_string()).collect::<Vec<_>>().join("\n")
+                    )
+                };
+                send_long(&bot, chat_id, text).await?;
+            },
+            FreeTextAction::Confirm(cost) => ask_cost_confirmation(&bot, &dialogue, chat_id, &settings, cost).await?,
+            FreeTextAction::Help => { 
+                send_with_retry(&bot, chat_id, "/help").await?;
+            },
+            FreeTextAction::Muted => {}
         }
+    } else if let (Some(photo), Some(entry)) = (msg.photo().and_then(|sizes| sizes.last()), msg.reply_to_message()) {
+        // only a reply to the entry message says which cost the receipt is for
+        match db.attach_photo(chat_id.into(), entry.id.0, photo.file.id.clone()).await? {
+            Some(_) => send_with_retry(&bot, chat_id, "Receipt attached").await?,
+            None => send_with_retry(&bot, chat_id, "Reply with the receipt to the message the cost was added with").await?
+        };
+    }
+    Ok(())
+}
+
+/// Where replies go, so handlers can be exercised without Telegram
+#[async_trait]
+trait Notifier: Send + Sync {
+    async fn notify(&self, chat_id: ChatId, text: &str) -> Result<(), BotError>;
+}
+
+#[async_trait]
+impl Notifier for Bot {
+    async fn notify(&self, chat_id: ChatId, text: &str) -> Result<(), BotError> {
+        send_with_retry(self, chat_id, text).await?;
+        Ok(())
+    }
+}
+
+/// Success reply for an add, skipped in quiet mode
+async fn confirm_added(notifier: &dyn Notifier, chat_id: ChatId, settings: &Settings, text: &str) -> Result<(), BotError> {
+    match settings.quiet {
+        true => Ok(()),
+        false => notifier.notify(chat_id, text).await
     }
+}
+
+/// Stores a cost that needs no confirmation and tells the user
+async fn save_cost(
+    db: &DB,
+    notifier: &dyn Notifier,
+    metrics: &Metrics,
+    chat_id: ChatId,
+    settings: &Settings,
+    cost: &NewCost,
+    reply: &str
+) -> Result<(), BotError> {
+    db.create_cost_by(cost.category_id, cost.amount, cost.currency.as_deref(), cost.dt, cost.user_id).await?;
+    metrics.cost_created();
+    confirm_added(notifier, chat_id, settings, reply).await
+}
+
+/// Parks a cost above the chat's cap until the user confirms it
+async fn ask_cost_confirmation(
+    bot: &Bot,
+    dialogue: &MyDialogue,
+    chat_id: ChatId,
+    settings: &Settings,
+    cost: NewCost
+) -> Result<(), BotError> {
+    let text = format!(
+        "{:.2} is above the {:.2} cap. Type \"yes\" to save it",
+        cost.amount, settings.max_transaction()
+    );
+    send_with_retry(bot, chat_id, text).await?;
+    dialogue.update(State::ConfirmAction { action: PendingAction::CreateCost(cost) }).await?;
     Ok(())
 }
 
+/// First word is the alias, the rest of the line is kept as is
+fn parse_cat_info(input: String) -> Result<(String, String), ParseError> {
+    let input = input.trim();
+    let (alias, text) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    if alias.is_empty() {
+        return Err(ParseError::Custom("expected alias text".into()));
+    }
+    Ok((alias.to_string(), text.trim().to_string()))
+}
+
+fn parse_add_cost(input: String) -> Result<(String, String, f64), ParseError> {
+    let parts = input.split_whitespace().collect::<Vec<_>>();
+    let (alias, date, amount) = match parts.as_slice() {
+        [alias, date, amount] => (alias, date.to_string(), amount),
+        [alias, date, time, amount] => (alias, format!("{date} {time}"), amount),
+        _ => return Err(ParseError::Custom("expected alias YYYY-MM-DD [HH:MM] XX.XX".into()))
+    };
+    let amount = amount.parse::<f64>().map_err(|e| ParseError::IncorrectFormat(e.into()))?;
+    Ok((alias.to_string(), date, amount))
+}
+
+/// Accepts `YYYY-MM-DD` (midnight) or `YYYY-MM-DD HH:MM`, both in UTC
+fn parse_cost_date(date: &str) -> Option<DateTime<Utc>> {
+    let dt = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(&(date.to_string() + " 00:00:00"), "%Y-%m-%d %H:%M:%S"))
+        .ok()?;
+    Some(DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn cmd_add_cost(
     bot: Bot,
+    dialogue: &MyDialogue,
     db: DB,
-    chat_id: ChatId,
+    metrics: &Metrics,
+    msg: &Message,
     alias: String,
     date: String,
     amount: f64
 ) -> Result<(), BotError> {
-    let cat = match db.get_category_by_alias(chat_id, alias).await? {
+    let chat_id = dialogue.chat_id();
+    let cat = match db.get_category_by_alias(chat_id.into(), alias).await? {
         Some(cat) => cat,
         None => {
-            bot.send_message(chat_id, "Provide existing category alias").await?;
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
             return Ok(());
         }
     };
-    let dt = match NaiveDateTime::parse_from_str(
-        &(date.to_string() + " 00:00:00"),
-        "%Y-%m-%d %H:%M:%S"
-    ) {
-        Ok(dt) => DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc),
-        Err(_) => {
-            bot.send_message(chat_id, "Provide date in YYYY-MM-DD format").await?;
+    let dt = match parse_cost_date(&date) {
+        Some(dt) => dt,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide date in YYYY-MM-DD or YYYY-MM-DD HH:MM format").await?;
             return Ok(());
         }
     };
-    db.create_cost(cat.id, amount, Some(dt)).await?;
-    bot.send_message(chat_id, "Created!").await?;
-    Ok(())
+    let settings = db.get_settings(chat_id.into()).await?;
+    let cost = NewCost { dt: Some(dt), user_id: sender_id(msg), ..NewCost::new(cat.id, amount) };
+    if settings.exceeds_cap(amount) {
+        return ask_cost_confirmation(&bot, dialogue, chat_id, &settings, cost).await;
+    }
+    save_cost(&db, &bot, metrics, chat_id, &settings, &cost, "Created!").await
+}
+
+/// Typed twin of free-text logging, the alias must exist
+async fn cmd_log(
+    bot: Bot,
+    dialogue: &MyDialogue,
+    db: DB,
+    metrics: &Metrics,
+    msg: &Message,
+    rest: String
+) -> Result<(), BotError> {
+    let chat_id = dialogue.chat_id();
+    let settings = db.get_settings(chat_id.into()).await?;
+    let entry = parse_entry(&rest, settings.integer_cents_input);
+    let cat = find_alias(&db, chat_id, &entry.words).await?;
+    let (Some(amount), Some(cat)) = (entry.amount, cat) else {
+        send_with_retry(&bot, chat_id, "Provide an amount and an existing category alias").await?;
+        return Ok(());
+    };
+    let cost = NewCost { dt: entry.date, user_id: sender_id(msg), ..NewCost::new(cat.id, amount) };
+    if settings.exceeds_cap(amount) {
+        return ask_cost_confirmation(&bot, dialogue, chat_id, &settings, cost).await;
+    }
+    save_cost(&db, &bot, metrics, chat_id, &settings, &cost, "Created!").await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn cmd_add_cost_in(
+    bot: Bot,
+    dialogue: &MyDialogue,
+    db: DB,
+    metrics: &Metrics,
+    msg: &Message,
+    alias: String,
+    amount: f64,
+    code: String
+) -> Result<(), BotError> {
+    let chat_id = dialogue.chat_id();
+    let cat = match db.get_category_by_alias(chat_id.into(), alias).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+            return Ok(());
+        }
+    };
+    let code = match currency::parse_code(&code) {
+        Some(code) => code,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide a three-letter currency code").await?;
+            return Ok(());
+        }
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let cost = NewCost { currency: Some(code), user_id: sender_id(msg), ..NewCost::new(cat.id, amount) };
+    if settings.exceeds_cap(amount) {
+        return ask_cost_confirmation(&bot, dialogue, chat_id, &settings, cost).await;
+    }
+    save_cost(&db, &bot, metrics, chat_id, &settings, &cost, "Created!").await
 }
 
 async fn cmd_list_categories(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
-    let cats = db.get_categories(chat_id).await?;
+    let cats = db.get_categories(chat_id.into()).await?;
     let to_sent = match cats.is_empty() {
         true => "No categories created".to_string(),
         false => format!(
@@ -147,13 +818,110 @@ async fn cmd_list_categories(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), Bo
             cats.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("\n")
         )
     };
-    bot.send_message(chat_id, to_sent).await?;
+    send_long(&bot, chat_id, to_sent).await?;
     Ok(())
 }
 
+/// First and last day of the period, `date_to` itself is not included
+fn period_header(date_from: DateTime<Utc>, date_to: DateTime<Utc>, settings: &Settings) -> String {
+    format!(
+        "{} - {}",
+        format_date(date_from, settings.timezone, settings.lang),
+        format_date(date_to - chrono::Duration::seconds(1), settings.timezone, settings.lang)
+    )
+}
+
+/// Budget cycle "this month" stands for, in the chat's timezone
+fn this_cycle(settings: &Settings) -> (DateTime<Utc>, DateTime<Utc>) {
+    local_cycle_bounds(Utc::now(), settings.fiscal_start_day, settings.timezone)
+}
+
+/// Exact bounds "this month" stands for under the chat settings, end excluded
+fn period_report(now: DateTime<Utc>, settings: &Settings) -> String {
+    let (date_from, date_to) = local_cycle_bounds(now, settings.fiscal_start_day, settings.timezone);
+    let local = |dt: DateTime<Utc>| dt.with_timezone(&settings.timezone).format("%Y-%m-%d %H:%M %Z").to_string();
+    format!(
+        "This month is counted\nfrom {} ({})\nto {} ({}), not included",
+        date_from.format("%Y-%m-%d %H:%M UTC"), local(date_from),
+        date_to.format("%Y-%m-%d %H:%M UTC"), local(date_to)
+    )
+}
+
 async fn cmd_stat_this_month(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
-    let stat = db.get_stat_this_month(chat_id).await?;
-    bot.send_message(chat_id, stat.to_string()).await?;
+    let settings = db.get_settings(chat_id.into()).await?;
+    let (date_from, date_to) = this_cycle(&settings);
+    let stat = db.get_stat_this_cycle(chat_id.into(), settings.fiscal_start_day, settings.timezone).await?
+        .round_display(settings.round_display);
+    let mut report = format!("{}\n{}", period_header(date_from, date_to, &settings), stat);
+    let other = db.currency_totals(chat_id.into(), date_from, date_to).await?
+        .into_iter()
+        .filter_map(|(currency, amount)| Some(format!("{}: {:.2}", currency?, amount)))
+        .collect::<Vec<_>>();
+    if !other.is_empty() {
+        report = format!("{}\n\nOther currencies, see /statbase:\n{}", report, other.join("\n"));
+    }
+    send_long(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_stat_base(
+    bot: Bot,
+    db: DB,
+    rates: &dyn RateProvider,
+    chat_id: ChatId,
+    code: String
+) -> Result<(), BotError> {
+    let base = match currency::parse_code(&code) {
+        Some(base) => base,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide a three-letter currency code").await?;
+            return Ok(());
+        }
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let (date_from, date_to) = local_month_bounds(Utc::now(), settings.timezone);
+    let totals = db.currency_totals(chat_id.into(), date_from, date_to).await?;
+
+    let mut table = HashMap::from([(base.clone(), 1.0)]);
+    for currency in totals.iter().filter_map(|(c, _)| c.as_ref()) {
+        match rates.rate(currency, &base).await {
+            Ok(rate) => {
+                table.insert(currency.clone(), rate);
+            },
+            Err(error) => tracing::warn!(%error, "exchange rate unavailable")
+        }
+    }
+
+    let stat = db.stat_in_base(chat_id.into(), date_from, date_to, &base, &table).await?;
+    let totals = totals.into_iter()
+        .map(|(currency, amount)| {
+            let currency = currency.unwrap_or(base.clone());
+            let unconverted = if table.contains_key(&currency) { "" } else { " (no rate, not included)" };
+            format!("{}: {:.2}{}", currency, amount, unconverted)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let report = format!("{} in {}\n{}\n\n{}", period_header(date_from, date_to, &settings), base, stat, totals);
+    send_long(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_since(bot: Bot, db: DB, chat_id: ChatId, date: String) -> Result<(), BotError> {
+    let date_from = match parse_date(&date) {
+        Some(date_from) if date_from <= Utc::now() => date_from,
+        Some(_) => {
+            send_with_retry(&bot, chat_id, "Date must not be in the future").await?;
+            return Ok(());
+        },
+        None => {
+            send_with_retry(&bot, chat_id, PeriodError::DateFrom.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let stat = db.get_stat(chat_id.into(), Some(date_from), None).await?.round_display(settings.round_display);
+    let header = format!("Since {}", format_date(date_from, settings.timezone, settings.lang));
+    send_long(&bot, chat_id, format!("{header}\n{stat}")).await?;
     Ok(())
 }
 
@@ -164,70 +932,1276 @@ async fn cmd_stat_period(
     date_from: String,
     date_to: String
 ) -> Result<(), BotError> {
-    let df = match NaiveDateTime::parse_from_str(
-        &(date_from + " 00:00:00"),
-        "%Y-%m-%d %H:%M:%S"
-    ) { 
-        Ok(df) => DateTime::<Utc>::from_naive_utc_and_offset(df, Utc),
-        Err(_) => {
-            bot.send_message(chat_id, "Provide date from in YYYY-MM-DD format").await?;
+    let (df, dt) = match parse_period(&date_from, &date_to) {
+        Ok(period) => period,
+        Err(e) => {
+            send_with_retry(&bot, chat_id, e.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let stat = db.get_stat(chat_id.into(), Some(df), Some(dt)).await?.round_display(settings.round_display);
+    let report = format!("{}\n{}", period_header(df, dt, &settings), stat);
+    send_long(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_cat_period(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    alias: String,
+    week: bool
+) -> Result<(), BotError> {
+    let cat = match db.find_category_by_alias(chat_id.into(), alias).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+            return Ok(());
+        }
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let stat = match week {
+        true => db.get_category_stat_this_week(chat_id.into(), cat.id).await?,
+        false => db.get_category_stat_this_month(chat_id.into(), cat.id).await?
+    }.round_display(settings.round_display);
+    send_long(&bot, chat_id, stat.to_string()).await?;
+    Ok(())
+}
+
+async fn cmd_set_budget(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    alias: String,
+    amount: f64,
+    of_income: bool
+) -> Result<(), BotError> {
+    let cat = match db.get_category_by_alias(chat_id.into(), alias).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+            return Ok(());
+        }
+    };
+    if amount <= 0.0 {
+        send_with_retry(&bot, chat_id, "Budget must be positive").await?;
+        return Ok(());
+    }
+    if of_income && !(0.0..=100.0).contains(&amount) {
+        send_with_retry(&bot, chat_id, "Percent of income can't be above 100").await?;
+        return Ok(());
+    }
+    match of_income {
+        true => db.set_budget_percent(cat.id, amount).await?,
+        false => db.set_budget(cat.id, amount).await?
+    };
+    send_with_retry(&bot, chat_id, "Budget saved").await?;
+    Ok(())
+}
+
+async fn cmd_budget(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let budgets = db.get_budgets_this_month(chat_id.into()).await?;
+    let to_sent = match budgets.is_empty() {
+        true => "No budgets set".to_string(),
+        false => format!(
+            "Budgets \n{}",
+            budgets.iter().map(|b| {
+                let share = b.percent.map(|p| format!(" ({p}% of income)")).unwrap_or_default();
+                format!(
+                    "{} {} {:.2}/{:.2}{share}",
+                    b.category.name, progress_bar(b.spent, b.limit, 8), b.spent, b.limit
+                )
+            }).collect::<Vec<_>>().join("\n")
+        )
+    };
+    send_long(&bot, chat_id, to_sent).await?;
+    Ok(())
+}
+
+async fn cmd_budget_runway(bot: Bot, db: DB, chat_id: ChatId, alias: String) -> Result<(), BotError> {
+    let Some(cat) = db.get_category_by_alias(chat_id.into(), alias.trim().to_string()).await? else {
+        send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+        return Ok(());
+    };
+    let budgets = db.get_budgets_this_month(chat_id.into()).await?;
+    let Some(budget) = budgets.iter().find(|b| b.category.alias == cat.category.alias) else {
+        send_with_retry(&bot, chat_id, format!("No budget for {}", cat.category.name)).await?;
+        return Ok(());
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    let now = Utc::now();
+    let (date_from, date_to) = this_cycle(&settings);
+    let elapsed_days = (now - date_from).num_seconds() as f64 / 86400.0;
+    // rounded, a DST switch makes the cycle an hour shorter or longer
+    let cycle_days = ((date_to - date_from).num_hours() as f64 / 24.0).round() as u32;
+    let name = &budget.category.name;
+    let text = match budget_runway(budget.limit, budget.spent, elapsed_days, cycle_days) {
+        Runway::Exhausted => format!("{name} budget is already used up: {:.2}/{:.2}", budget.spent, budget.limit),
+        Runway::Lasts => format!("{name} budget should last the month at this pace"),
+        Runway::Day(day) => {
+            let date = settings.local_today(date_from) + chrono::Duration::days(day as i64 - 1);
+            format!("{name} budget likely exhausted around the {}", ordinal(date.day()))
+        }
+    };
+    send_with_retry(&bot, chat_id, text).await?;
+    Ok(())
+}
+
+async fn cmd_savings(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let settings = db.get_settings(chat_id.into()).await?;
+    let income = settings.monthly_income();
+    let (date_from, date_to) = this_cycle(&settings);
+    let spent = db.total_amount(chat_id.into(), Some(date_from), Some(date_to)).await?;
+    let report = match savings_rate(income, spent) {
+        Some(rate) => format!(
+            "Income: {:.2}\nSpent: {:.2}\nSaved: {:.2} ({:.1}%)",
+            income, spent, income - spent, rate
+        ),
+        None => "Set monthly income first: /setincome XX.XX".to_string()
+    };
+    send_with_retry(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_set_goal(bot: Bot, db: DB, chat_id: ChatId, amount: f64, date: String) -> Result<(), BotError> {
+    let now = Utc::now();
+    let deadline = match parse_date(&date) {
+        Some(deadline) if deadline > now => deadline,
+        Some(_) => {
+            send_with_retry(&bot, chat_id, "Deadline must be in the future").await?;
+            return Ok(());
+        },
+        None => {
+            send_with_retry(&bot, chat_id, "Provide deadline in YYYY-MM-DD format").await?;
+            return Ok(());
+        }
+    };
+    if amount <= 0.0 {
+        send_with_retry(&bot, chat_id, "Goal must be positive").await?;
+        return Ok(());
+    }
+    db.set_goal(chat_id.into(), amount, now, deadline).await?;
+    send_with_retry(&bot, chat_id, "Goal saved").await?;
+    Ok(())
+}
+
+async fn cmd_goal(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let Some(goal) = db.get_goal(chat_id.into()).await? else {
+        send_with_retry(&bot, chat_id, "Set a goal first: /setgoal XX.XX YYYY-MM-DD").await?;
+        return Ok(());
+    };
+    let settings = db.get_settings(chat_id.into()).await?;
+    if settings.monthly_income() <= 0.0 {
+        send_with_retry(&bot, chat_id, "Set monthly income first: /setincome XX.XX").await?;
+        return Ok(());
+    }
+    let now = Utc::now();
+    let elapsed_days = (now - goal.started).num_days();
+    let total_days = (goal.deadline - goal.started).num_days();
+    let income = settings.monthly_income() * 12.0 / 365.0 * elapsed_days as f64;
+    let spent = db.total_amount(chat_id.into(), Some(goal.started), Some(now)).await?;
+    let saved = income - spent;
+    let report = match goal_progress(goal.amount, saved, elapsed_days, total_days) {
+        Some((percent, on_pace)) => format!(
+            "Goal: {:.2} by {}\nSaved: {:.2} ({:.1}%)\n{}",
+            goal.amount,
+            format_date(goal.deadline, settings.timezone, settings.lang),
+            saved,
+            percent,
+            if on_pace { "On pace" } else { "Behind pace" }
+        ),
+        None => "Goal period is empty, set it again".to_string()
+    };
+    send_with_retry(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn alias_availability(db: &DB, chat_id: ChatId, alias: String) -> Result<String, BotError> {
+    if !db.category_exists(chat_id.into(), alias.clone()).await? {
+        return Ok("available".to_string());
+    }
+    let name = db.find_category_by_alias(chat_id.into(), alias).await?
+        .map(|row| row.category.name)
+        .unwrap_or_default();
+    Ok(format!("taken by {name}"))
+}
+
+async fn cmd_add_alias(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    existing: String,
+    new: String
+) -> Result<(), BotError> {
+    let cat = match db.get_category_by_alias(chat_id.into(), existing).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
             return Ok(());
         }
     };
-    let dt = match NaiveDateTime::parse_from_str(
-        &(date_to + " 00:00:00"),
-        "%Y-%m-%d %H:%M:%S"
-    ) { 
-        Ok(dt) => DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc),
-        Err(_) => {
-            bot.send_message(chat_id, "Provide date to in YYYY-MM-DD format").await?;
+    if let Some(row) = db.find_category_by_alias(chat_id.into(), new.clone()).await? {
+        send_with_retry(&bot, chat_id, format!("This alias is reserved for {}", row.category.name)).await?;
+        return Ok(());
+    }
+    db.add_alias(chat_id.into(), cat.id, new.clone()).await?;
+    send_with_retry(&bot, chat_id, format!("{new} now points to {}", cat.category.name)).await?;
+    Ok(())
+}
+
+async fn cmd_export(bot: Bot, db: DB, chat_id: ChatId, format: String) -> Result<(), BotError> {
+    let Some(format) = CsvFormat::parse(&format) else {
+        send_with_retry(&bot, chat_id, "Use a one-character delimiter (or \"tab\") and \".\" or \",\" for decimals").await?;
+        return Ok(());
+    };
+    let costs = db.get_costs(chat_id.into(), None, None).await?;
+    if costs.is_empty() {
+        send_with_retry(&bot, chat_id, "Nothing to export").await?;
+        return Ok(());
+    }
+    let settings = db.get_settings(chat_id.into()).await?;
+    let csv = export_csv(&costs, &format, settings.timezone);
+    retry_request(bot.send_document(chat_id, InputFile::memory(csv.into_bytes()).file_name("costs.csv"))).await?;
+    Ok(())
+}
+
+async fn cmd_diff(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    dates: [String; 4]
+) -> Result<(), BotError> {
+    let periods = parse_period(&dates[0], &dates[1]).and_then(|first| {
+        parse_period(&dates[2], &dates[3]).map(|second| (first, second))
+    });
+    let ((df1, dt1), (df2, dt2)) = match periods {
+        Ok(periods) => periods,
+        Err(e) => {
+            send_with_retry(&bot, chat_id, e.to_string()).await?;
             return Ok(());
         }
     };
-    let stat = db.get_stat(chat_id, Some(df), Some(dt)).await?;
-    bot.send_message(chat_id, stat.to_string()).await?;
+    let first = db.get_stat(chat_id.into(), Some(df1), Some(dt1)).await?;
+    let second = db.get_stat(chat_id.into(), Some(df2), Some(dt2)).await?;
+    send_long(&bot, chat_id, first.diff(&second).to_string()).await?;
     Ok(())
 }
 
+async fn cmd_cat_diff(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    alias: String,
+    dates: [String; 4]
+) -> Result<(), BotError> {
+    let periods = parse_period(&dates[0], &dates[1]).and_then(|first| {
+        parse_period(&dates[2], &dates[3]).map(|second| (first, second))
+    });
+    let ((df1, dt1), (df2, dt2)) = match periods {
+        Ok(periods) => periods,
+        Err(e) => {
+            send_with_retry(&bot, chat_id, e.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let cat = match db.find_category_by_alias(chat_id.into(), alias.clone()).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+            return Ok(());
+        }
+    };
+    let filter = StatFilter::default().category(cat.id);
+    let first = db.get_stat_by(chat_id.into(), Some(df1), Some(dt1), &filter).await?.amount();
+    let second = db.get_stat_by(chat_id.into(), Some(df2), Some(dt2), &filter).await?.amount();
+    send_with_retry(&bot, chat_id, format!("{alias}: {}", describe_delta(first, second))).await?;
+    Ok(())
+}
+
+/// Only members of the target chat (or its owner, for private chats) may copy into it
+async fn can_write_to_chat(bot: &Bot, msg: &Message, target: ChatId) -> Result<bool, BotError> {
+    let user = match msg.from.as_ref() {
+        Some(user) => user,
+        None => return Ok(false)
+    };
+    if target.0 == user.id.0 as i64 {
+        return Ok(true);
+    }
+    match bot.get_chat_member(target, user.id).await {
+        Ok(member) => Ok(member.is_present()),
+        Err(teloxide::RequestError::Api(_)) => Ok(false),
+        Err(e) => Err(e.into())
+    }
+}
+
+async fn cmd_copy_to(bot: Bot, db: DB, msg: &Message, target: ChatId) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    if !can_write_to_chat(&bot, msg, target).await? {
+        send_with_retry(&bot, chat_id, "You are not a member of that chat").await?;
+        return Ok(());
+    }
+    let n = db.copy_categories(chat_id.into(), target.into()).await?;
+    send_with_retry(&bot, chat_id, format!("Copied {n} categories")).await?;
+    Ok(())
+}
+
+async fn cmd_trend(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let settings = db.get_settings(chat_id.into()).await?;
+    let now = Utc::now();
+    let (date_from, date_to) = this_cycle(&settings);
+    let (previous_from, previous_to) = local_cycle_bounds(
+        date_from - chrono::Duration::seconds(1), settings.fiscal_start_day, settings.timezone
+    );
+    let spent = db.total_amount(chat_id.into(), Some(date_from), Some(date_to)).await?;
+    let previous = db.total_amount(chat_id.into(), Some(previous_from), Some(previous_to)).await?;
+    let projected = spent / elapsed_fraction(now, date_from, date_to);
+    let report = match percent_change(previous, projected) {
+        Some(change) => format!(
+            "This month: {:.2} (on pace for {:.2})\nLast month: {:.2}\nPace is {:.0}% {}",
+            spent, projected, previous, change.abs(), if change >= 0.0 { "up" } else { "down" }
+        ),
+        None => format!("This month: {:.2} (on pace for {:.2})\nNo spendings last month to compare", spent, projected)
+    };
+    send_with_retry(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_forecast(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let settings = db.get_settings(chat_id.into()).await?;
+    let now = Utc::now();
+    let (date_from, date_to) = this_cycle(&settings);
+    let elapsed = elapsed_fraction(now, date_from, date_to);
+    let spent = db.total_amount(chat_id.into(), Some(date_from), Some(date_to)).await?;
+    let report = format!(
+        "Spent so far: {:.2}\nProjected for the month: {:.2}\n{} days in — {}",
+        spent,
+        spent / elapsed,
+        (now - date_from).num_days() + 1,
+        Confidence::from_elapsed(elapsed).describe()
+    );
+    send_with_retry(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+fn shortcuts_help() -> String {
+    let lines = SHORTCUTS.iter()
+        .map(|(command, alias)| format!("/{alias} = /{command}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("Shortcuts \n{lines}")
+}
+
+async fn cmd_retire(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    from: String,
+    into: String
+) -> Result<(), BotError> {
+    let (from, into) = match (
+        db.find_category_by_alias(chat_id.into(), from).await?,
+        db.get_category_by_alias(chat_id.into(), into).await?
+    ) {
+        (Some(from), Some(into)) if from.id != into.id => (from, into),
+        _ => {
+            send_with_retry(&bot, chat_id, "Provide two different existing category aliases").await?;
+            return Ok(());
+        }
+    };
+    let moved = db.retire_category(from.id, into.id).await?;
+    let report = format!("Moved {moved} costs to {}, {} deleted", into.category.name, from.category.name);
+    send_with_retry(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+async fn cmd_add_recurring(
+    bot: Bot,
+    db: DB,
+    chat_id: ChatId,
+    alias: String,
+    amount: f64,
+    (frequency, day): (String, u32)
+) -> Result<(), BotError> {
+    let cat = match db.get_category_by_alias(chat_id.into(), alias).await? {
+        Some(cat) => cat,
+        None => {
+            send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+            return Ok(());
+        }
+    };
+    let frequency = match Frequency::parse(&frequency, day) {
+        Some(frequency) => frequency,
+        None => {
+            send_with_retry(&bot, chat_id, "Use \"monthly 1-31\" or \"weekly 1-7\" (1 is Monday)").await?;
+            return Ok(());
+        }
+    };
+    let id = db.add_recurring(chat_id.into(), cat.id, amount, frequency).await?;
+    send_with_retry(&bot, chat_id, format!("Recurring cost #{id} saved")).await?;
+    Ok(())
+}
+
+async fn cmd_recurring(bot: Bot, db: DB, chat_id: ChatId) -> Result<(), BotError> {
+    let rows = db.list_recurring(chat_id.into()).await?;
+    if rows.is_empty() {
+        send_with_retry(&bot, chat_id, "No recurring costs").await?;
+        return Ok(());
+    }
+    let text = rows.iter()
+        .map(|r| {
+            let skip = if r.skip_next { " (next skipped)" } else { "" };
+            format!(
+                "#{} {} {:.2} {} {}{}",
+                r.id, r.category.alias, r.amount, r.frequency.kind(), r.frequency.day(), skip
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    send_long(&bot, chat_id, text).await?;
+    Ok(())
+}
+
+/// Operator chat set with `ADMIN_CHAT_ID`, nobody is admin without it
+fn is_admin(chat_id: ChatId) -> bool {
+    std::env::var("ADMIN_CHAT_ID")
+        .ok()
+        .and_then(|id| id.trim().parse::<i64>().ok())
+        .is_some_and(|id| id == chat_id.0)
+}
+
+/// Today's cost of the category default amount, `None` for an unknown alias or no default
+async fn default_cost(db: &DB, chat_id: ChatId, alias: String) -> Result<Option<NewCost>, BotError> {
+    let Some(cat) = db.get_category_by_alias(chat_id.into(), alias).await? else {
+        return Ok(None);
+    };
+    Ok(db.get_default_amount(cat.id).await?.map(|amount| NewCost::new(cat.id, amount)))
+}
+
+/// Strictly increasing positive histogram edges
+fn parse_edges(text: &str) -> Option<Vec<f64>> {
+    let edges = text.split_whitespace().map(|e| e.parse::<f64>().ok()).collect::<Option<Vec<_>>>()?;
+    let valid = edges.first().is_some_and(|first| *first > 0.0)
+        && edges.windows(2).all(|pair| pair[0] < pair[1]);
+    valid.then_some(edges)
+}
+
+/// `alias:name` pairs separated by commas, `None` if any pair is malformed
+fn parse_category_list(text: &str) -> Option<Vec<(String, String)>> {
+    let pairs = text.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (alias, name) = pair.split_once(':')?;
+            let (alias, name) = (alias.trim(), name.trim());
+            let valid = !alias.is_empty() && !alias.contains(char::is_whitespace) && !name.is_empty();
+            valid.then(|| (alias.to_string(), name.to_string()))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!pairs.is_empty()).then_some(pairs)
+}
+
+/// Reserved category for costs logged without thinking about the category
+async fn misc_category(db: &DB, chat_id: ChatId) -> Result<i64, BotError> {
+    Ok(db.get_or_create_category(chat_id.into(), MISC_ALIAS.to_string(), MISC_NAME.to_string()).await?)
+}
+
+/// Moves every misc cost into the category, `None` if the alias is unknown
+async fn recategorize_misc(db: &DB, chat_id: ChatId, alias: String) -> Result<Option<u64>, BotError> {
+    let Some(target) = db.get_category_by_alias(chat_id.into(), alias).await? else {
+        return Ok(None);
+    };
+    match db.find_category_by_alias(chat_id.into(), MISC_ALIAS.to_string()).await? {
+        Some(misc) if misc.id != target.id => Ok(Some(db.merge_categories(misc.id, target.id).await?)),
+        _ => Ok(Some(0))
+    }
+}
+
+/// First word of the command message, without arguments that may hold amounts
+fn command_name(msg: &Message) -> &str {
+    msg.text().and_then(|t| t.split_whitespace().next()).unwrap_or_default()
+}
+
+/// Tells the user when storage stopped accepting writes, the error still reaches the error handler
+async fn report_storage_error(bot: &Bot, chat_id: ChatId, result: Result<(), BotError>) -> Result<(), BotError> {
+    if let Err(BotError::DB(e)) = &result {
+        if e.is_storage_unavailable() {
+            let text = "Can't save anything right now, storage is unavailable. Please try again later";
+            if let Err(error) = send_with_retry(bot, chat_id, text).await {
+                tracing::warn!(%error, "storage error notification failed");
+            }
+        }
+    }
+    result
+}
+
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0, command = command_name(&msg)), err)]
 async fn command_handler(
     bot: Bot,
     dialogue: MyDialogue,
     msg: Message,
     cmd: Command,
-    db: DB
+    db: DB,
+    metrics: Arc<Metrics>,
+    rates: Arc<dyn RateProvider>
 ) -> Result<(), BotError> {
     let chat_id = msg.chat.id;
+    let result = run_command(bot.clone(), dialogue, msg, cmd, db, metrics, rates).await;
+    report_storage_error(&bot, chat_id, result).await
+}
+
+async fn run_command(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    cmd: Command,
+    db: DB,
+    metrics: Arc<Metrics>,
+    rates: Arc<dyn RateProvider>
+) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    metrics.command(command_name(&msg));
+    if db.get_settings(chat_id.into()).await?.audit {
+        db.log_action(chat_id.into(), command_name(&msg), Utc::now()).await?;
+    }
     match cmd {
         Command::Start => {
-            bot.send_message(msg.chat.id, "/help").await?;
+            send_with_retry(&bot, msg.chat.id, "/help").await?;
         }
         Command::ListCategory => cmd_list_categories(bot, db, chat_id).await?,
+        Command::SetOrder { alias, pos } => {
+            match db.set_category_position(chat_id.into(), alias, pos).await? {
+                true => cmd_list_categories(bot, db, chat_id).await?,
+                false => {
+                    send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+                }
+            };
+        },
         Command::AddCategory => {
-            bot.send_message(chat_id, "Specify category alias").await?;
+            send_with_retry(&bot, chat_id, "Specify category alias").await?;
             dialogue.update(State::NewCategoryReceiveAlias).await?;
         },
-        Command::UpdateCategory => {
-            let cats = db.get_categories(chat_id).await?;
-            bot.send_message(chat_id, "Specify alias for category to update").await?;
-            send_message_with_cats(chat_id, &bot, &cats).await?;
-            dialogue.update(State::UpdCategoryReceiveAlias).await?;
+        Command::UpdateCategory => {
+            let cats = db.get_categories(chat_id.into()).await?;
+            retry_request(
+                bot.send_message(chat_id, "Pick a category to update").reply_markup(rename_keyboard(&cats))
+            ).await?;
+            dialogue.update(State::UpdCategoryReceiveAlias).await?;
+        },
+        Command::AddCost { alias, date, amount } => cmd_add_cost(bot, &dialogue, db, &metrics, &msg, alias, date, amount).await?,
+        Command::Log { rest } => cmd_log(bot, &dialogue, db, &metrics, &msg, rest).await?,
+        Command::AddCostIn { alias, amount, code } => cmd_add_cost_in(bot, &dialogue, db, &metrics, &msg, alias, amount, code).await?,
+        Command::CatInfo { alias } => {
+            let text = match db.find_category_by_alias(chat_id.into(), alias.trim().to_string()).await? {
+                Some(cat) => {
+                    let mut lines = vec![cat.to_string()];
+                    if let Some(description) = db.get_description(cat.id).await? {
+                        lines.push(description);
+                    }
+                    if let Some(amount) = db.get_default_amount(cat.id).await? {
+                        lines.push(format!("Default amount: {amount:.2}"));
+                    }
+                    lines.join("\n")
+                },
+                None => "Provide existing category alias".to_string()
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::SetCatInfo { alias, text } => {
+            let description = (!text.is_empty()).then_some(text);
+            let report = match db.set_description(chat_id.into(), alias.clone(), description.clone()).await? {
+                true if description.is_some() => format!("Note saved for {alias}"),
+                true => format!("Note cleared for {alias}"),
+                false => "Provide existing category alias".to_string()
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::SetParent { child, parent } => {
+            let parent = (parent != "none").then_some(parent);
+            let report = match db.set_parent(chat_id.into(), child.clone(), parent.clone()).await? {
+                ParentUpdate::Done => match parent {
+                    Some(parent) => format!("{child} is now under {parent}"),
+                    None => format!("{child} is a top-level category")
+                },
+                ParentUpdate::NotFound => "Provide existing category aliases".to_string(),
+                ParentUpdate::Cycle => "A category can't be placed under itself or its subcategories".to_string()
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::SetDefaultAmount { alias, amount } => {
+            let amount = (amount > 0.0).then_some(amount);
+            match db.set_default_amount(chat_id.into(), alias.clone(), amount).await? {
+                true => match amount {
+                    Some(amount) => send_with_retry(&bot, chat_id, format!("/logdefault {alias} logs {amount:.2}")).await?,
+                    None => send_with_retry(&bot, chat_id, format!("No default amount for {alias}")).await?
+                },
+                false => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::LogDefault { alias } => {
+            let Some(cost) = default_cost(&db, chat_id, alias).await? else {
+                send_with_retry(&bot, chat_id, "Provide an alias with a default amount: /setdefaultamount alias XX.XX").await?;
+                return Ok(());
+            };
+            let cost = NewCost { user_id: sender_id(&msg), ..cost };
+            let settings = db.get_settings(chat_id.into()).await?;
+            if settings.exceeds_cap(cost.amount) {
+                ask_cost_confirmation(&bot, &dialogue, chat_id, &settings, cost).await?;
+            } else {
+                let reply = format!("Created {:.2}!", cost.amount);
+                save_cost(&db, &bot, &metrics, chat_id, &settings, &cost, &reply).await?;
+            }
+        },
+        Command::Misc { amount } => {
+            let cat_id = misc_category(&db, chat_id).await?;
+            let settings = db.get_settings(chat_id.into()).await?;
+            let cost = NewCost { user_id: sender_id(&msg), ..NewCost::new(cat_id, amount) };
+            if settings.exceeds_cap(amount) {
+                ask_cost_confirmation(&bot, &dialogue, chat_id, &settings, cost).await?;
+            } else {
+                save_cost(&db, &bot, &metrics, chat_id, &settings, &cost, "Created in misc!").await?;
+            }
+        },
+        Command::Recategorize { alias } => {
+            match recategorize_misc(&db, chat_id, alias).await? {
+                Some(moved) => send_with_retry(&bot, chat_id, format!("Moved {moved} costs from misc")).await?,
+                None => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::SplitCost { total } => {
+            if total <= 0.0 {
+                send_with_retry(&bot, chat_id, "Provide the purchase total").await?;
+            } else {
+                let text = format!("Send portions as \"alias amount\", one per message, {total:.2} left. \"cancel\" stops");
+                send_with_retry(&bot, chat_id, text).await?;
+                dialogue.update(State::SplitCostReceivePortion { total, portions: Vec::new() }).await?;
+            }
+        },
+        Command::Split { amount, people } => {
+            let shares = split_evenly(amount, people);
+            let text = match shares.first() {
+                Some(first) if shares.iter().all(|s| s == first) => format!(
+                    "{people} × {first:.2}\nLog your share with /misc {first:.2}"
+                ),
+                Some(first) => format!(
+                    "{} × {:.2}, one pays {first:.2}\nLog your share with /misc {:.2}",
+                    people - 1, shares[1], shares[1]
+                ),
+                None => "Split between at least one person".to_string()
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::RemoveLastCost => {
+            match db.remove_last_cost(chat_id.into()).await? {
+                Some(_) => send_with_retry(&bot, chat_id, "Removed").await?,
+                None => send_with_retry(&bot, chat_id, "Nothing to remove").await?
+            };
+        },
+        Command::Recent => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let costs = db.get_costs(chat_id.into(), None, None).await?;
+            let text = match costs.is_empty() {
+                true => "No costs yet".to_string(),
+                false => costs[costs.len().saturating_sub(RECENT_COSTS)..]
+                    .iter()
+                    .map(|c| c.display(&settings))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::Dupes => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let dupes = db.find_duplicate_costs(chat_id.into(), DUPLICATE_WINDOW_SECS).await?;
+            let text = match dupes.is_empty() {
+                true => "No duplicates found".to_string(),
+                false => dupes.iter()
+                    .map(|(a, b)| format!("#{} {}\n#{} {}", a.id, a.display(&settings), b.id, b.display(&settings)))
+                    .collect::<Vec<_>>()
+                    .join("\n\n") + "\n\nRemove one with /removecost id"
+            };
+            send_long(&bot, chat_id, text).await?;
+        },
+        Command::RemoveCost { id } => {
+            let report = match db.remove_cost(chat_id.into(), id).await? {
+                true => format!("Cost #{id} removed"),
+                false => format!("No cost #{id}")
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::Undo { n } => {
+            if n < 1 {
+                send_with_retry(&bot, chat_id, "Provide how many costs to remove").await?;
+            } else {
+                let removed = db.remove_last_n_costs(chat_id.into(), n).await?;
+                send_with_retry(&bot, chat_id, format!("Removed {removed} costs")).await?;
+            }
+        },
+        Command::StatThisMonth => cmd_stat_this_month(bot, db, chat_id).await?,
+        Command::StatGrouped => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let stat = db.get_stat_grouped(chat_id.into(), Some(date_from), Some(date_to)).await?
+                .round_display(settings.round_display);
+            let report = format!("{}\n{}", period_header(date_from, date_to, &settings), stat);
+            send_long(&bot, chat_id, report).await?;
+        },
+        Command::Shares => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let shares = db.get_stat_this_cycle(chat_id.into(), settings.fiscal_start_day, settings.timezone).await?.shares();
+            let text = match shares.is_empty() {
+                true => "No spendings this month".to_string(),
+                false => shares.iter()
+                    .map(|(name, share)| format!("{name} {share:.0}%"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            send_long(&bot, chat_id, text).await?;
+        },
+        Command::Tree => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let tree = render_tree(&db.category_tree(chat_id.into(), date_from, date_to).await?);
+            let text = match tree.is_empty() {
+                true => "No spendings this month".to_string(),
+                false => format!("<pre>{}</pre>", html::escape(&tree))
+            };
+            retry_request(bot.send_message(chat_id, text).parse_mode(ParseMode::Html)).await?;
+        },
+        Command::MyStat => {
+            let Some(user_id) = sender_id(&msg) else {
+                send_with_retry(&bot, chat_id, "Can't tell who is asking").await?;
+                return Ok(());
+            };
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let filter = StatFilter::default().user(user_id);
+            let stat = db.get_stat_by(chat_id.into(), Some(date_from), Some(date_to), &filter).await?
+                .round_display(settings.round_display);
+            let total = db.total_amount(chat_id.into(), Some(date_from), Some(date_to)).await?;
+            let report = format!(
+                "{}\n{}\nChat total: {}",
+                period_header(date_from, date_to, &settings), stat, db::format_amount(total, settings.round_display)
+            );
+            send_long(&bot, chat_id, report).await?;
+        },
+        Command::Receipt => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = local_month_bounds(Utc::now(), settings.timezone);
+            let stat = db.get_stat(chat_id.into(), Some(date_from), Some(date_to)).await?
+                .round_display(settings.round_display);
+            let receipt = stat.receipt(&period_header(date_from, date_to, &settings));
+            retry_request(
+                bot.send_message(chat_id, format!("<pre>{}</pre>", html::escape(&receipt))).parse_mode(ParseMode::Html)
+            ).await?;
+        },
+        Command::Since { date } => cmd_since(bot, db, chat_id, date).await?,
+        Command::Period => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            send_with_retry(&bot, chat_id, period_report(Utc::now(), &settings)).await?;
+        },
+        Command::FirstCost => {
+            match db.first_cost(chat_id.into()).await? {
+                Some(cost) => {
+                    let settings = db.get_settings(chat_id.into()).await?;
+                    let days = (Utc::now() - cost.dt).num_days();
+                    let text = format!(
+                        "Tracking since {}, {days} days ago\nFirst cost: {}",
+                        format_date(cost.dt, settings.timezone, settings.lang), cost.display(&settings)
+                    );
+                    send_with_retry(&bot, chat_id, text).await?
+                },
+                None => send_with_retry(&bot, chat_id, "No costs yet").await?
+            };
+        },
+        Command::StatBase { code } => cmd_stat_base(bot, db, rates.as_ref(), chat_id, code).await?,
+        Command::StatPeriod { date_from, date_to } => cmd_stat_period(bot, db, chat_id, date_from, date_to).await?,
+        Command::CatMonth { alias } => cmd_cat_period(bot, db, chat_id, alias, false).await?,
+        Command::CatWeek { alias } => cmd_cat_period(bot, db, chat_id, alias, true).await?,
+        Command::DailyAvg { alias } => {
+            match db.find_category_by_alias(chat_id.into(), alias.clone()).await? {
+                Some(cat) => {
+                    let avg = db.category_daily_average(chat_id.into(), cat.id, DAILY_AVG_MONTHS).await?;
+                    match avg > 0.0 {
+                        true => send_with_retry(&bot, chat_id, format!("{alias}: {avg:.2} a day")).await?,
+                        false => send_with_retry(&bot, chat_id, format!("No spendings in {alias} lately")).await?
+                    }
+                },
+                None => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::Sparkline { alias } => {
+            match db.find_category_by_alias(chat_id.into(), alias.clone()).await? {
+                Some(cat) => {
+                    let trend = db.category_trend(chat_id.into(), cat.id, SPARKLINE_MONTHS).await?;
+                    let last = trend.last().copied().unwrap_or(0.0);
+                    send_with_retry(&bot, chat_id, format!("{alias}: {} {last:.2}", sparkline(&trend))).await?
+                },
+                None => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::MovingAvg { window } => {
+            let text = match window {
+                0 => "Provide how many months to average".to_string(),
+                _ => {
+                    let average = db.moving_average_monthly(chat_id.into(), window, Utc::now()).await?;
+                    format!("Average over the last {window} full months: {average:.2}")
+                }
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::PeakDay => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let text = match db.peak_day(chat_id.into(), date_from, date_to, settings.timezone).await? {
+                Some((day, amount)) => format!("Peak day: {} with {amount:.2}", day.format("%Y-%m-%d")),
+                None => "No spendings this month".to_string()
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::TodayVsAvg => {
+            let today = db.get_settings(chat_id.into()).await?.local_midnight(Utc::now());
+            let history_from = today - chrono::Duration::days(TYPICAL_DAY_WINDOW);
+            let history = db.total_amount(chat_id.into(), Some(history_from), Some(today)).await?;
+            let spent = db.total_amount(chat_id.into(), Some(today), None).await?;
+            let average = history / TYPICAL_DAY_WINDOW as f64;
+            let text = match compare_to_average(spent, average) {
+                Some(comparison) => format!("Today: {spent:.2}, average: {average:.2}\n{comparison}"),
+                None => format!("Today: {spent:.2}\nNo spendings in the last {TYPICAL_DAY_WINDOW} days to compare")
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::Distribution { edges } => {
+            let edges = match edges.trim() {
+                "" => Some(HISTOGRAM_EDGES.to_vec()),
+                edges => parse_edges(edges)
+            };
+            let Some(edges) = edges else {
+                send_with_retry(&bot, chat_id, "Give increasing positive amounts, e.g. 10 50 100").await?;
+                return Ok(());
+            };
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let counts = db.amount_histogram(chat_id.into(), date_from, date_to, &edges).await?;
+            send_with_retry(&bot, chat_id, render_histogram(&edges, &counts)).await?;
+        },
+        Command::Trend => cmd_trend(bot, db, chat_id).await?,
+        Command::Movers => {
+            let diff = db.compare_months(chat_id.into(), Utc::now()).await?;
+            let (up, down) = diff.movers(MOVERS_TOP);
+            let list = |deltas: Vec<&CategoryDelta>| match deltas.is_empty() {
+                true => "none".to_string(),
+                false => deltas.iter().map(|c| c.to_string()).collect::<Vec<_>>().join("\n")
+            };
+            let text = match up.is_empty() && down.is_empty() {
+                true => "No changes since last month".to_string(),
+                false => format!("Biggest increases:\n{}\n\nBiggest decreases:\n{}", list(up), list(down))
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::Forecast => cmd_forecast(bot, db, chat_id).await?,
+        Command::Pivot => {
+            // columns are calendar months in UTC, as `stat_matrix` groups them, not budget cycles
+            let (this_month, date_to) = month_bounds(Utc::now());
+            let date_from = this_month.checked_sub_months(Months::new(PIVOT_MONTHS - 1)).unwrap_or(this_month);
+            let cells = db.stat_matrix(chat_id.into(), date_from, date_to).await?;
+            match cells.is_empty() {
+                true => send_with_retry(&bot, chat_id, "No spendings yet").await?,
+                false => retry_request(
+                    bot.send_message(chat_id, format!("<pre>{}</pre>", html::escape(&render_pivot(&cells)))).parse_mode(ParseMode::Html)
+                ).await?
+            };
+        },
+        Command::Heatmap => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = local_month_bounds(Utc::now(), settings.timezone);
+            let days = db.stat_by_day(chat_id.into(), date_from, date_to, settings.timezone).await?;
+            send_with_retry(&bot, chat_id, render_heatmap(&days)).await?;
+        },
+        Command::Lapsed => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let lapsed = db.lapsed_categories(chat_id.into(), settings.timezone).await?;
+            let text = match lapsed.is_empty() {
+                true => "Every category from last month is in use".to_string(),
+                false => format!(
+                    "No spendings this month yet \n{}",
+                    lapsed.iter().map(|c| c.to_string()).collect::<Vec<_>>().join("\n")
+                )
+            };
+            send_long(&bot, chat_id, text).await?;
+        },
+        Command::MostUsed => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            match db.most_frequent_category(chat_id.into(), date_from, date_to).await? {
+                Some((cat, n)) => send_with_retry(&bot, chat_id, format!("{}: {} costs", cat.category.name, n)).await?,
+                None => send_with_retry(&bot, chat_id, "No spendings this month").await?
+            };
+        },
+        Command::StatExcept { alias } => {
+            match db.find_category_by_alias(chat_id.into(), alias).await? {
+                Some(cat) => {
+                    let settings = db.get_settings(chat_id.into()).await?;
+                    let stat = db.get_stat_this_month_except(chat_id.into(), cat.id).await?.round_display(settings.round_display);
+                    send_long(&bot, chat_id, stat.to_string()).await?
+                },
+                None => {
+                    send_with_retry(&bot, chat_id, "Provide existing category alias").await?;
+                }
+            };
+        },
+        Command::SetBudget { alias, amount } => cmd_set_budget(bot, db, chat_id, alias, amount, false).await?,
+        Command::SetBudgetPercent { alias, percent } => cmd_set_budget(bot, db, chat_id, alias, percent, true).await?,
+        Command::Budget => cmd_budget(bot, db, chat_id).await?,
+        Command::BudgetRunway { alias } => cmd_budget_runway(bot, db, chat_id, alias).await?,
+        Command::Mute => {
+            db.set_setting(chat_id.into(), Setting::Muted, flag(true)).await?;
+            send_with_retry(&bot, chat_id, "Muted, plain messages are ignored until /unmute").await?;
+        },
+        Command::Unmute => {
+            db.set_setting(chat_id.into(), Setting::Muted, flag(false)).await?;
+            send_with_retry(&bot, chat_id, "Logging plain messages again").await?;
+        },
+        Command::Audit => {
+            let enabled = !db.get_settings(chat_id.into()).await?.audit;
+            db.set_setting(chat_id.into(), Setting::Audit, flag(enabled)).await?;
+            let report = match enabled {
+                true => "Commands are recorded, see /history",
+                false => "Commands are no longer recorded"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::History => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let actions = db.get_actions(chat_id.into(), HISTORY_SIZE).await?;
+            let text = match actions.is_empty() {
+                true => "Nothing recorded, turn it on with /audit".to_string(),
+                false => actions.iter()
+                    .map(|(dt, action)| format!("{} {action}", dt.with_timezone(&settings.timezone).format("%Y-%m-%d %H:%M")))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            send_long(&bot, chat_id, text).await?;
+        },
+        Command::RoundDisplay => {
+            let enabled = !db.get_settings(chat_id.into()).await?.round_display;
+            db.set_setting(chat_id.into(), Setting::RoundDisplay, flag(enabled)).await?;
+            let report = match enabled {
+                true => "Summaries show whole amounts",
+                false => "Summaries show cents"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::Roundup => {
+            let enabled = !db.get_settings(chat_id.into()).await?.roundup_savings;
+            db.set_setting(chat_id.into(), Setting::RoundupSavings, flag(enabled)).await?;
+            let report = match enabled {
+                true => "New costs are rounded up, the difference goes to savings",
+                false => "Costs are no longer rounded up"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::SpareChange => {
+            let saved = db.spare_change(chat_id.into()).await?;
+            send_with_retry(&bot, chat_id, format!("Saved by rounding up: {saved:.2}")).await?;
+        },
+        Command::CentsInput => {
+            let enabled = !db.get_settings(chat_id.into()).await?.integer_cents_input;
+            db.set_setting(chat_id.into(), Setting::IntegerCentsInput, flag(enabled)).await?;
+            let report = match enabled {
+                true => "Whole numbers are cents now: 1250 is 12.50, 12 is 0.12. Type 12.0 for 12",
+                false => "Whole numbers are whole units again: 1250 is 1250.00"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::StrictCategories => {
+            let enabled = !db.get_settings(chat_id.into()).await?.strict_categories;
+            db.set_setting(chat_id.into(), Setting::StrictCategories, flag(enabled)).await?;
+            let report = match enabled {
+                true => "Amounts need a category alias in the same message",
+                false => "Amounts without an alias ask for a category"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::Quiet => {
+            db.set_setting(chat_id.into(), Setting::Quiet, flag(true)).await?;
+            send_with_retry(&bot, chat_id, "Adds are saved silently, errors are still reported").await?;
+        },
+        Command::Verbose => {
+            db.set_setting(chat_id.into(), Setting::Quiet, flag(false)).await?;
+            send_with_retry(&bot, chat_id, "Every add is confirmed").await?;
+        },
+        Command::AutoUncategorized => {
+            let enabled = !db.get_settings(chat_id.into()).await?.auto_uncategorized;
+            db.set_setting(chat_id.into(), Setting::AutoUncategorized, flag(enabled)).await?;
+            let report = match enabled {
+                true => "Bare amounts go to Uncategorized",
+                false => "Bare amounts ask for a category"
+            };
+            send_with_retry(&bot, chat_id, report).await?;
+        },
+        Command::SetTimezone { tz } => {
+            match tz.trim().parse::<chrono_tz::Tz>() {
+                Ok(tz) => {
+                    db.set_setting(chat_id.into(), Setting::Timezone, tz.name().to_string()).await?;
+                    send_with_retry(&bot, chat_id, format!("Timezone set to {}", tz.name())).await?;
+                },
+                Err(_) => {
+                    send_with_retry(&bot, chat_id, "Provide timezone like Europe/Berlin").await?;
+                }
+            };
+        },
+        Command::SetDigest { weekday } => {
+            let weekday = weekday.trim();
+            if weekday == "off" {
+                db.set_setting(chat_id.into(), Setting::DigestWeekday, String::new()).await?;
+                send_with_retry(&bot, chat_id, "Weekly digest turned off").await?;
+                return Ok(());
+            }
+            match weekday.parse::<Weekday>() {
+                Ok(weekday) => {
+                    db.set_setting(chat_id.into(), Setting::DigestWeekday, weekday.to_string()).await?;
+                    send_with_retry(&bot, chat_id, format!("Weekly digest is sent on {weekday}")).await?;
+                },
+                Err(_) => {
+                    send_with_retry(&bot, chat_id, "Provide a weekday like mon or off").await?;
+                }
+            };
+        },
+        Command::Remind { time } => {
+            let time = time.trim();
+            if time == "off" {
+                db.set_setting(chat_id.into(), Setting::RemindAt, String::new()).await?;
+                send_with_retry(&bot, chat_id, "Reminder turned off").await?;
+                return Ok(());
+            }
+            match NaiveTime::parse_from_str(time, "%H:%M") {
+                Ok(time) => {
+                    db.set_setting(chat_id.into(), Setting::RemindAt, time.format("%H:%M").to_string()).await?;
+                    let report = format!("Reminding at {} if nothing is logged by then", time.format("%H:%M"));
+                    send_with_retry(&bot, chat_id, report).await?;
+                },
+                Err(_) => {
+                    send_with_retry(&bot, chat_id, "Provide time like 21:00 or off").await?;
+                }
+            };
+        },
+        Command::SetLang { lang } => {
+            match lang.trim().parse::<Lang>() {
+                Ok(lang) => {
+                    db.set_setting(chat_id.into(), Setting::Lang, lang.code().to_string()).await?;
+                    send_with_retry(&bot, chat_id, "Date format saved").await?;
+                },
+                Err(_) => {
+                    send_with_retry(&bot, chat_id, "Provide one of: iso, en, ru").await?;
+                }
+            };
+        },
+        Command::SetCycleStart { day } => {
+            if (1..=31).contains(&day) {
+                db.set_setting(chat_id.into(), Setting::FiscalStartDay, day.to_string()).await?;
+                send_with_retry(&bot, chat_id, format!("Cycle starts on day {day}")).await?;
+            } else {
+                send_with_retry(&bot, chat_id, "Provide a day from 1 to 31").await?;
+            }
         },
-        Command::AddCost { alias, date, amount } => cmd_add_cost(bot, db, chat_id, alias, date, amount).await?,
-        Command::RemoveLastCost => {
-            match db.remove_last_cost(chat_id).await? {
-                Some(_) => bot.send_message(chat_id, "Removed").await?,
-                None => bot.send_message(chat_id, "Nothing to remove").await?
+        Command::SetMaxTransaction { amount } => {
+            if amount < 0.0 {
+                send_with_retry(&bot, chat_id, "Cap can't be negative").await?;
+            } else {
+                let cents = currency::to_cents(amount, None);
+                db.set_setting(chat_id.into(), Setting::MaxTransaction, cents.to_string()).await?;
+                send_with_retry(&bot, chat_id, "Cap saved").await?;
+            }
+        },
+        Command::SetIncome { amount } => {
+            if amount < 0.0 {
+                send_with_retry(&bot, chat_id, "Income can't be negative").await?;
+            } else {
+                let cents = currency::to_cents(amount, None);
+                db.set_setting(chat_id.into(), Setting::MonthlyIncome, cents.to_string()).await?;
+                send_with_retry(&bot, chat_id, "Income saved").await?;
+            }
+        },
+        Command::Savings => cmd_savings(bot, db, chat_id).await?,
+        Command::SetGoal { amount, date } => cmd_set_goal(bot, db, chat_id, amount, date).await?,
+        Command::Goal => cmd_goal(bot, db, chat_id).await?,
+        Command::AddAlias { existing, new } => cmd_add_alias(bot, db, chat_id, existing, new).await?,
+        Command::AliasFree { alias } => {
+            let reply = alias_availability(&db, chat_id, alias).await?;
+            send_with_retry(&bot, chat_id, reply).await?;
+        },
+        Command::Pin { alias } => {
+            match db.set_favorite(chat_id.into(), alias, true).await? {
+                true => send_with_retry(&bot, chat_id, "Pinned").await?,
+                false => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
             };
         },
-        Command::StatThisMonth => cmd_stat_this_month(bot, db, chat_id).await?,
-        Command::StatPeriod { date_from, date_to } => cmd_stat_period(bot, db, chat_id, date_from, date_to).await?,
-        Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string()).await?;
+        Command::Unpin { alias } => {
+            match db.set_favorite(chat_id.into(), alias, false).await? {
+                true => send_with_retry(&bot, chat_id, "Unpinned").await?,
+                false => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::Archive { alias } => {
+            match db.archive_category(chat_id.into(), alias).await? {
+                true => send_with_retry(&bot, chat_id, "Archived").await?,
+                false => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::Unarchive { alias } => {
+            match db.unarchive_category(chat_id.into(), alias).await? {
+                true => send_with_retry(&bot, chat_id, "Restored").await?,
+                false => send_with_retry(&bot, chat_id, "Provide existing category alias").await?
+            };
+        },
+        Command::Retire { from, into } => cmd_retire(bot, db, chat_id, from, into).await?,
+        Command::AddRecurring { alias, amount, frequency, day } => {
+            cmd_add_recurring(bot, db, chat_id, alias, amount, (frequency, day)).await?
+        },
+        Command::Recurring => cmd_recurring(bot, db, chat_id).await?,
+        Command::Committed => {
+            let total = db.recurring_monthly_total(chat_id.into()).await?;
+            let text = match total > 0.0 {
+                true => format!("Recurring costs take {total:.2} a month"),
+                false => "No recurring costs".to_string()
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::DeleteRecurring { id } => {
+            match db.delete_recurring(chat_id.into(), id).await? {
+                true => send_with_retry(&bot, chat_id, format!("Recurring cost #{id} deleted")).await?,
+                false => send_with_retry(&bot, chat_id, "No such recurring cost").await?
+            };
+        },
+        Command::SkipRecurring { id } => {
+            match db.skip_next_recurring(chat_id.into(), id).await? {
+                true => send_with_retry(&bot, chat_id, format!("Next occurrence of #{id} will be skipped")).await?,
+                false => send_with_retry(&bot, chat_id, "No such recurring cost").await?
+            };
+        },
+        Command::ExportCategories => {
+            let json = db.export_categories_json(chat_id.into()).await?;
+            retry_request(bot.send_document(chat_id, InputFile::memory(json.into_bytes()).file_name("categories.json"))).await?;
+        },
+        Command::AddCategories { rest } => {
+            let text = match parse_category_list(&rest) {
+                Some(pairs) => {
+                    let (created, skipped) = db.create_categories(chat_id.into(), &pairs).await?;
+                    match skipped.is_empty() {
+                        true => format!("Created {created} categories"),
+                        false => format!("Created {created} categories\nSkipped, alias taken: {}", skipped.join(", "))
+                    }
+                },
+                None => "Use alias:name pairs separated by commas, like food:Food, rent:Rent".to_string()
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::ImportCategories { json } => {
+            match db.import_categories_json(chat_id.into(), &json).await? {
+                Some(n) => send_with_retry(&bot, chat_id, format!("Imported {n} categories")).await?,
+                None => send_with_retry(&bot, chat_id, "Paste the JSON from /exportcategories").await?
+            };
+        },
+        Command::CopyTo { chat_id: target } => cmd_copy_to(bot, db, &msg, ChatId(target)).await?,
+        Command::Check => {
+            let mut report = db.integrity_check(chat_id.into()).await?;
+            if is_admin(chat_id) {
+                report.orphaned_costs = Some(db.orphaned_costs().await?);
+            }
+            send_with_retry(&bot, chat_id, report.to_string()).await?;
+        },
+        Command::Reconcile { expected } => {
+            let discrepancy = db.reconcile(chat_id.into(), expected).await?;
+            let text = match discrepancy == 0.0 {
+                true => format!("Stored total matches {expected:.2}"),
+                false => format!(
+                    "Stored total is {:.2}, off by {discrepancy:+.2} from {expected:.2}",
+                    expected + discrepancy
+                )
+            };
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::Export { format } => cmd_export(bot, db, chat_id, format).await?,
+        #[cfg(feature = "pdf")]
+        Command::Pdf => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let (date_from, date_to) = this_cycle(&settings);
+            let stat = db.get_stat_this_cycle(chat_id.into(), settings.fiscal_start_day, settings.timezone).await?;
+            let title = format!("Spendings {}", period_header(date_from, date_to, &settings));
+            let file_name = format!("{}.pdf", date_from.with_timezone(&settings.timezone).format("%Y-%m"));
+            let pdf = crate::pdf::stat_pdf(&title, &stat);
+            retry_request(bot.send_document(chat_id, InputFile::memory(pdf).file_name(file_name))).await?;
+        },
+        Command::CatDiff { alias, from1, to1, from2, to2 } => {
+            cmd_cat_diff(bot, db, chat_id, alias, [from1, to1, from2, to2]).await?
+        },
+        Command::Diff { from1, to1, from2, to2 } => cmd_diff(bot, db, chat_id, [from1, to1, from2, to2]).await?,
+        Command::ApiToken => {
+            let token = Alphanumeric.sample_string(&mut rand::thread_rng(), API_TOKEN_LEN);
+            db.set_setting(chat_id.into(), Setting::ApiToken, token.clone()).await?;
+            let text = format!("GET /stats?chat_id={chat_id}&from=YYYY-MM-DD&to=YYYY-MM-DD\nAuthorization: Bearer {token}");
+            send_with_retry(&bot, chat_id, text).await?;
+        },
+        Command::Ping => {
+            db.ping().await?;
+            let version = db.schema_version().await?;
+            send_with_retry(&bot, chat_id, format!("pong, schema {version}")).await?;
+        },
+        Command::Metrics => {
+            match is_admin(chat_id) {
+                true => send_long(&bot, chat_id, metrics.snapshot().to_string()).await?,
+                false => {
+                    send_with_retry(&bot, chat_id, "not authorized").await?;
+                }
+            };
+        },
+        Command::AdminStats => {
+            match is_admin(chat_id) {
+                true => send_with_retry(&bot, chat_id, db.global_counts().await?.to_string()).await?,
+                false => send_with_retry(&bot, chat_id, "not authorized").await?
+            };
+        },
+        Command::ClearCosts => {
+            send_with_retry(&bot, chat_id, "All costs will be deleted, categories are kept. Type \"yes\" to confirm").await?;
+            dialogue.update(State::ConfirmAction { action: PendingAction::ClearCosts }).await?;
+        },
+        Command::ShiftDates { hours } => {
+            if hours == 0 {
+                send_with_retry(&bot, chat_id, "Provide a non-zero number of hours, e.g. 9 or -5").await?;
+            } else {
+                let text = format!("All costs will be moved by {hours:+} hours. Type \"yes\" to confirm");
+                send_with_retry(&bot, chat_id, text).await?;
+                dialogue.update(State::ConfirmAction { action: PendingAction::ShiftDates { hours } }).await?;
+            }
+        },
+        Command::DeleteRange { from, to } => {
+            match parse_period(&from, &to) {
+                Ok((from, to)) => {
+                    let text = format!(
+                        "Costs from {} up to {} will be deleted. Type \"yes\" to confirm",
+                        from.format("%Y-%m-%d"), to.format("%Y-%m-%d")
+                    );
+                    send_with_retry(&bot, chat_id, text).await?;
+                    dialogue.update(State::ConfirmAction { action: PendingAction::DeleteRange { from, to } }).await?;
+                },
+                Err(e) => {
+                    send_with_retry(&bot, chat_id, e.to_string()).await?;
+                }
+            }
+        },
+        Command::Help { mode } => {
+            let text = match mode.trim() {
+                "full" => format!("{}\n\n{}", Command::descriptions(), shortcuts_help()),
+                _ => Command::descriptions().to_string()
+            };
+            send_long(&bot, msg.chat.id, text).await?;
         },
     }
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn new_category_get_alias(
     bot: Bot,
     dialogue: MyDialogue,
@@ -237,26 +2211,27 @@ async fn new_category_get_alias(
     let chat_id = msg.chat.id;
     match msg.text() {
         Some(alias) => {
-            match db.get_category_by_alias(chat_id, alias.to_string()).await? {
+            match db.find_category_by_alias(chat_id.into(), alias.to_string()).await? {
                 None => {
-                    bot.send_message(chat_id, "Give full name").await?;
+                    send_with_retry(&bot, chat_id, "Give full name").await?;
                     dialogue.update(State::NewCategoryReceiveName {
                         alias: alias.to_string()
                     }).await?
                 },
                 Some(row) => {
                     let report = format!("This alias is reserved for {}", row.category.name);
-                    bot.send_message(chat_id, report).await?;
+                    send_with_retry(&bot, chat_id, report).await?;
                 }
             }
         },
         None => {
-            bot.send_message(chat_id, "Give an alias for category").await?;
+            send_with_retry(&bot, chat_id, "Give an alias for category").await?;
         }
     }
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn new_category_get_name(
     bot: Bot,
     dialogue: MyDialogue,
@@ -268,30 +2243,74 @@ async fn new_category_get_name(
     match msg.text() {
         Some(name) => {
             let name = name.to_string();
-            let report = format!("Category saved \n\t Alias={alias} \n\t Name={name}");
-            db.create_category(chat_id, alias, name).await?;
-            bot.send_message(chat_id, report).await?;
+            let similar = db.find_similar_categories(chat_id.into(), name.clone()).await?;
+            let mut report = format!("Category saved \n\t Alias={alias} \n\t Name={name}");
+            if !similar.is_empty() {
+                report = format!(
+                    "{report}\nWarning: similar categories exist: {}",
+                    similar.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+                );
+            }
+            if is_command_keyword(&alias) {
+                report = format!("{report}\nWarning: /{alias} is also a command");
+            }
+            db.create_category(chat_id.into(), alias, name).await?;
+            send_with_retry(&bot, chat_id, report).await?;
             dialogue.exit().await?;
         },
         None => {
-            bot.send_message(chat_id, "Give a name for category").await?;
+            send_with_retry(&bot, chat_id, "Give a name for category").await?;
         }
     }
     Ok(())
 }
 
+/// Whether `/alias` would be taken as one of the bot commands or their shortcuts
+fn is_command_keyword(alias: &str) -> bool {
+    !matches!(
+        Command::parse(&format!("/{}", alias.to_lowercase()), ""),
+        Err(ParseError::UnknownCommand(_))
+    )
+}
+
+/// One-tap alias buttons, pinned categories first
+fn category_keyboard(cats: &[CategoryRow]) -> KeyboardMarkup {
+    let mut cats = cats.iter().collect::<Vec<_>>();
+    cats.sort_by_key(|c| !c.is_favorite);
+    let rows = cats.chunks(KEYBOARD_COLUMNS)
+        .map(|row| row.iter().map(|c| KeyboardButton::new(c.category.alias.clone())).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    KeyboardMarkup::new(rows).resize_keyboard().one_time_keyboard()
+}
+
+/// A button per category, pressing it starts renaming that category
+fn rename_keyboard(cats: &[CategoryRow]) -> InlineKeyboardMarkup {
+    let rows = cats.chunks(KEYBOARD_COLUMNS)
+        .map(|row| row.iter()
+            .map(|c| InlineKeyboardButton::callback(c.category.alias.clone(), format!("{RENAME_CALLBACK}{}", c.id)))
+            .collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Category id out of a `rename_keyboard` button
+fn parse_rename_callback(data: &str) -> Option<i64> {
+    data.strip_prefix(RENAME_CALLBACK)?.parse().ok()
+}
+
 async fn send_message_with_cats(
     chat_id: ChatId,
     bot: &Bot,
     cats: &[CategoryRow]
 ) -> Result<(), BotError> {
-    bot.send_message(chat_id, format!(
+    send_long(bot, chat_id, format!(
         "Categories \n{}",
         cats.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("\n")
     )).await?;
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn upd_category_start(
     bot: Bot,
     dialogue: MyDialogue,
@@ -299,7 +2318,7 @@ async fn upd_category_start(
     db: DB
 ) -> Result<(), BotError> {
     let chat_id = msg.chat.id;
-    let cats = db.get_categories(chat_id).await?;
+    let cats = db.get_categories(chat_id.into()).await?;
     match msg.text() {
         Some(alias) => {
             let alias = alias.to_string();
@@ -307,7 +2326,7 @@ async fn upd_category_start(
             if n == 0 {
                 send_message_with_cats(chat_id, &bot, &cats).await?
             } else {
-                bot.send_message(chat_id, "Provide new alias").await?;
+                send_with_retry(&bot, chat_id, "Provide new alias").await?;
                 dialogue.update(State::UpdCategoryReceiveNewAlias { alias }).await?;
             }
         },
@@ -318,6 +2337,7 @@ async fn upd_category_start(
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn upd_category_alias(
     bot: Bot,
     dialogue: MyDialogue,
@@ -328,16 +2348,17 @@ async fn upd_category_alias(
     match msg.text() {
         Some(new_alias) => {
             let new_alias = new_alias.to_string();
-            bot.send_message(chat_id, "Provide name").await?;
+            send_with_retry(&bot, chat_id, "Provide name").await?;
             dialogue.update(State::UpdCategoryReceiveNewName { alias, new_alias }).await?;
         },
         None => {
-            bot.send_message(chat_id, "Provide alias name").await?;
+            send_with_retry(&bot, chat_id, "Please send the new alias as text").await?;
         }
     };
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn upd_category_name(
     bot: Bot,
     dialogue: MyDialogue,
@@ -349,32 +2370,38 @@ async fn upd_category_name(
     match msg.text() {
         Some(name) => {
             let name = name.to_string();
-            db.update_category(chat_id, alias, new_alias, name).await?;
-            bot.send_message(chat_id, "Category updated").await?;
+            db.update_category(chat_id.into(), alias, new_alias, name).await?;
+            send_with_retry(&bot, chat_id, "Category updated").await?;
             dialogue.exit().await?;
         },
         None => {
-            bot.send_message(chat_id, "Provide a name").await?;
+            send_with_retry(&bot, chat_id, "Please send the name as text").await?;
         }
     };
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn new_cost_get_alias(
     bot: Bot,
     dialogue: MyDialogue,
     amount: f64,
     msg: Message,
-    db: DB
+    db: DB,
+    metrics: Arc<Metrics>
 ) -> Result<(), BotError> {
     let chat_id = msg.chat.id;
-    let cats = db.get_categories(chat_id).await?;
+    let cats = db.get_categories(chat_id.into()).await?;
     if let Some(alias) = msg.text() {
         let alias = alias.to_string();
         match cats.iter().filter(|i| i.category.alias == alias).collect::<Vec<_>>().first() {
             Some(cat) => {
-                db.create_cost(cat.id, amount, None).await?;
-                bot.send_message(chat_id, "Saved").await?;
+                let settings = db.get_settings(chat_id.into()).await?;
+                let cost = NewCost { user_id: sender_id(&msg), ..NewCost::new(cat.id, amount) };
+                if settings.exceeds_cap(amount) {
+                    return ask_cost_confirmation(&bot, &dialogue, chat_id, &settings, cost).await;
+                }
+                save_cost(&db, &bot, &metrics, chat_id, &settings, &cost, "Saved").await?;
                 dialogue.exit().await?;
             },
             None => {
@@ -387,34 +2414,299 @@ async fn new_cost_get_alias(
     Ok(())
 }
 
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
 async fn new_cost_get_amount(
     bot: Bot,
     dialogue: MyDialogue,
     id: i64,
     msg: Message,
-    db: DB
+    db: DB,
+    metrics: Arc<Metrics>
 ) -> Result<(), BotError> {
     let chat_id = msg.chat.id;
-    if let Some(amount_str) = msg.text() {
-        match amount_str.parse::<f64>() {
-            Ok(amount) => {
-                db.create_cost(id, amount, None).await?;
-                bot.send_message(chat_id, "Created!").await?;
-                dialogue.exit().await?;
-            },
-            Err(_) => {
-                bot.send_message(chat_id, "Specify amount").await?;
+    match parse_amount_reply(msg.text()) {
+        Ok(amount) => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            let cost = NewCost { user_id: sender_id(&msg), ..NewCost::new(id, amount) };
+            if settings.exceeds_cap(amount) {
+                return ask_cost_confirmation(&bot, &dialogue, chat_id, &settings, cost).await;
+            }
+            save_cost(&db, &bot, &metrics, chat_id, &settings, &cost, "Created!").await?;
+            dialogue.exit().await?;
+        },
+        Err(prompt) => {
+            send_with_retry(&bot, chat_id, prompt).await?;
+        }
+    };
+    Ok(())
+}
+
+/// Part of the split total not yet given to a category, in cents
+fn split_cents_left(total: f64, portions: &[(i64, f64)]) -> i64 {
+    let cents = |amount: f64| (amount * 100.0).round() as i64;
+    cents(total) - portions.iter().map(|(_, a)| cents(*a)).sum::<i64>()
+}
+
+/// Collects `alias amount` portions until they cover the total, then stores them all at once
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
+async fn split_cost_get_portion(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (total, mut portions): (f64, Vec<(i64, f64)>),
+    msg: Message,
+    db: DB,
+    metrics: Arc<Metrics>
+) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    let text = msg.text().unwrap_or_default();
+    if text.trim().eq_ignore_ascii_case("cancel") {
+        dialogue.exit().await?;
+        send_with_retry(&bot, chat_id, "Cancelled, nothing saved").await?;
+        return Ok(());
+    }
+    let entry = parse_entry(text, false);
+    let cat = find_alias(&db, chat_id, &entry.words).await?;
+    let (Some(amount), Some(cat)) = (entry.amount, cat) else {
+        let left = split_cents_left(total, &portions) as f64 / 100.0;
+        send_with_retry(&bot, chat_id, format!("Send \"alias amount\", {left:.2} left")).await?;
+        return Ok(());
+    };
+    let left = split_cents_left(total, &portions);
+    if (amount * 100.0).round() as i64 > left || amount <= 0.0 {
+        let text = format!("That doesn't fit, {:.2} left", left as f64 / 100.0);
+        send_with_retry(&bot, chat_id, text).await?;
+        return Ok(());
+    }
+    portions.push((cat.id, amount));
+    let left = split_cents_left(total, &portions);
+    if left > 0 {
+        send_with_retry(&bot, chat_id, format!("{:.2} left", left as f64 / 100.0)).await?;
+        dialogue.update(State::SplitCostReceivePortion { total, portions }).await?;
+        return Ok(());
+    }
+    dialogue.exit().await?;
+    let text = match db.create_split_costs(chat_id.into(), total, &portions, sender_id(&msg)).await? {
+        true => {
+            metrics.costs_created(portions.len() as u64);
+            format!("Saved {} costs", portions.len())
+        },
+        false => "Portions don't add up to the total, nothing saved".to_string()
+    };
+    send_with_retry(&bot, chat_id, text).await?;
+    Ok(())
+}
+
+/// Amount typed in reply to "How much?", or the prompt to send again.
+/// Stickers, photos and other non-text messages get a prompt too
+fn parse_amount_reply(text: Option<&str>) -> Result<f64, &'static str> {
+    match text {
+        Some(text) => text.trim().parse::<f64>().map_err(|_| "Specify amount"),
+        None => Err("Please send the amount as text, e.g. 12.50")
+    }
+}
+
+#[instrument(skip_all, fields(chat_id = msg.chat.id.0), err)]
+async fn confirm_action(
+    bot: Bot,
+    dialogue: MyDialogue,
+    action: PendingAction,
+    msg: Message,
+    db: DB,
+    metrics: Arc<Metrics>
+) -> Result<(), BotError> {
+    let chat_id = msg.chat.id;
+    let confirmed = msg.text().is_some_and(|t| t.trim().eq_ignore_ascii_case("yes"));
+    dialogue.exit().await?;
+    if !confirmed {
+        send_with_retry(&bot, chat_id, "Cancelled").await?;
+        return Ok(());
+    }
+    match action {
+        PendingAction::ClearCosts => {
+            let n = db.clear_costs(chat_id.into()).await?;
+            send_with_retry(&bot, chat_id, format!("Deleted {n} costs")).await?;
+        },
+        PendingAction::DeleteRange { from, to } => {
+            let n = db.delete_costs_in_range(chat_id.into(), from, to).await?;
+            send_with_retry(&bot, chat_id, format!("Deleted {n} costs")).await?;
+        },
+        PendingAction::ShiftDates { hours } => {
+            let n = db.shift_dates(chat_id.into(), hours).await?;
+            send_with_retry(&bot, chat_id, format!("Moved {n} costs by {hours:+} hours")).await?;
+        },
+        PendingAction::CreateCost(cost) => {
+            let settings = db.get_settings(chat_id.into()).await?;
+            save_cost(&db, &bot, &metrics, chat_id, &settings, &cost, "Created!").await?;
+        }
+    }
+    Ok(())
+}
+
+/// Posts due recurring costs once an hour and tells the chats about them
+async fn recurring_task(bot: Bot, db: DB) {
+    let mut interval = tokio::time::interval(RECURRING_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let posted = match db.materialize_recurring(Utc::now()).await {
+            Ok(posted) => posted,
+            Err(error) => {
+                tracing::error!(%error, "recurring costs failed");
+                continue;
             }
         };
+        for r in posted {
+            let text = format!("Recurring cost added: {} {:.2}", r.category.name, r.amount);
+            if let Err(error) = send_with_retry(&bot, r.chat_id.into(), text).await {
+                tracing::warn!(%error, chat_id = r.chat_id.0, "recurring notification failed");
+            }
+        }
+    }
+}
+
+/// Sends last week's summary to one chat if its digest is due
+async fn send_digest(bot: &Bot, db: &DB, chat_id: db::ChatId, now: DateTime<Utc>) -> Result<(), BotError> {
+    let settings = db.get_settings(chat_id).await?;
+    if !settings.digest_due(now) {
+        return Ok(());
+    }
+    let (date_from, date_to) = week_bounds(now - chrono::Duration::days(7));
+    let stat = db.get_stat(chat_id, Some(date_from), Some(date_to)).await?
+        .round_display(settings.round_display);
+    let report = format!("Last week\n{}\n{}", period_header(date_from, date_to, &settings), stat);
+    send_long(bot, chat_id.into(), report).await?;
+    let today = settings.local_today(now).format("%Y-%m-%d").to_string();
+    db.set_setting(chat_id, Setting::DigestSent, today).await?;
+    Ok(())
+}
+
+/// Nudges one chat if its reminder time passed and nothing was logged today
+async fn send_reminder(bot: &Bot, db: &DB, chat_id: db::ChatId, now: DateTime<Utc>) -> Result<(), BotError> {
+    let settings = db.get_settings(chat_id).await?;
+    let today = settings.local_today(now);
+    let midnight = settings.local_midnight(now);
+    let logged_today = !db.get_costs(chat_id, Some(midnight), None).await?.is_empty();
+    if !settings.should_remind(now, logged_today) {
+        return Ok(());
+    }
+    send_with_retry(bot, chat_id.into(), "Did you log today's spending?").await?;
+    db.set_setting(chat_id, Setting::RemindSent, today.format("%Y-%m-%d").to_string()).await?;
+    Ok(())
+}
+
+/// Sends digests and reminders that are due, checked every few minutes
+async fn schedule_task(bot: Bot, db: DB) {
+    let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        for setting in [Setting::DigestWeekday, Setting::RemindAt] {
+            let chats = match db.chats_with_setting(setting).await {
+                Ok(chats) => chats,
+                Err(error) => {
+                    tracing::error!(%error, ?setting, "schedule lookup failed");
+                    continue;
+                }
+            };
+            for chat_id in chats {
+                let sent = match setting {
+                    Setting::DigestWeekday => send_digest(&bot, &db, chat_id, Utc::now()).await,
+                    _ => send_reminder(&bot, &db, chat_id, Utc::now()).await
+                };
+                if let Err(error) = sent {
+                    tracing::warn!(%error, ?setting, chat_id = chat_id.0, "scheduled message failed");
+                }
+            }
+        }
+    }
+}
+
+/// Categories whose alias or name starts with `prefix`, ignoring case
+fn filter_by_prefix<'a>(cats: &'a [CategoryRow], prefix: &str) -> Vec<&'a CategoryRow> {
+    let prefix = prefix.to_lowercase();
+    cats.iter()
+        .filter(|c| {
+            c.category.alias.to_lowercase().starts_with(&prefix)
+                || c.category.name.to_lowercase().starts_with(&prefix)
+        })
+        .collect()
+}
+
+/// Picking a category on the `/uc` keyboard, same as typing its alias
+#[instrument(skip_all, fields(chat_id = dialogue.chat_id().0), err)]
+async fn rename_callback(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    db: DB
+) -> Result<(), BotError> {
+    bot.answer_callback_query(q.id).await?;
+    let chat_id = dialogue.chat_id();
+    let Some(id) = q.data.as_deref().and_then(parse_rename_callback) else {
+        return Ok(());
+    };
+    let cats = db.get_categories(chat_id.into()).await?;
+    match cats.into_iter().find(|c| c.id == id) {
+        Some(cat) => {
+            send_with_retry(&bot, chat_id, format!("Provide new alias for {}", cat.category.name)).await?;
+            dialogue.update(State::UpdCategoryReceiveNewAlias { alias: cat.category.alias }).await?;
+        },
+        None => {
+            send_with_retry(&bot, chat_id, "This category is gone, try /uc again").await?;
+        }
     }
     Ok(())
 }
 
+/// Buttons of an earlier `/uc` stay in the chat, pressing them only stops the spinner
+async fn stale_callback(bot: Bot, q: CallbackQuery) -> Result<(), BotError> {
+    bot.answer_callback_query(q.id).await?;
+    Ok(())
+}
+
+/// `@bot foo 12.5` offers the user's categories starting with "foo",
+/// picking one sends "alias 12.5" for the free-text handler to log
+#[instrument(skip_all, fields(user_id = q.from.id.0), err)]
+async fn inline_query_handler(bot: Bot, q: InlineQuery, db: DB) -> Result<(), BotError> {
+    let mut words = q.query.split_whitespace();
+    let prefix = words.next().unwrap_or_default();
+    let amount = words.next().and_then(|w| w.parse::<f64>().ok());
+    // private chats share the user's id
+    let cats = db.get_categories(db::ChatId(q.from.id.0 as i64)).await?;
+    let results = filter_by_prefix(&cats, prefix)
+        .into_iter()
+        .map(|c| {
+            let text = match amount {
+                Some(amount) => format!("{} {}", c.category.alias, amount),
+                None => c.category.alias.clone()
+            };
+            InlineQueryResult::Article(InlineQueryResultArticle::new(
+                c.id.to_string(),
+                c.to_string(),
+                InputMessageContent::Text(InputMessageContentText::new(text))
+            ))
+        })
+        .collect::<Vec<_>>();
+    bot.answer_inline_query(q.id, results).is_personal(true).await?;
+    Ok(())
+}
+
+fn rate_limit_exceeded(msg: Message, limiter: Arc<RateLimiter>) -> bool {
+    !limiter.check(msg.chat.id.0)
+}
+
+async fn slow_down(bot: Bot, msg: Message) -> Result<(), BotError> {
+    send_with_retry(&bot, msg.chat.id, "Slow down").await?;
+    Ok(())
+}
+
 pub async fn run_bot(db: DB) -> Result<(), BotError> {
     let bot = Bot::from_env();
     let storage = InMemStorage::<State>::new();
-    let handler = Update::filter_message()
+    let metrics = Arc::new(Metrics::default());
+    let limiter = Arc::new(RateLimiter::from_env());
+    let rates: Arc<dyn RateProvider> = Arc::new(CachedRateProvider::new(HttpRateProvider::from_env(), RATE_CACHE_TTL));
+    let messages = Update::filter_message()
         .enter_dialogue::<Message, InMemStorage<State>, State>()
+        .branch(dptree::filter(rate_limit_exceeded).endpoint(slow_down))
         .branch(
             dptree::entry()
                 .filter_command::<Command>()
@@ -427,14 +2719,445 @@ pub async fn run_bot(db: DB) -> Result<(), BotError> {
         .branch(dptree::case![State::UpdCategoryReceiveNewName { alias, new_alias }].endpoint(upd_category_name))
         .branch(dptree::case![State::NewCostReceiveAlias { amount } ].endpoint(new_cost_get_alias))
         .branch(dptree::case![State::NewCostReceiveAmount { id }].endpoint(new_cost_get_amount))
+        .branch(dptree::case![State::ConfirmAction { action }].endpoint(confirm_action))
+        .branch(dptree::case![State::SplitCostReceivePortion { total, portions }].endpoint(split_cost_get_portion))
         .branch(Update::filter_message().endpoint(msg_handler));
+    let callbacks = Update::filter_callback_query()
+        .enter_dialogue::<CallbackQuery, InMemStorage<State>, State>()
+        .branch(dptree::case![State::UpdCategoryReceiveAlias].endpoint(rename_callback))
+        .endpoint(stale_callback);
+    let handler = dptree::entry()
+        .branch(messages)
+        .branch(callbacks)
+        .branch(Update::filter_inline_query().endpoint(inline_query_handler));
+
+    let recurring = tokio::spawn(recurring_task(bot.clone(), db.clone()));
+    let schedule = tokio::spawn(schedule_task(bot.clone(), db.clone()));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![storage, db.clone()])
+        .dependencies(dptree::deps![storage, db.clone(), metrics.clone(), limiter, rates])
+        .error_handler(Arc::new(move |error: BotError| {
+            if let BotError::DB(_) = error {
+                metrics.db_error();
+            }
+            tracing::error!(%error, "handler failed");
+            std::future::ready(())
+        }))
         .enable_ctrlc_handler()
         .build()
         .dispatch()
         .await;
 
+    recurring.abort();
+    schedule.abort();
+    db.close().await;
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing_subscriber::fmt::MakeWriter;
+    use crate::item::Category;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_backoff_delay() {
+        let delays = (0..6).map(|i| backoff_delay(i, None)).collect::<Vec<_>>();
+        assert_eq!(delays, vec![
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+            Duration::from_secs(4),
+            Duration::from_secs(8),
+            Duration::from_secs(8),
+        ]);
+        assert_eq!(backoff_delay(0, Some(Duration::from_secs(30))), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_split_message() {
+        let line = format!("-> {}", "x".repeat(97));
+        let text = vec![line.clone(); 100].join("\n");
+        assert_eq!(text.chars().count(), 10_099);
+
+        let chunks = split_message(&text, MESSAGE_MAX_CHARS);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| utf16_len(c) <= MESSAGE_MAX_CHARS));
+        assert!(chunks.iter().flat_map(|c| c.split('\n')).all(|l| l == line));
+        assert_eq!(chunks.join("\n"), text);
+
+        // emoji take two UTF-16 units each
+        let emoji = "💸".repeat(3000);
+        let chunks = split_message(&emoji, MESSAGE_MAX_CHARS);
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|c| utf16_len(c) <= MESSAGE_MAX_CHARS));
+        assert_eq!(chunks.concat(), emoji);
+    }
+
+    #[test]
+    fn test_help_shortcuts() {
+        let help = format!("{}\n\n{}", Command::descriptions(), shortcuts_help());
+        for alias in ["lc", "nc", "uc", "rm", "stm", "sp"] {
+            assert!(help.contains(&format!("/{alias} = ")));
+        }
+        // the table must not drift from the attributes
+        let descriptions = Command::descriptions().to_string();
+        for (command, alias) in SHORTCUTS {
+            assert!(descriptions.contains(&format!("/{command}, /{alias}")), "{command}");
+        }
+        assert!(matches!(Command::parse("/help", "bot"), Ok(Command::Help { .. })));
+        assert!(matches!(Command::parse("/help full", "bot"), Ok(Command::Help { mode }) if mode == "full"));
+    }
+
+    #[test]
+    fn test_parse_cost_date() {
+        let dt = parse_cost_date("2025-02-05").unwrap();
+        assert_eq!(dt.timestamp(), 1738713600);
+        let dt = parse_cost_date("2025-02-05 14:30").unwrap();
+        assert_eq!(dt.timestamp(), 1738713600 + 14 * 3600 + 30 * 60);
+        assert!(parse_cost_date("2025-02-05 25:00").is_none());
+    }
+
+    #[test]
+    fn test_parse_add_cost() {
+        let (alias, date, amount) = parse_add_cost("food 2025-02-05 12.5".to_string()).unwrap();
+        assert_eq!((alias.as_str(), date.as_str(), amount), ("food", "2025-02-05", 12.5));
+        let (_, date, _) = parse_add_cost("food 2025-02-05 14:30 12.5".to_string()).unwrap();
+        assert_eq!(date, "2025-02-05 14:30");
+        assert!(parse_add_cost("food 12.5".to_string()).is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_free_text_logs_db_error() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(captured.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        // callsites hit by parallel tests may have cached interest without our subscriber
+        tracing::callsite::rebuild_interest_cache();
+
+        let db = DB::from_memory().await.unwrap();
+        db.close().await;
+        let res = handle_free_text(&db, ChatId(7), None, "food 12.5", &Settings::default()).await;
+        assert!(res.is_err());
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("ERROR"));
+        assert!(logs.contains("handle_free_text"));
+        assert!(logs.contains("chat_id=7"));
+        assert!(!logs.contains("12.5"));
+    }
+
+    #[tokio::test]
+    async fn test_free_text_auto_uncategorized() {
+        let db = DB::from_memory().await.unwrap();
+        let mut settings = Settings::default();
+
+        let action = handle_free_text(&db, ChatId(0), None, "12.5", &settings).await.unwrap();
+        assert_eq!(action, FreeTextAction::AskAlias { amount: 12.5 });
+        assert!(db.get_category_by_alias(db::ChatId(0), UNCATEGORIZED_ALIAS.to_string()).await.unwrap().is_none());
+
+        settings.auto_uncategorized = true;
+        let action = handle_free_text(&db, ChatId(0), None, "12.5", &settings).await.unwrap();
+        assert!(matches!(action, FreeTextAction::Logged { .. }));
+        let cat = db.get_category_by_alias(db::ChatId(0), UNCATEGORIZED_ALIAS.to_string()).await.unwrap();
+        assert_eq!(cat.unwrap().category.name, UNCATEGORIZED_NAME);
+        assert_eq!(db.get_stat(db::ChatId(0), None, None).await.unwrap().amount(), 12.5);
+    }
+
+    #[test]
+    fn test_parse_entry_any_order() {
+        let march = parse_date("2025-03-01");
+        for text in ["food 12.5 2025-03-01", "12.5 food 2025-03-01", "2025-03-01 12.5 food", "2025-03-01  food\t12.5"] {
+            let entry = parse_entry(text, false);
+            assert_eq!(entry, Entry { amount: Some(12.5), date: march, words: vec!["food"] }, "{text}");
+        }
+        assert_eq!(parse_entry("12 taxi", false), Entry { amount: Some(12.0), date: None, words: vec!["taxi"] });
+        assert_eq!(parse_entry("taxi", false), Entry { amount: None, date: None, words: vec!["taxi"] });
+        assert_eq!(parse_entry("12.5 taxi", true).amount, Some(12.5));
+    }
+
+    #[tokio::test]
+    async fn test_free_text_strict_categories() {
+        let db = DB::from_memory().await.unwrap();
+        let settings = Settings { strict_categories: true, auto_uncategorized: true, ..Settings::default() };
+        let action = handle_free_text(&db, ChatId(0), None, "12.5", &settings).await.unwrap();
+        assert_eq!(action, FreeTextAction::NeedAlias);
+        assert!(db.get_stat(db::ChatId(0), None, None).await.unwrap().is_empty());
+
+        let settings = Settings { strict_categories: false, ..settings };
+        let action = handle_free_text(&db, ChatId(0), None, "12.5", &settings).await.unwrap();
+        assert!(matches!(action, FreeTextAction::Logged { .. }));
+    }
+
+    #[test]
+    fn test_split_cents_left() {
+        assert_eq!(split_cents_left(50.1, &[]), 5010);
+        assert_eq!(split_cents_left(50.1, &[(1, 30.05)]), 2005);
+        assert_eq!(split_cents_left(50.1, &[(1, 30.05), (2, 20.05)]), 0);
+        assert_eq!(split_cents_left(10.0, &[(1, 12.0)]), -200);
+    }
+
+    #[tokio::test]
+    async fn test_free_text_integer_cents() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let settings = Settings { integer_cents_input: true, ..Settings::default() };
+        let action = handle_free_text(&db, ChatId(0), None, "food 1250", &settings).await.unwrap();
+        assert!(matches!(action, FreeTextAction::Logged { .. }));
+        assert_eq!(db.first_cost(db::ChatId(0)).await.unwrap().unwrap().amount, 12.5);
+    }
+
+    #[tokio::test]
+    async fn test_free_text_with_date() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let action = handle_free_text(&db, ChatId(0), None, "2025-03-01 food 12.5", &Settings::default()).await.unwrap();
+        assert!(matches!(action, FreeTextAction::Logged { .. }));
+        let cost = db.first_cost(db::ChatId(0)).await.unwrap().unwrap();
+        assert_eq!(Some(cost.dt), parse_date("2025-03-01"));
+        assert_eq!(cost.amount, 12.5);
+    }
+
+    #[test]
+    fn test_period_report() {
+        let settings = Settings {
+            timezone: chrono_tz::Asia::Tokyo,
+            fiscal_start_day: 25,
+            ..Settings::default()
+        };
+        let now = Utc.with_ymd_and_hms(2025, 3, 10, 12, 0, 0).unwrap();
+        assert_eq!(period_report(now, &settings), [
+            "This month is counted",
+            "from 2025-02-24 15:00 UTC (2025-02-25 00:00 JST)",
+            "to 2025-03-24 15:00 UTC (2025-03-25 00:00 JST), not included"
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_period_header() {
+        let settings = Settings { timezone: chrono_tz::Asia::Tokyo, ..Settings::default() };
+        let now = Utc.with_ymd_and_hms(2025, 2, 28, 20, 0, 0).unwrap();
+        let (date_from, date_to) = local_month_bounds(now, settings.timezone);
+        assert_eq!(period_header(date_from, date_to, &settings), "2025-03-01 - 2025-03-31");
+    }
+
+    #[test]
+    fn test_parse_edges() {
+        assert_eq!(parse_edges("5 20 100"), Some(vec![5.0, 20.0, 100.0]));
+        assert_eq!(parse_edges("20 5"), None);
+        assert_eq!(parse_edges("0 5"), None);
+        assert_eq!(parse_edges("5 x"), None);
+    }
+
+    #[test]
+    fn test_parse_category_list() {
+        assert_eq!(
+            parse_category_list("food:Food, rent: Rent ,fun:Entertainment,"),
+            Some(vec![
+                ("food".to_string(), "Food".to_string()),
+                ("rent".to_string(), "Rent".to_string()),
+                ("fun".to_string(), "Entertainment".to_string())
+            ])
+        );
+        assert_eq!(parse_category_list("food Food"), None);
+        assert_eq!(parse_category_list("food:Food, :Rent"), None);
+        assert_eq!(parse_category_list(" , "), None);
+    }
+
+    #[test]
+    fn test_parse_rename_callback() {
+        assert_eq!(parse_rename_callback("rename:42"), Some(42));
+        assert_eq!(parse_rename_callback(&format!("{RENAME_CALLBACK}7")), Some(7));
+        assert_eq!(parse_rename_callback("rename:"), None);
+        assert_eq!(parse_rename_callback("rename:x"), None);
+        assert_eq!(parse_rename_callback("pin:42"), None);
+    }
+
+    #[test]
+    fn test_parse_amount_reply() {
+        assert_eq!(parse_amount_reply(Some("12.5")), Ok(12.5));
+        assert_eq!(parse_amount_reply(Some(" 7 ")), Ok(7.0));
+        assert_eq!(parse_amount_reply(Some("lots")), Err("Specify amount"));
+        // a sticker or photo has no text, the user is asked again instead of being ignored
+        assert_eq!(parse_amount_reply(None), Err("Please send the amount as text, e.g. 12.50"));
+    }
+
+    #[test]
+    fn test_is_command_keyword() {
+        assert!(is_command_keyword("stm"));
+        assert!(is_command_keyword("help"));
+        assert!(is_command_keyword("Start"));
+        assert!(is_command_keyword("cost"));
+        assert!(!is_command_keyword("food"));
+    }
+
+    #[tokio::test]
+    async fn test_default_cost() {
+        let db = DB::from_memory().await.unwrap();
+        let coffee = db.create_category(db::ChatId(0), "coffee".to_string(), "Coffee".to_string()).await.unwrap();
+        assert_eq!(default_cost(&db, ChatId(0), "coffee".to_string()).await.unwrap(), None);
+        assert_eq!(default_cost(&db, ChatId(0), "tea".to_string()).await.unwrap(), None);
+
+        assert!(db.set_default_amount(db::ChatId(0), "coffee".to_string(), Some(3.5)).await.unwrap());
+        assert!(!db.set_default_amount(db::ChatId(0), "tea".to_string(), Some(2.0)).await.unwrap());
+        let cost = default_cost(&db, ChatId(0), "coffee".to_string()).await.unwrap().unwrap();
+        assert_eq!(cost, NewCost::new(coffee, 3.5));
+
+        db.set_default_amount(db::ChatId(0), "coffee".to_string(), None).await.unwrap();
+        assert_eq!(default_cost(&db, ChatId(0), "coffee".to_string()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_misc_category_created_once() {
+        let db = DB::from_memory().await.unwrap();
+        let id = misc_category(&db, ChatId(0)).await.unwrap();
+        assert_eq!(misc_category(&db, ChatId(0)).await.unwrap(), id);
+        let cat = db.get_category_by_alias(db::ChatId(0), MISC_ALIAS.to_string()).await.unwrap().unwrap();
+        assert_eq!(cat.category.name, MISC_NAME);
+        assert_eq!(db.get_categories(db::ChatId(0)).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_recategorize_misc() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        assert_eq!(recategorize_misc(&db, ChatId(0), "food".to_string()).await.unwrap(), Some(0));
+
+        let misc = misc_category(&db, ChatId(0)).await.unwrap();
+        db.create_cost(misc, 10.0, None).await.unwrap();
+        db.create_cost(misc, 2.5, None).await.unwrap();
+        db.create_cost(food, 1.0, None).await.unwrap();
+
+        assert_eq!(recategorize_misc(&db, ChatId(0), "nope".to_string()).await.unwrap(), None);
+        assert_eq!(recategorize_misc(&db, ChatId(0), "food".to_string()).await.unwrap(), Some(2));
+        let stat = db.get_category_stat_this_month(db::ChatId(0), food).await.unwrap();
+        assert_eq!(stat.amount(), 13.5);
+        let stat = db.get_category_stat_this_month(db::ChatId(0), misc).await.unwrap();
+        assert_eq!(stat.amount(), 0.0);
+    }
+
+    #[derive(Default)]
+    struct RecordingNotifier(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, _chat_id: ChatId, text: &str) -> Result<(), BotError> {
+            self.0.lock().unwrap().push(text.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_cost_quiet() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let metrics = Metrics::default();
+        let notifier = RecordingNotifier::default();
+        let cost = NewCost::new(food, 12.5);
+
+        let quiet = Settings { quiet: true, ..Settings::default() };
+        save_cost(&db, &notifier, &metrics, ChatId(0), &quiet, &cost, "Created!").await.unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+        assert_eq!(db.total_amount(db::ChatId(0), None, None).await.unwrap(), 12.5);
+
+        save_cost(&db, &notifier, &metrics, ChatId(0), &Settings::default(), &cost, "Created!").await.unwrap();
+        assert_eq!(*notifier.0.lock().unwrap(), vec!["Created!".to_string()]);
+        assert_eq!(db.total_amount(db::ChatId(0), None, None).await.unwrap(), 25.0);
+    }
+
+    #[tokio::test]
+    async fn test_free_text_muted() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let settings = Settings { muted: true, ..Settings::default() };
+
+        let action = handle_free_text(&db, ChatId(0), None, "food 12.50", &settings).await.unwrap();
+        assert_eq!(action, FreeTextAction::Muted);
+        assert!(db.first_cost(db::ChatId(0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_free_text_over_cap_asks_confirmation() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let settings = Settings { max_transaction_cent: 10000, ..Settings::default() };
+
+        let action = handle_free_text(&db, ChatId(0), None, "food 12500", &settings).await.unwrap();
+        assert_eq!(action, FreeTextAction::Confirm(NewCost::new(cat_id, 12500.0)));
+        assert!(db.get_stat(db::ChatId(0), None, None).await.unwrap().is_empty());
+
+        let action = handle_free_text(&db, ChatId(0), None, "food 100", &settings).await.unwrap();
+        assert!(matches!(action, FreeTextAction::Logged { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_alias_availability() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(db::ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        assert_eq!(alias_availability(&db, ChatId(0), "food".to_string()).await.unwrap(), "taken by Food");
+        assert_eq!(alias_availability(&db, ChatId(0), "fun".to_string()).await.unwrap(), "available");
+    }
+
+    #[test]
+    fn test_category_keyboard_favorites_first() {
+        let cat = |id: i64, alias: &str, is_favorite| CategoryRow {
+            id,
+            chat_id: db::ChatId(0),
+            category: Category::new(alias.to_string(), alias.to_string()),
+            is_favorite
+        };
+        let cats = [cat(1, "a", false), cat(2, "b", true), cat(3, "c", false), cat(4, "d", true)];
+        let keyboard = category_keyboard(&cats);
+        let aliases = keyboard.keyboard.iter()
+            .flatten()
+            .map(|b| b.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(aliases, vec!["b", "d", "a", "c"]);
+        assert_eq!(keyboard.keyboard[0].len(), KEYBOARD_COLUMNS);
+    }
+
+    #[test]
+    fn test_filter_by_prefix() {
+        let cat = |id: i64, alias: &str, name: &str| CategoryRow {
+            id,
+            chat_id: db::ChatId(0),
+            category: Category::new(alias.to_string(), name.to_string()),
+            is_favorite: false
+        };
+        let cats = [cat(1, "f", "Food"), cat(2, "fun", "Entertainment"), cat(3, "r", "Rent")];
+        let ids = |prefix| filter_by_prefix(&cats, prefix).iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids("f"), vec![1, 2]);
+        assert_eq!(ids("FOO"), vec![1]);
+        assert_eq!(ids("ent"), vec![2]);
+        assert_eq!(ids(""), vec![1, 2, 3]);
+        assert!(ids("x").is_empty());
+    }
+}
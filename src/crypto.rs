@@ -0,0 +1,142 @@
+use std::ffi::c_void;
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce
+};
+use libsqlite3_sys as ffi;
+use sqlx::sqlite::LockedSqliteHandle;
+
+
+/// Env var with the 64 hex character key, amounts stay plaintext without it
+pub const KEY_VAR: &str = "AMOUNT_KEY";
+const NONCE_LEN: usize = 12;
+/// Name of the SQL function, 0-terminated for sqlite
+static FN_NAME: &[u8] = b"decrypt_amount\0";
+static UNREADABLE: &[u8] = b"encrypted amount can't be read, check AMOUNT_KEY\0";
+
+/// Encrypts amounts in cents for storage, the blob is the nonce followed by the ciphertext
+#[derive(Clone)]
+pub struct AmountCipher(ChaCha20Poly1305);
+
+impl AmountCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self(ChaCha20Poly1305::new(key.into()))
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self::new(&key))
+    }
+
+    /// `Ok(None)` when `AMOUNT_KEY` is not set, `Err` when it's set but malformed
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var(KEY_VAR) {
+            Ok(hex) => Self::from_hex(&hex)
+                .map(Some)
+                .ok_or_else(|| format!("{KEY_VAR} must be 64 hex characters")),
+            Err(_) => Ok(None)
+        }
+    }
+
+    pub fn encrypt(&self, cents: i64) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.0.encrypt(&nonce, cents.to_le_bytes().as_slice())
+            .expect("in-memory encryption of 8 bytes can't fail");
+        let mut blob = nonce.to_vec();
+        blob.extend(ciphertext);
+        blob
+    }
+
+    /// `None` for blobs written with another key or damaged ones
+    pub fn decrypt(&self, blob: &[u8]) -> Option<i64> {
+        if blob.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let plain = self.0.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        Some(i64::from_le_bytes(plain.try_into().ok()?))
+    }
+}
+
+/// Registers `decrypt_amount(amount_enc, amount_cent)` on a connection. It returns
+/// `amount_cent` for plaintext rows and fails the query on blobs it can't decrypt.
+/// Returns the result code of `sqlite3_create_function_v2`
+pub fn register(handle: &mut LockedSqliteHandle<'_>, cipher: Option<AmountCipher>) -> i32 {
+    let data = Box::into_raw(Box::new(cipher));
+    // sqlite owns `data` from here on and hands it to `drop_cipher`, also when registration fails
+    unsafe {
+        ffi::sqlite3_create_function_v2(
+            handle.as_raw_handle().as_ptr(),
+            FN_NAME.as_ptr().cast(),
+            2,
+            ffi::SQLITE_UTF8 | ffi::SQLITE_DETERMINISTIC,
+            data.cast(),
+            Some(decrypt_amount),
+            None,
+            None,
+            Some(drop_cipher)
+        )
+    }
+}
+
+unsafe extern "C" fn decrypt_amount(
+    ctx: *mut ffi::sqlite3_context,
+    n_arg: i32,
+    args: *mut *mut ffi::sqlite3_value
+) {
+    let args = std::slice::from_raw_parts(args, n_arg as usize);
+    let [blob, cents] = args else {
+        ffi::sqlite3_result_error_code(ctx, ffi::SQLITE_MISUSE);
+        return;
+    };
+    if ffi::sqlite3_value_type(*blob) != ffi::SQLITE_BLOB {
+        ffi::sqlite3_result_value(ctx, *cents);
+        return;
+    }
+    // the pointer has to be taken before the length, see sqlite3_value_bytes
+    let ptr = ffi::sqlite3_value_blob(*blob).cast::<u8>();
+    let len = ffi::sqlite3_value_bytes(*blob) as usize;
+    let bytes = match ptr.is_null() {
+        true => &[][..],
+        false => std::slice::from_raw_parts(ptr, len)
+    };
+    let cipher = &*ffi::sqlite3_user_data(ctx).cast::<Option<AmountCipher>>();
+    match cipher.as_ref().and_then(|c| c.decrypt(bytes)) {
+        Some(cents) => ffi::sqlite3_result_int64(ctx, cents),
+        None => ffi::sqlite3_result_error(ctx, UNREADABLE.as_ptr().cast(), -1)
+    }
+}
+
+unsafe extern "C" fn drop_cipher(data: *mut c_void) {
+    drop(Box::from_raw(data.cast::<Option<AmountCipher>>()));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cipher = AmountCipher::new(&[7; 32]);
+        let blob = cipher.encrypt(1250);
+        assert_ne!(blob, cipher.encrypt(1250));
+        assert_eq!(cipher.decrypt(&blob), Some(1250));
+        assert_eq!(cipher.decrypt(&cipher.encrypt(-5)), Some(-5));
+        assert_eq!(AmountCipher::new(&[8; 32]).decrypt(&blob), None);
+        assert_eq!(cipher.decrypt(&blob[..5]), None);
+
+        let hex = "07".repeat(32);
+        assert_eq!(AmountCipher::from_hex(&hex).unwrap().decrypt(&blob), Some(1250));
+        assert!(AmountCipher::from_hex("07").is_none());
+        assert!(AmountCipher::from_hex(&"zz".repeat(32)).is_none());
+    }
+}
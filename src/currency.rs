@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+
+/// Uppercased ISO 4217-like code, `None` unless it's three latin letters
+pub fn parse_code(code: &str) -> Option<String> {
+    let code = code.trim();
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(code.to_ascii_uppercase())
+    } else {
+        None
+    }
+}
+
+/// Rough USD value of one unit of each supported currency
+pub fn static_rates() -> HashMap<String, f64> {
+    [
+        ("USD", 1.0),
+        ("EUR", 1.08),
+        ("GBP", 1.27),
+        ("CHF", 1.13),
+        ("RUB", 0.011),
+        ("KZT", 0.002),
+        ("GEL", 0.37),
+        ("TRY", 0.028),
+        ("AMD", 0.0026),
+        ("RSD", 0.0092)
+    ]
+    .into_iter()
+    .map(|(code, rate)| (code.to_string(), rate))
+    .collect()
+}
+
+/// Cash rounding steps in cents, currencies not listed go by a single cent
+const ROUNDING_INCREMENTS: &[(&str, i64)] = &[
+    ("CHF", 5)
+];
+
+pub fn rounding_increment(code: &str) -> i64 {
+    ROUNDING_INCREMENTS.iter()
+        .find(|(c, _)| *c == code)
+        .map_or(1, |(_, step)| *step)
+}
+
+/// Amount in cents rounded to the currency's increment, halfway goes up
+pub fn to_cents(amount: f64, code: Option<&str>) -> i64 {
+    let cents = (amount * 100.0).round() as i64;
+    let step = code.map_or(1, rounding_increment);
+    (cents + step / 2).div_euclid(step) * step
+}
+
+/// Converts `amount` between currencies whose values are given in one common unit
+pub fn convert(amount: f64, from: &str, to: &str, rates: &HashMap<String, f64>) -> Option<f64> {
+    if from == to {
+        return Some(amount);
+    }
+    let from = rates.get(from)?;
+    let to = rates.get(to)?;
+    Some(amount * from / to)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_code() {
+        assert_eq!(parse_code("eur"), Some("EUR".to_string()));
+        assert_eq!(parse_code("euro"), None);
+        assert_eq!(parse_code("12$"), None);
+    }
+
+    #[test]
+    fn test_to_cents() {
+        assert_eq!(to_cents(12.32, Some("CHF")), 1230);
+        assert_eq!(to_cents(12.33, Some("CHF")), 1235);
+        assert_eq!(to_cents(12.38, Some("CHF")), 1240);
+        assert_eq!(to_cents(12.32, Some("USD")), 1232);
+        assert_eq!(to_cents(12.33, None), 1233);
+    }
+
+    #[test]
+    fn test_convert() {
+        let rates = HashMap::from([("USD".to_string(), 1.0), ("EUR".to_string(), 1.25)]);
+        assert_eq!(convert(10.0, "EUR", "USD", &rates), Some(12.5));
+        assert_eq!(convert(12.5, "USD", "EUR", &rates), Some(10.0));
+        assert_eq!(convert(5.0, "XYZ", "XYZ", &rates), Some(5.0));
+        assert_eq!(convert(5.0, "GBP", "USD", &rates), None);
+    }
+}
@@ -1,25 +1,81 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::time::Duration;
 
-use chrono::{DateTime, Datelike, TimeZone, Utc};
+use chrono::{DateTime, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
 use sqlx::{
-    Row,
-    sqlite::{SqlitePool, SqliteRow}
+    pool::PoolConnection,
+    Row, Sqlite,
+    sqlite::{SqliteConnection, SqlitePool, SqlitePoolOptions, SqliteRow}
 };
+use crate::crypto::{self, AmountCipher};
+use crate::currency::{convert, to_cents};
 use crate::item::Category;
-use teloxide::types::ChatId;
+use crate::period::{local_cycle_bounds, local_month_bounds, month_bounds, previous_month_bounds, week_bounds};
+use crate::recurring::Frequency;
+use crate::report::{format_date, TreeNode};
+use crate::settings::{Setting, Settings};
+use serde::Serialize;
 use thiserror::Error;
+use tracing::instrument;
+
+/// Telegram chat id as the data layer sees it, the bot converts at its boundary
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChatId(pub i64);
+
+/// Outcome of `set_parent`
+#[derive(Debug, PartialEq)]
+pub enum ParentUpdate {
+    Done,
+    NotFound,
+    /// The parent is the child itself or one of its subcategories
+    Cycle
+}
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const RECEIPT_WIDTH: usize = 32;
+const SQLITE_READONLY: i32 = 8;
+const SQLITE_FULL: i32 = 13;
 
 
 #[derive(Error, Debug)]
 pub enum DBError {
     #[error("failed to connect: {0}")]
-    Connection(#[from] sqlx::Error),
+    Connection(sqlx::Error),
     #[error("failed to migrate: {0}")]
     Migration(#[from] sqlx::migrate::MigrateError),
     #[error("wrong date format: {0}")]
-    DateFormatError(String)
+    DateFormatError(String),
+    #[error("database is read-only")]
+    ReadOnly,
+    #[error("database or disk is full")]
+    Full
+}
+
+impl From<sqlx::Error> for DBError {
+    fn from(error: sqlx::Error) -> Self {
+        // primary result code, extended codes keep it in the low byte
+        let code = error.as_database_error()
+            .and_then(|e| e.code())
+            .and_then(|c| c.parse::<i32>().ok())
+            .map(|c| c & 0xff);
+        match code {
+            Some(SQLITE_READONLY) => DBError::ReadOnly,
+            Some(SQLITE_FULL) => DBError::Full,
+            _ => DBError::Connection(error)
+        }
+    }
 }
 
+impl DBError {
+    /// Storage can't take writes, retrying won't help until an operator steps in
+    pub fn is_storage_unavailable(&self) -> bool {
+        matches!(self, DBError::ReadOnly | DBError::Full)
+    }
+}
+
+#[derive(Serialize)]
 pub struct StatCategory {
     category: Category,
     n_items: u64,
@@ -36,20 +92,59 @@ impl From<SqliteRow> for StatCategory {
     }
 }
 
+impl StatCategory {
+    pub fn category(&self) -> &Category {
+        &self.category
+    }
+
+    pub fn n_items(&self) -> u64 {
+        self.n_items
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.amount
+    }
+
+    fn line(&self, round: bool) -> String {
+        format!("-> {}: n={}, amount={}", self.category.name, self.n_items, format_amount(self.amount, round))
+    }
+}
+
 impl Display for StatCategory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "-> {}: n={}, amount={:.2}", self.category.name, self.n_items, self.amount)
+        write!(f, "{}", self.line(false))
+    }
+}
+
+/// Whole units when `round`, cents otherwise
+pub fn format_amount(amount: f64, round: bool) -> String {
+    match round {
+        true => format!("{:.0}", amount),
+        false => format!("{:.2}", amount)
     }
 }
 
+#[derive(Serialize)]
 pub struct Stat {
-    items: Vec<StatCategory> 
+    items: Vec<StatCategory>,
+    #[serde(skip)]
+    round: bool
 }
 
 impl Stat {
 
     pub fn new(items: Vec<StatCategory>) -> Self {
-        Self { items }
+        Self { items, round: false }
+    }
+
+    /// Show whole amounts instead of cents when displayed
+    pub fn round_display(mut self, round: bool) -> Self {
+        self.round = round;
+        self
+    }
+
+    fn format_amount(&self, amount: f64) -> String {
+        format_amount(amount, self.round)
     }
 
     pub fn n_items(&self) -> u64 {
@@ -64,26 +159,174 @@ impl Stat {
         self.len() == 0
     }
 
+    /// Per-category lines, in the order the query returned them
+    pub fn items(&self) -> &[StatCategory] {
+        &self.items
+    }
+
     pub fn len(&self) -> usize {
         self.items.len()
     }
+
+    /// Percent of the total per category name, largest first. Empty when nothing was spent
+    pub fn shares(&self) -> Vec<(String, f64)> {
+        let total = self.amount();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+        let mut shares = self.items.iter()
+            .map(|i| (i.category.name.clone(), i.amount / total * 100.0))
+            .collect::<Vec<_>>();
+        shares.sort_by(|a, b| b.1.total_cmp(&a.1));
+        shares
+    }
+
+    /// Per-category change from `self` (earlier period) to `other`
+    pub fn diff(&self, other: &Stat) -> StatDiff {
+        let mut categories = self.items.iter().map(|i| CategoryDelta {
+            category: i.category.clone(),
+            before: Some(i.amount),
+            after: other.items.iter()
+                .find(|o| o.category.alias == i.category.alias)
+                .map(|o| o.amount)
+        }).collect::<Vec<_>>();
+        categories.extend(
+            other.items.iter()
+                .filter(|o| !self.items.iter().any(|i| i.category.alias == o.category.alias))
+                .map(|o| CategoryDelta { category: o.category.clone(), before: None, after: Some(o.amount) })
+        );
+        StatDiff { categories }
+    }
+
+    /// Fixed-width listing with right-aligned amounts and a total line, meant for a monospace font
+    pub fn receipt(&self, header: &str) -> String {
+        let line = |label: &str, amount: f64| {
+            let amount = self.format_amount(amount);
+            let room = RECEIPT_WIDTH.saturating_sub(amount.chars().count() + 1);
+            let label = label.chars().take(room).collect::<String>();
+            format!("{:<room$} {}", label, amount)
+        };
+        let rule = "-".repeat(RECEIPT_WIDTH);
+        let mut lines = vec![format!("{:^width$}", header, width = RECEIPT_WIDTH), rule.clone()];
+        lines.extend(self.items.iter().map(|i| line(&i.category.name, i.amount)));
+        lines.push(rule);
+        lines.push(line("TOTAL", self.amount()));
+        lines.join("\n")
+    }
 }
 
 impl Display for Stat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let cats = self.items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        let cats = self.items.iter().map(|i| i.line(self.round)).collect::<Vec<_>>().join("\n");
+        let total = match self.round {
+            true => self.format_amount(self.amount()),
+            false => self.amount().to_string()
+        };
         let report = format!(
             "{} \n=======================\nItems: {} \t Amount: {}",
-            cats, self.n_items(), self.amount()
+            cats, self.n_items(), total
         );
         write!(f, "{}", report)
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct StatFilter {
+    pub category_id: Option<i64>,
+    pub exclude_category_id: Option<i64>,
+    pub user_id: Option<i64>
+}
+
+impl StatFilter {
+    /// Only costs of this category, resolve aliases with `find_category_by_alias` first
+    pub fn category(mut self, category_id: i64) -> Self {
+        self.category_id = Some(category_id);
+        self
+    }
+
+    pub fn exclude_category(mut self, category_id: i64) -> Self {
+        self.exclude_category_id = Some(category_id);
+        self
+    }
+
+    /// Only costs logged by this user, for chats shared by several people
+    pub fn user(mut self, user_id: i64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+}
+
+pub struct CategoryDelta {
+    pub category: Category,
+    pub before: Option<f64>,
+    pub after: Option<f64>
+}
+
+impl CategoryDelta {
+    pub fn delta(&self) -> f64 {
+        self.after.unwrap_or(0.0) - self.before.unwrap_or(0.0)
+    }
+}
+
+impl Display for CategoryDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.before, self.after) {
+            (None, Some(after)) => write!(f, "+ {}: new {:.2}", self.category.name, after),
+            (Some(before), None) => write!(f, "- {}: gone (was {:.2})", self.category.name, before),
+            (before, after) => write!(
+                f,
+                "-> {}: {:.2} -> {:.2} ({:+.2})",
+                self.category.name, before.unwrap_or(0.0), after.unwrap_or(0.0), self.delta()
+            )
+        }
+    }
+}
+
+pub struct StatDiff {
+    pub categories: Vec<CategoryDelta>
+}
+
+impl StatDiff {
+    pub fn before(&self) -> f64 {
+        self.categories.iter().filter_map(|c| c.before).sum()
+    }
+
+    pub fn after(&self) -> f64 {
+        self.categories.iter().filter_map(|c| c.after).sum()
+    }
+
+    pub fn delta(&self) -> f64 {
+        self.after() - self.before()
+    }
+
+    /// Up to `n` biggest increases and up to `n` biggest decreases, largest change first
+    pub fn movers(&self, n: usize) -> (Vec<&CategoryDelta>, Vec<&CategoryDelta>) {
+        let mut up = self.categories.iter().filter(|c| c.delta() > 0.0).collect::<Vec<_>>();
+        up.sort_by(|a, b| b.delta().total_cmp(&a.delta()));
+        up.truncate(n);
+        let mut down = self.categories.iter().filter(|c| c.delta() < 0.0).collect::<Vec<_>>();
+        down.sort_by(|a, b| a.delta().total_cmp(&b.delta()));
+        down.truncate(n);
+        (up, down)
+    }
+}
+
+impl Display for StatDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cats = self.categories.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        write!(
+            f,
+            "{} \n=======================\nTotal: {:.2} -> {:.2} ({:+.2})",
+            cats, self.before(), self.after(), self.delta()
+        )
+    }
+}
+
 pub struct CategoryRow {
     pub id: i64,
     pub chat_id: ChatId,
-    pub category: Category
+    pub category: Category,
+    pub is_favorite: bool
 }
 
 impl Display for CategoryRow {
@@ -100,29 +343,280 @@ impl From<SqliteRow> for CategoryRow {
             category: Category::new(
                 row.get("alias"),
                 row.get("name")
-            )
+            ),
+            is_favorite: row.get("is_favorite")
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CostRow {
+    pub id: i64,
+    pub dt: DateTime<Utc>,
+    pub category: Category,
+    pub amount: f64,
+    pub currency: Option<String>,
+    /// Telegram file id of an attached receipt photo
+    pub photo_file_id: Option<String>
+}
+
+impl CostRow {
+    /// One listing line, dated in the chat's timezone and date format
+    pub fn display(&self, settings: &Settings) -> String {
+        let mut line = format!(
+            "{} {} {:.2}",
+            format_date(self.dt, settings.timezone, settings.lang), self.category.alias, self.amount
+        );
+        if let Some(code) = &self.currency {
+            line = format!("{line} {code}");
+        }
+        if self.photo_file_id.is_some() {
+            line.push_str(" 📎");
+        }
+        line
+    }
+}
+
+impl From<SqliteRow> for CostRow {
+    fn from(row: SqliteRow) -> Self {
+        Self {
+            id: row.get("id"),
+            dt: DateTime::from_timestamp(row.get("dt"), 0).unwrap_or_default(),
+            category: Category::new(row.get("alias"), row.get("name")),
+            amount: row.get::<i64, _>("amount_cent") as f64 / 100.0,
+            currency: row.get("currency"),
+            photo_file_id: row.get("photo_file_id")
+        }
+    }
+}
+
+pub struct GoalRow {
+    pub amount: f64,
+    pub started: DateTime<Utc>,
+    pub deadline: DateTime<Utc>
+}
+
+pub struct BudgetRow {
+    pub category: Category,
+    /// Effective limit, for percent budgets computed from the income
+    pub limit: f64,
+    /// Share of the monthly income, `None` for a fixed amount
+    pub percent: Option<f64>,
+    pub spent: f64
+}
+
+impl BudgetRow {
+    pub fn is_over(&self) -> bool {
+        self.spent > self.limit
+    }
+}
+
+impl From<SqliteRow> for BudgetRow {
+    fn from(row: SqliteRow) -> Self {
+        Self {
+            category: Category::new(row.get("alias"), row.get("name")),
+            limit: row.get::<i64,_>("limit_cent") as f64 / 100.0,
+            percent: row.get("percent"),
+            spent: row.get::<i64,_>("spent_cent") as f64 / 100.0
+        }
+    }
+}
+
+pub struct RecurringRow {
+    pub id: i64,
+    pub chat_id: ChatId,
+    pub category_id: i64,
+    pub category: Category,
+    pub amount: f64,
+    pub frequency: Frequency,
+    pub last_run: Option<NaiveDate>,
+    pub skip_next: bool
+}
+
+const RECURRING_SELECT: &str = "
+    SELECT
+        r.id AS id,
+        r.chat_id AS chat_id,
+        r.category_id AS category_id,
+        c.alias AS alias,
+        c.name AS name,
+        r.amount_cent AS amount_cent,
+        r.frequency AS frequency,
+        COALESCE(r.day_of_month, r.day_of_week) AS day,
+        r.last_run AS last_run,
+        r.skip_next AS skip_next
+    FROM recurring r
+    JOIN category c ON (r.category_id = c.id)";
+
+impl RecurringRow {
+    /// `None` for rows with an unknown frequency
+    fn from_row(row: SqliteRow) -> Option<Self> {
+        let frequency = Frequency::parse(row.get("frequency"), row.get("day"))?;
+        Some(Self {
+            id: row.get("id"),
+            chat_id: ChatId(row.get("chat_id")),
+            category_id: row.get("category_id"),
+            category: Category::new(row.get("alias"), row.get("name")),
+            amount: row.get::<i64,_>("amount_cent") as f64 / 100.0,
+            frequency,
+            last_run: row.get::<Option<String>,_>("last_run")
+                .and_then(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok()),
+            skip_next: row.get("skip_next")
+        })
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IntegrityReport {
+    /// Counted across every chat, so only filled in for the operator
+    pub orphaned_costs: Option<i64>,
+    pub duplicate_aliases: i64,
+    pub bad_amounts: i64
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.orphaned_costs.unwrap_or(0) == 0 && self.duplicate_aliases == 0 && self.bad_amounts == 0
+    }
+}
+
+impl Display for IntegrityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(orphaned) = self.orphaned_costs {
+            writeln!(f, "Orphaned costs: {orphaned}")?;
         }
+        write!(f, "Duplicate aliases: {}\nBad amounts: {}", self.duplicate_aliases, self.bad_amounts)
+    }
+}
+
+/// Totals across every chat using the bot
+#[derive(Debug, Default, PartialEq)]
+pub struct GlobalCounts {
+    pub chats: i64,
+    pub categories: i64,
+    pub spendings: i64
+}
+
+impl Display for GlobalCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Chats: {}\nCategories: {}\nSpendings: {}",
+            self.chats, self.categories, self.spendings
+        )
     }
 }
 
 #[derive(Clone)]
 pub struct DB {
-    conn: SqlitePool
+    conn: SqlitePool,
+    /// Set in privacy mode, new amounts are then stored only encrypted
+    cipher: Option<AmountCipher>,
+    /// The pool came from `from_pool`, so its connections miss `decrypt_amount` from `after_connect`
+    foreign_pool: bool
 }
 
 impl DB {
     pub async fn new(path: &str) -> Result<Self, DBError> {
-        let pool = SqlitePool::connect(path).await?;
-        sqlx::migrate!("./src/migrations").run(&pool).await?;
-        Ok(Self { conn: pool })
+        let options = SqlitePoolOptions::new()
+            .idle_timeout(POOL_IDLE_TIMEOUT)
+            .test_before_acquire(true);
+        Self::connect(path, options, None).await
     }
 
+    /// Like `new`, but amounts of new costs are encrypted with `cipher`.
+    /// Costs stored in plaintext before stay readable
+    pub async fn encrypted(path: &str, cipher: AmountCipher) -> Result<Self, DBError> {
+        let options = SqlitePoolOptions::new()
+            .idle_timeout(POOL_IDLE_TIMEOUT)
+            .test_before_acquire(true);
+        Self::connect(path, options, Some(cipher)).await
+    }
+
+    /// Every in-memory connection is a separate database,
+    /// so the pool keeps exactly one and never recycles it
     pub async fn from_memory() -> Result<Self, DBError> {
-        Self::new(":memory:").await
+        let options = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+        Self::connect(":memory:", options, None).await
+    }
+
+    /// Wraps a pool managed by the caller. Migrations are not applied,
+    /// call `migrate` unless the schema is already in place
+    pub fn from_pool(pool: SqlitePool) -> Self {
+        Self { conn: pool, cipher: None, foreign_pool: true }
+    }
+
+    async fn connect(
+        path: &str,
+        options: SqlitePoolOptions,
+        cipher: Option<AmountCipher>
+    ) -> Result<Self, DBError> {
+        let registered = cipher.clone();
+        let options = options.after_connect(move |conn, _| {
+            let cipher = registered.clone();
+            Box::pin(async move { Self::register(conn, cipher).await })
+        });
+        let db = Self { conn: options.connect(path).await?, cipher, foreign_pool: false };
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn register(conn: &mut SqliteConnection, cipher: Option<AmountCipher>) -> Result<(), sqlx::Error> {
+        let mut handle = conn.lock_handle().await?;
+        match crypto::register(&mut handle, cipher) {
+            libsqlite3_sys::SQLITE_OK => Ok(()),
+            code => Err(sqlx::Error::Protocol(format!("failed to register decrypt_amount: {code}")))
+        }
+    }
+
+    /// Connection for queries that call `decrypt_amount`. Connections of a foreign pool
+    /// get it registered on every use, as there is no hook for when they are opened
+    async fn amounts(&self) -> Result<PoolConnection<Sqlite>, DBError> {
+        let mut conn = self.conn.acquire().await?;
+        if self.foreign_pool {
+            Self::register(&mut conn, self.cipher.clone()).await?;
+        }
+        Ok(conn)
+    }
+
+    /// Values for the `amount_cent` and `amount_enc` columns
+    fn seal(&self, cents: i64) -> (Option<i64>, Option<Vec<u8>>) {
+        match &self.cipher {
+            Some(cipher) => (None, Some(cipher.encrypt(cents))),
+            None => (Some(cents), None)
+        }
+    }
+
+    pub async fn migrate(&self) -> Result<(), DBError> {
+        sqlx::migrate!("./src/migrations").run(&self.conn).await?;
+        Ok(())
+    }
+
+    pub async fn close(&self) {
+        self.conn.close().await
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn ping(&self) -> Result<(), DBError> {
+        sqlx::query("SELECT 1").execute(&self.conn).await?;
+        Ok(())
+    }
+
+    /// Latest applied migration version, 0 before any migration ran
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn schema_version(&self) -> Result<i64, DBError> {
+        let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success=1")
+            .fetch_one(&self.conn)
+            .await?;
+        Ok(version.unwrap_or(0))
     }
 
+    #[instrument(level = "debug", skip(self), err)]
     pub async fn get_categories(&self, chat_id: ChatId) -> Result<Vec<CategoryRow>, DBError> {
-        let categories = sqlx::query("SELECT id, alias, name, chat_id FROM category WHERE chat_id=? ORDER BY id")
+        let categories = sqlx::query("SELECT id, alias, name, chat_id, is_favorite FROM category WHERE chat_id=? AND is_archived=0 ORDER BY sort_order IS NULL, sort_order, id")
             .bind(chat_id.0)
             .map(| row: SqliteRow | CategoryRow::from(row))
             .fetch_all(&self.conn)
@@ -130,8 +624,30 @@ impl DB {
         Ok(categories)
     }
 
+    /// Active category by its main or extra alias
+    #[instrument(level = "debug", skip(self), err)]
     pub async fn get_category_by_alias(&self, chat_id: ChatId, alias: String) -> Result<Option<CategoryRow>, DBError> {
-        let category = sqlx::query("SELECT id, chat_id, alias, name FROM category WHERE chat_id=? AND alias=? LIMIT 1")
+        self.category_by_alias(chat_id, alias, false).await
+    }
+
+    /// Like `get_category_by_alias`, but archived categories are found too
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn find_category_by_alias(&self, chat_id: ChatId, alias: String) -> Result<Option<CategoryRow>, DBError> {
+        self.category_by_alias(chat_id, alias, true).await
+    }
+
+    async fn category_by_alias(&self, chat_id: ChatId, alias: String, include_archived: bool) -> Result<Option<CategoryRow>, DBError> {
+        let category = sqlx::query("
+            SELECT id, chat_id, alias, name, is_favorite FROM category
+            WHERE chat_id=? AND (is_archived=0 OR ?) AND (
+                alias=? OR
+                id IN (SELECT category_id FROM category_alias WHERE chat_id=? AND alias=?)
+            )
+            LIMIT 1
+            ")
+            .bind(chat_id.0)
+            .bind(include_archived)
+            .bind(alias.clone())
             .bind(chat_id.0)
             .bind(alias)
             .map(| row: SqliteRow | CategoryRow::from(row))
@@ -140,250 +656,2927 @@ impl DB {
         Ok(category)
     }
 
-    pub async fn update_category(&self, chat_id: ChatId, alias: String, new_alias: String, name: String) -> Result<(), DBError> {
-        sqlx::query("UPDATE category SET alias=?, name=? WHERE chat_id=? and alias=?")
-            .bind(new_alias)
-            .bind(name)
+    /// Whether the alias is used by any category, archived ones and extra aliases included
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn category_exists(&self, chat_id: ChatId, alias: String) -> Result<bool, DBError> {
+        Ok(self.category_by_alias(chat_id, alias, true).await?.is_some())
+    }
+
+    /// Extra alias pointing to an existing category
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn add_alias(&self, chat_id: ChatId, category_id: i64, alias: String) -> Result<(), DBError> {
+        sqlx::query("INSERT INTO category_alias (chat_id, category_id, alias) VALUES (?, ?, ?)")
             .bind(chat_id.0)
+            .bind(category_id)
             .bind(alias)
             .execute(&self.conn)
             .await?;
         Ok(())
     }
 
-    pub async fn create_category(&self, chat_id: ChatId, alias: String, name: String) -> Result<i64, DBError> {
-        let id = sqlx::query(
-            "INSERT INTO category (chat_id, alias, name) VALUES (?, ?, ?) RETURNING id"
-            )
+    /// Categories whose name matches ignoring case and surrounding spaces
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn find_similar_categories(&self, chat_id: ChatId, name: String) -> Result<Vec<CategoryRow>, DBError> {
+        let categories = sqlx::query("
+            SELECT id, chat_id, alias, name, is_favorite FROM category
+            WHERE chat_id=? AND lower(trim(name))=lower(trim(?))
+            ORDER BY id
+            ")
             .bind(chat_id.0)
-            .bind(alias)
             .bind(name)
-            .fetch_one(&self.conn)
-            .await?
-            .get::<i64, _>("id");
-        Ok(id)
+            .map(| row: SqliteRow | CategoryRow::from(row))
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(categories)
     }
 
-    pub async fn create_cost(
-        &self,
-        category_id: i64,
-        amount: f64,
-        dt: Option<DateTime<Utc>>
-    ) -> Result<i64, DBError> {
-        let dt = match dt {
-            Some(dt) => dt.timestamp(),
-            None => Utc::now().timestamp()
-        };
-        let id = sqlx::query(
-            "INSERT INTO spendings (dt, category_id, amount_cent) VALUES (?, ?, ?) RETURNING id"
-            )
-            .bind(dt)
-            .bind(category_id)
-            .bind((amount * 100.0).round() as i64)
-            .fetch_one(&self.conn)
-            .await?
-            .get::<i64, _>("id");
-        Ok(id)
+    /// Archived categories are hidden from listings and matching, their spendings stay in stats.
+    /// Returns `false` when there is no such category
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn archive_category(&self, chat_id: ChatId, alias: String) -> Result<bool, DBError> {
+        self.set_archived(chat_id, alias, true).await
     }
 
-    pub async fn remove_last_cost(&self, chat_id: ChatId) -> Result<Option<i64>, DBError> {
-        let row = sqlx::query("
-            SELECT s.id 
-            FROM spendings s
-            LEFT JOIN category c ON (s.category_id=c.id)
-            WHERE c.chat_id=? and is_deleted=0
-            ORDER BY s.id DESC LIMIT 1
-            ")
-            .bind(chat_id.0)
-            .fetch_optional(&self.conn)
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn unarchive_category(&self, chat_id: ChatId, alias: String) -> Result<bool, DBError> {
+        self.set_archived(chat_id, alias, false).await
+    }
+
+    async fn set_archived(&self, chat_id: ChatId, alias: String, archived: bool) -> Result<bool, DBError> {
+        let category = match self.find_category_by_alias(chat_id, alias).await? {
+            Some(category) => category,
+            None => return Ok(false)
+        };
+        sqlx::query("UPDATE category SET is_archived=? WHERE id=?")
+            .bind(archived)
+            .bind(category.id)
+            .execute(&self.conn)
             .await?;
-        match row {
-            Some(row) => {
-                let id = row.get::<i64,_>("id");
-                sqlx::query("UPDATE spendings SET is_deleted=1 WHERE id=?")
-                    .bind(id)
-                    .execute(&self.conn)
-                    .await?;
-                Ok(Some(id))
-            },
-            None => Ok(None)
-        }
+        Ok(true)
     }
 
-    pub async fn get_stat(
-        &self,
-        chat_id: ChatId,
-        date_from: Option<DateTime<Utc>>,
-        date_to: Option<DateTime<Utc>>
-    ) -> Result<Stat, DBError> {
+    /// Favorites come first on the category keyboard. Returns `false` when there is no such category
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_favorite(&self, chat_id: ChatId, alias: String, favorite: bool) -> Result<bool, DBError> {
+        let category = match self.get_category_by_alias(chat_id, alias).await? {
+            Some(category) => category,
+            None => return Ok(false)
+        };
+        sqlx::query("UPDATE category SET is_favorite=? WHERE id=?")
+            .bind(favorite)
+            .bind(category.id)
+            .execute(&self.conn)
+            .await?;
+        Ok(true)
+    }
 
-        let mut where_clause = "is_deleted=0 AND chat_id=?".to_string();
+    /// Amount logged by `LogDefault`, `None` clears it
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn set_default_amount(&self, chat_id: ChatId, alias: String, amount: Option<f64>) -> Result<bool, DBError> {
+        let category = match self.get_category_by_alias(chat_id, alias).await? {
+            Some(category) => category,
+            None => return Ok(false)
+        };
+        sqlx::query("UPDATE category SET default_amount_cent=? WHERE id=?")
+            .bind(amount.map(|a| to_cents(a, None)))
+            .bind(category.id)
+            .execute(&self.conn)
+            .await?;
+        Ok(true)
+    }
 
-        if let Some(d) = date_from {
-            where_clause = format!("{} AND dt >= {}", where_clause, d.timestamp())
+    /// Makes `child` a subcategory of `parent`, `None` makes it top-level again
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_parent(&self, chat_id: ChatId, child: String, parent: Option<String>) -> Result<ParentUpdate, DBError> {
+        let child = match self.get_category_by_alias(chat_id, child).await? {
+            Some(category) => category,
+            None => return Ok(ParentUpdate::NotFound)
+        };
+        let parent_id = match parent {
+            Some(alias) => match self.get_category_by_alias(chat_id, alias).await? {
+                Some(parent) => Some(parent.id),
+                None => return Ok(ParentUpdate::NotFound)
+            },
+            None => None
+        };
+        if let Some(parent_id) = parent_id {
+            let cycle: bool = sqlx::query_scalar("
+                WITH RECURSIVE ancestor(id) AS (
+                    SELECT ?
+                    UNION
+                    SELECT c.parent_id FROM category c JOIN ancestor a ON (c.id = a.id)
+                    WHERE c.parent_id IS NOT NULL
+                )
+                SELECT EXISTS (SELECT 1 FROM ancestor WHERE id=?)
+                ")
+                .bind(parent_id)
+                .bind(child.id)
+                .fetch_one(&self.conn)
+                .await?;
+            if cycle {
+                return Ok(ParentUpdate::Cycle);
+            }
+        }
+        sqlx::query("UPDATE category SET parent_id=? WHERE id=?")
+            .bind(parent_id)
+            .bind(child.id)
+            .execute(&self.conn)
+            .await?;
+        Ok(ParentUpdate::Done)
+    }
+
+    /// Free-form note shown by `/catinfo`, `None` clears it.
+    /// Returns `false` when there is no such category
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_description(&self, chat_id: ChatId, alias: String, description: Option<String>) -> Result<bool, DBError> {
+        let category = match self.find_category_by_alias(chat_id, alias).await? {
+            Some(category) => category,
+            None => return Ok(false)
+        };
+        sqlx::query("UPDATE category SET description=? WHERE id=?")
+            .bind(description)
+            .bind(category.id)
+            .execute(&self.conn)
+            .await?;
+        Ok(true)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_description(&self, category_id: i64) -> Result<Option<String>, DBError> {
+        let description: Option<String> = sqlx::query_scalar("SELECT description FROM category WHERE id=?")
+            .bind(category_id)
+            .fetch_optional(&self.conn)
+            .await?
+            .flatten();
+        Ok(description)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_default_amount(&self, category_id: i64) -> Result<Option<f64>, DBError> {
+        let cents: Option<i64> = sqlx::query_scalar("SELECT default_amount_cent FROM category WHERE id=?")
+            .bind(category_id)
+            .fetch_optional(&self.conn)
+            .await?
+            .flatten();
+        Ok(cents.map(|c| c as f64 / 100.0))
+    }
+
+    /// Moves all spendings of `from_id` into `into_id`, returns how many were moved
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn merge_categories(&self, from_id: i64, into_id: i64) -> Result<u64, DBError> {
+        let mut tx = self.conn.begin().await?;
+        let moved = Self::move_spendings(&mut tx, from_id, into_id).await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    /// Merges `from_id` into `into_id` and deletes `from_id` with its aliases and budget
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn retire_category(&self, from_id: i64, into_id: i64) -> Result<u64, DBError> {
+        let mut tx = self.conn.begin().await?;
+        let moved = Self::move_spendings(&mut tx, from_id, into_id).await?;
+        for q in [
+            "DELETE FROM category_alias WHERE category_id=?",
+            "DELETE FROM budget WHERE category_id=?",
+            "DELETE FROM category WHERE id=?"
+        ] {
+            sqlx::query(q).bind(from_id).execute(&mut *tx).await?;
+        }
+        sqlx::query("UPDATE recurring SET category_id=? WHERE category_id=?")
+            .bind(into_id)
+            .bind(from_id)
+            .execute(&mut *tx)
+            .await?;
+        // reparenting to `into_id` could close a cycle when it's a descendant
+        sqlx::query("UPDATE category SET parent_id=NULL WHERE parent_id=?")
+            .bind(from_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    /// Removed costs move too so they never point to a deleted category,
+    /// but only active ones are counted
+    async fn move_spendings(conn: &mut SqliteConnection, from_id: i64, into_id: i64) -> Result<u64, DBError> {
+        let moved = sqlx::query("SELECT count(0) AS n FROM spendings WHERE category_id=? AND is_deleted=0")
+            .bind(from_id)
+            .fetch_one(&mut *conn)
+            .await?
+            .get::<i64, _>("n");
+        sqlx::query("UPDATE spendings SET category_id=? WHERE category_id=?")
+            .bind(into_id)
+            .bind(from_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(moved as u64)
+    }
+
+    /// Copies active category definitions, not spendings, skipping aliases already used in `to_chat`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn copy_categories(&self, from_chat: ChatId, to_chat: ChatId) -> Result<u64, DBError> {
+        let copied = sqlx::query("
+            INSERT OR IGNORE INTO category (chat_id, alias, name)
+            SELECT ?, alias, name FROM category
+            WHERE chat_id=? AND is_archived=0 AND alias NOT IN (SELECT alias FROM category_alias WHERE chat_id=?)
+            ORDER BY id
+            ")
+            .bind(to_chat.0)
+            .bind(from_chat.0)
+            .bind(to_chat.0)
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(copied)
+    }
+
+    /// Category definitions as a JSON array of `{"alias", "name"}`, for sharing a scheme
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn export_categories_json(&self, chat_id: ChatId) -> Result<String, DBError> {
+        let categories = self.get_categories(chat_id).await?
+            .into_iter()
+            .map(|c| serde_json::json!({ "alias": c.category.alias, "name": c.category.name }))
+            .collect::<Vec<_>>();
+        Ok(serde_json::Value::Array(categories).to_string())
+    }
+
+    /// Creates categories from `export_categories_json` output, skipping aliases
+    /// already taken in the chat. `None` when the JSON is not such an array
+    #[instrument(level = "debug", skip(self, json), err)]
+    pub async fn import_categories_json(&self, chat_id: ChatId, json: &str) -> Result<Option<u64>, DBError> {
+        let Ok(serde_json::Value::Array(items)) = serde_json::from_str(json) else {
+            return Ok(None);
+        };
+        let mut categories = Vec::with_capacity(items.len());
+        for item in &items {
+            match (item["alias"].as_str(), item["name"].as_str()) {
+                (Some(alias), Some(name)) if !alias.trim().is_empty() => {
+                    categories.push((alias.trim().to_string(), name.to_string()))
+                },
+                _ => return Ok(None)
+            }
+        }
+        let mut created = 0;
+        for (alias, name) in categories {
+            if !self.category_exists(chat_id, alias.clone()).await? {
+                self.create_category(chat_id, alias, name).await?;
+                created += 1;
+            }
+        }
+        Ok(Some(created))
+    }
+
+    /// Creates `(alias, name)` categories in one transaction. Returns how many were
+    /// created and the aliases skipped because the chat or the list already uses them
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn create_categories(
+        &self,
+        chat_id: ChatId,
+        categories: &[(String, String)]
+    ) -> Result<(u64, Vec<String>), DBError> {
+        let mut created = 0;
+        let mut skipped = Vec::new();
+        let mut tx = self.conn.begin().await?;
+        for (alias, name) in categories {
+            let inserted = sqlx::query("
+                INSERT OR IGNORE INTO category (chat_id, alias, name)
+                SELECT ?, ?, ? WHERE ? NOT IN (SELECT alias FROM category_alias WHERE chat_id=?)
+                ")
+                .bind(chat_id.0)
+                .bind(alias)
+                .bind(name)
+                .bind(alias)
+                .bind(chat_id.0)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            match inserted {
+                0 => skipped.push(alias.clone()),
+                _ => created += 1
+            }
+        }
+        tx.commit().await?;
+        Ok((created, skipped))
+    }
+
+    /// Moves the category to a 1-based place in `get_categories`, numbering the rest around it.
+    /// Categories that were never placed follow the placed ones
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_category_position(&self, chat_id: ChatId, alias: String, pos: usize) -> Result<bool, DBError> {
+        let Some(cat) = self.get_category_by_alias(chat_id, alias).await? else {
+            return Ok(false);
+        };
+        let mut ids = self.get_categories(chat_id).await?
+            .into_iter()
+            .map(|c| c.id)
+            .filter(|id| *id != cat.id)
+            .collect::<Vec<_>>();
+        ids.insert(pos.clamp(1, ids.len() + 1) - 1, cat.id);
+
+        let mut tx = self.conn.begin().await?;
+        for (order, id) in ids.iter().enumerate() {
+            sqlx::query("UPDATE category SET sort_order=? WHERE id=?")
+                .bind(order as i64 + 1)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn update_category(&self, chat_id: ChatId, alias: String, new_alias: String, name: String) -> Result<(), DBError> {
+        sqlx::query("UPDATE category SET alias=?, name=? WHERE chat_id=? and alias=?")
+            .bind(new_alias)
+            .bind(name)
+            .bind(chat_id.0)
+            .bind(alias)
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn create_category(&self, chat_id: ChatId, alias: String, name: String) -> Result<i64, DBError> {
+        let id = sqlx::query(
+            "INSERT INTO category (chat_id, alias, name) VALUES (?, ?, ?) RETURNING id"
+            )
+            .bind(chat_id.0)
+            .bind(alias)
+            .bind(name)
+            .fetch_one(&self.conn)
+            .await?
+            .get::<i64, _>("id");
+        Ok(id)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_or_create_category(&self, chat_id: ChatId, alias: String, name: String) -> Result<i64, DBError> {
+        match self.find_category_by_alias(chat_id, alias.clone()).await? {
+            Some(cat) => Ok(cat.id),
+            None => self.create_category(chat_id, alias, name).await
+        }
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_settings(&self, chat_id: ChatId) -> Result<Settings, DBError> {
+        let mut conn = self.conn.acquire().await?;
+        Self::read_settings(&mut conn, chat_id).await
+    }
+
+    /// Settings read through `conn`, so it works inside an open transaction
+    async fn read_settings(conn: &mut SqliteConnection, chat_id: ChatId) -> Result<Settings, DBError> {
+        let rows = sqlx::query("SELECT key, value FROM setting WHERE chat_id=?")
+            .bind(chat_id.0)
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut settings = Settings::default();
+        for row in rows {
+            settings.apply(row.get("key"), row.get("value"));
+        }
+        Ok(settings)
+    }
+
+    /// Chats that have a non-empty value for `setting`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn chats_with_setting(&self, setting: Setting) -> Result<Vec<ChatId>, DBError> {
+        let rows = sqlx::query("SELECT chat_id FROM setting WHERE key=? AND value != '' ORDER BY chat_id")
+            .bind(setting.key())
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(rows.iter().map(|row| ChatId(row.get("chat_id"))).collect())
+    }
+
+    #[instrument(level = "debug", skip(self, value), err)]
+    pub async fn set_setting(&self, chat_id: ChatId, setting: Setting, value: String) -> Result<(), DBError> {
+        sqlx::query("
+            INSERT INTO setting (chat_id, key, value) VALUES (?, ?, ?)
+            ON CONFLICT(chat_id, key) DO UPDATE SET value=excluded.value
+            ")
+            .bind(chat_id.0)
+            .bind(setting.key())
+            .bind(value)
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn create_cost(
+        &self,
+        category_id: i64,
+        amount: f64,
+        dt: Option<DateTime<Utc>>
+    ) -> Result<i64, DBError> {
+        self.create_cost_in(category_id, amount, None, dt).await
+    }
+
+    /// Costs without a currency are in the chat's own one and only those are counted by plain stats.
+    /// The rest is summed per currency by `currency_totals` or converted by `stat_in_base`
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn create_cost_in(
+        &self,
+        category_id: i64,
+        amount: f64,
+        currency: Option<&str>,
+        dt: Option<DateTime<Utc>>
+    ) -> Result<i64, DBError> {
+        self.create_cost_by(category_id, amount, currency, dt, None).await
+    }
+
+    /// Cost attributed to the user who logged it, `None` when unknown.
+    /// The amount is rounded to the currency's step, 0.05 for CHF.
+    /// With `roundup_savings` on, the spare change up to the next whole unit is kept with it
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn create_cost_by(
+        &self,
+        category_id: i64,
+        amount: f64,
+        currency: Option<&str>,
+        dt: Option<DateTime<Utc>>,
+        user_id: Option<i64>
+    ) -> Result<i64, DBError> {
+        let dt = match dt {
+            Some(dt) => dt.timestamp(),
+            None => Utc::now().timestamp()
+        };
+        let mut conn = self.conn.acquire().await?;
+        self.insert_cost(&mut conn, category_id, amount, currency, dt, user_id).await
+    }
+
+    /// Insert shared by every way a cost gets created, applies the currency rounding,
+    /// `roundup_savings` and encryption
+    async fn insert_cost(
+        &self,
+        conn: &mut SqliteConnection,
+        category_id: i64,
+        amount: f64,
+        currency: Option<&str>,
+        dt: i64,
+        user_id: Option<i64>
+    ) -> Result<i64, DBError> {
+        let chat_id: Option<i64> = sqlx::query_scalar("SELECT chat_id FROM category WHERE id=?")
+            .bind(category_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let roundup = match chat_id {
+            Some(chat_id) => Self::read_settings(conn, ChatId(chat_id)).await?.roundup_savings,
+            None => false
+        };
+        let amount_cent = to_cents(amount, currency);
+        let spare_cent = match roundup {
+            true => (100 - amount_cent.rem_euclid(100)) % 100,
+            false => 0
+        };
+        let (amount_cent, amount_enc) = self.seal(amount_cent);
+        // the spare change gives away the cents of the amount, so it's sealed as well
+        let (spare_cent, spare_enc) = self.seal(spare_cent);
+        let id = sqlx::query("
+            INSERT INTO spendings (dt, category_id, amount_cent, amount_enc, currency, user_id, spare_cent, spare_enc)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id
+            ")
+            .bind(dt)
+            .bind(category_id)
+            .bind(amount_cent)
+            .bind(amount_enc)
+            .bind(currency)
+            .bind(user_id)
+            .bind(spare_cent.unwrap_or(0))
+            .bind(spare_enc)
+            .fetch_one(&mut *conn)
+            .await?
+            .get::<i64, _>("id");
+        Ok(id)
+    }
+
+    /// Spare change put aside by `roundup_savings`, removed costs don't count
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn spare_change(&self, chat_id: ChatId) -> Result<f64, DBError> {
+        let mut conn = self.amounts().await?;
+        let cents: Option<i64> = sqlx::query_scalar("
+            SELECT sum(decrypt_amount(s.spare_enc, s.spare_cent))
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=?
+            ")
+            .bind(chat_id.0)
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(cents.unwrap_or(0) as f64 / 100.0)
+    }
+
+    /// Stores one purchase split into `(category_id, amount)` portions as separate costs.
+    /// Nothing is stored and `false` is returned unless the portions add up to `total`
+    /// and every category belongs to the chat
+    #[instrument(level = "debug", skip(self, total, portions), err)]
+    pub async fn create_split_costs(
+        &self,
+        chat_id: ChatId,
+        total: f64,
+        portions: &[(i64, f64)],
+        user_id: Option<i64>
+    ) -> Result<bool, DBError> {
+        let sum = portions.iter().map(|(_, amount)| to_cents(*amount, None)).sum::<i64>();
+        if portions.is_empty() || sum != to_cents(total, None) {
+            return Ok(false);
+        }
+        let dt = Utc::now().timestamp();
+        let mut tx = self.conn.begin().await?;
+        for (category_id, amount) in portions {
+            let owned: bool = sqlx::query_scalar("SELECT count(0) > 0 FROM category WHERE id=? AND chat_id=?")
+                .bind(category_id)
+                .bind(chat_id.0)
+                .fetch_one(&mut *tx)
+                .await?;
+            if !owned {
+                tx.rollback().await?;
+                return Ok(false);
+            }
+            self.insert_cost(&mut tx, *category_id, *amount, None, dt, user_id).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn remove_last_cost(&self, chat_id: ChatId) -> Result<Option<i64>, DBError> {
+        let row = sqlx::query("
+            SELECT s.id 
+            FROM spendings s
+            LEFT JOIN category c ON (s.category_id=c.id)
+            WHERE c.chat_id=? and is_deleted=0
+            ORDER BY s.id DESC LIMIT 1
+            ")
+            .bind(chat_id.0)
+            .fetch_optional(&self.conn)
+            .await?;
+        match row {
+            Some(row) => {
+                let id = row.get::<i64,_>("id");
+                sqlx::query("UPDATE spendings SET is_deleted=1 WHERE id=?")
+                    .bind(id)
+                    .execute(&self.conn)
+                    .await?;
+                Ok(Some(id))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Remembers the chat message a cost was entered with, so a receipt can be sent as a reply to it
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_cost_message(&self, cost_id: i64, message_id: i32) -> Result<(), DBError> {
+        sqlx::query("UPDATE spendings SET message_id=? WHERE id=?")
+            .bind(message_id)
+            .bind(cost_id)
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Attaches a receipt photo to the active cost entered with `message_id`, returns its id
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn attach_photo(&self, chat_id: ChatId, message_id: i32, file_id: String) -> Result<Option<i64>, DBError> {
+        let id = sqlx::query_scalar("
+            UPDATE spendings SET photo_file_id=?
+            WHERE id=(
+                SELECT s.id FROM spendings s
+                JOIN category c ON (s.category_id=c.id)
+                WHERE c.chat_id=? AND s.message_id=? AND s.is_deleted=0
+            )
+            RETURNING id
+            ")
+            .bind(file_id)
+            .bind(chat_id.0)
+            .bind(message_id)
+            .fetch_optional(&self.conn)
+            .await?;
+        Ok(id)
+    }
+
+    /// Earliest active cost of the chat
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn first_cost(&self, chat_id: ChatId) -> Result<Option<CostRow>, DBError> {
+        let mut conn = self.amounts().await?;
+        let cost = sqlx::query("
+            SELECT s.id AS id, s.dt AS dt, c.alias AS alias, c.name AS name, decrypt_amount(s.amount_enc, s.amount_cent) AS amount_cent, s.currency AS currency,
+                s.photo_file_id AS photo_file_id
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=?
+            ORDER BY s.dt, s.id
+            LIMIT 1
+            ")
+            .bind(chat_id.0)
+            .map(|row: SqliteRow| CostRow::from(row))
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(cost)
+    }
+
+    /// Costs of the chat in the period, oldest first
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_costs(
+        &self,
+        chat_id: ChatId,
+        date_from: Option<DateTime<Utc>>,
+        date_to: Option<DateTime<Utc>>
+    ) -> Result<Vec<CostRow>, DBError> {
+        let mut conn = self.amounts().await?;
+        let costs = sqlx::query("
+            SELECT s.id AS id, s.dt AS dt, c.alias AS alias, c.name AS name, decrypt_amount(s.amount_enc, s.amount_cent) AS amount_cent, s.currency AS currency,
+                s.photo_file_id AS photo_file_id
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            ORDER BY s.dt, s.id
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.map(|d| d.timestamp()).unwrap_or(i64::MIN))
+            .bind(date_to.map(|d| d.timestamp()).unwrap_or(i64::MAX))
+            .map(|row: SqliteRow| CostRow::from(row))
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(costs)
+    }
+
+    /// Costs of the same category and amount logged within `window_secs` of each other,
+    /// every cost is paired with the closest earlier match
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn find_duplicate_costs(&self, chat_id: ChatId, window_secs: i64) -> Result<Vec<(CostRow, CostRow)>, DBError> {
+        let mut conn = self.amounts().await?;
+        let pairs = sqlx::query("
+            WITH pair AS (
+                SELECT e.id AS earlier_id, l.id AS later_id, l.dt AS later_dt,
+                    row_number() OVER (PARTITION BY l.id ORDER BY e.dt DESC, e.id DESC) AS closest
+                FROM spendings l
+                JOIN category c ON (l.category_id = c.id)
+                JOIN spendings e ON (
+                    e.category_id = l.category_id AND e.is_deleted=0 AND e.currency IS l.currency
+                    AND e.dt >= l.dt - ? AND (e.dt < l.dt OR (e.dt = l.dt AND e.id < l.id))
+                )
+                WHERE c.chat_id=? AND l.is_deleted=0
+                    AND decrypt_amount(e.amount_enc, e.amount_cent) = decrypt_amount(l.amount_enc, l.amount_cent)
+            )
+            SELECT earlier_id, later_id FROM pair WHERE closest=1 ORDER BY later_dt, later_id
+            ")
+            .bind(window_secs)
+            .bind(chat_id.0)
+            .map(|row: SqliteRow| (row.get::<i64, _>("earlier_id"), row.get::<i64, _>("later_id")))
+            .fetch_all(&mut *conn)
+            .await?;
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+        // only the paired costs are loaded, the ids come from the query above
+        let ids = pairs.iter()
+            .flat_map(|(earlier, later)| [earlier.to_string(), later.to_string()])
+            .collect::<Vec<_>>()
+            .join(",");
+        let costs = sqlx::query(&format!("
+            SELECT s.id AS id, s.dt AS dt, c.alias AS alias, c.name AS name, decrypt_amount(s.amount_enc, s.amount_cent) AS amount_cent, s.currency AS currency,
+                s.photo_file_id AS photo_file_id
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.id IN ({ids})
+            "))
+            .map(|row: SqliteRow| CostRow::from(row))
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|cost| (cost.id, cost))
+            .collect::<HashMap<_, _>>();
+        Ok(pairs.into_iter()
+            .filter_map(|(earlier, later)| Some((costs.get(&earlier)?.clone(), costs.get(&later)?.clone())))
+            .collect())
+    }
+
+    /// Soft-deletes one cost of the chat, `false` when there is no such cost
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn remove_cost(&self, chat_id: ChatId, cost_id: i64) -> Result<bool, DBError> {
+        let removed = sqlx::query("
+            UPDATE spendings SET is_deleted=1
+            WHERE id=? AND is_deleted=0 AND category_id IN (SELECT id FROM category WHERE chat_id=?)
+            ")
+            .bind(cost_id)
+            .bind(chat_id.0)
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(removed > 0)
+    }
+
+    /// Removes up to `n` latest costs at once, returns how many were removed
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn remove_last_n_costs(&self, chat_id: ChatId, n: i64) -> Result<u64, DBError> {
+        let mut tx = self.conn.begin().await?;
+        let removed = sqlx::query("
+            UPDATE spendings SET is_deleted=1
+            WHERE id IN (
+                SELECT s.id
+                FROM spendings s
+                JOIN category c ON (s.category_id=c.id)
+                WHERE c.chat_id=? AND s.is_deleted=0
+                ORDER BY s.id DESC LIMIT ?
+            )
+            ")
+            .bind(chat_id.0)
+            .bind(n.max(0))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat(
+        &self,
+        chat_id: ChatId,
+        date_from: Option<DateTime<Utc>>,
+        date_to: Option<DateTime<Utc>>
+    ) -> Result<Stat, DBError> {
+        self.get_stat_by(chat_id, date_from, date_to, &StatFilter::default()).await
+    }
+
+    /// Sum of active costs in the period, without grouping by category.
+    /// Like every stat it only counts costs in the chat's own currency, see `currency_totals`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn total_amount(
+        &self,
+        chat_id: ChatId,
+        date_from: Option<DateTime<Utc>>,
+        date_to: Option<DateTime<Utc>>
+    ) -> Result<f64, DBError> {
+        let mut conn = self.amounts().await?;
+        let cents: Option<i64> = sqlx::query_scalar("
+            SELECT sum(decrypt_amount(s.amount_enc, s.amount_cent))
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.map(|d| d.timestamp()).unwrap_or(i64::MIN))
+            .bind(date_to.map(|d| d.timestamp()).unwrap_or(i64::MAX))
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(cents.unwrap_or(0) as f64 / 100.0)
+    }
+
+    /// Lifetime total minus the balance the user claims, 0 when they agree to the cent
+    #[instrument(level = "debug", skip(self, expected), err)]
+    pub async fn reconcile(&self, chat_id: ChatId, expected: f64) -> Result<f64, DBError> {
+        let total = self.total_amount(chat_id, None, None).await?;
+        let cents = (total * 100.0).round() as i64 - (expected * 100.0).round() as i64;
+        Ok(cents as f64 / 100.0)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_by(
+        &self,
+        chat_id: ChatId,
+        date_from: Option<DateTime<Utc>>,
+        date_to: Option<DateTime<Utc>>,
+        filter: &StatFilter
+    ) -> Result<Stat, DBError> {
+
+        let mut where_clause = "is_deleted=0 AND s.currency IS NULL AND chat_id=?".to_string();
+
+        if filter.category_id.is_some() {
+            where_clause = format!("{} AND c.id=?", where_clause)
+        }
+
+        if filter.exclude_category_id.is_some() {
+            where_clause = format!("{} AND c.id<>?", where_clause)
+        }
+
+        if filter.user_id.is_some() {
+            where_clause = format!("{} AND s.user_id=?", where_clause)
+        }
+
+        if let Some(d) = date_from {
+            where_clause = format!("{} AND dt >= {}", where_clause, d.timestamp())
         }
 
         if let Some(d) = date_to {
             where_clause = format!("{} AND dt < {}", where_clause, d.timestamp())
         }
 
-        let q = format!("
-            SELECT
-                c.alias AS alias,
-                c.name AS name,
-                count(0) AS n,
-                sum(amount_cent) AS amount
-            FROM spendings s
-            LEFT JOIN category c
-                ON (s.category_id = c.id)
-            WHERE {}
-            GROUP BY alias, name
-        ", where_clause);
+        let q = format!("
+            SELECT
+                c.alias AS alias,
+                c.name AS name,
+                count(0) AS n,
+                sum(decrypt_amount(amount_enc, amount_cent)) AS amount
+            FROM spendings s
+            LEFT JOIN category c
+                ON (s.category_id = c.id)
+            WHERE {}
+            GROUP BY alias, name
+        ", where_clause);
+
+        let mut query = sqlx::query(&q).bind(chat_id.0);
+        if let Some(category_id) = filter.category_id {
+            query = query.bind(category_id);
+        }
+        if let Some(category_id) = filter.exclude_category_id {
+            query = query.bind(category_id);
+        }
+        if let Some(user_id) = filter.user_id {
+            query = query.bind(user_id);
+        }
+
+        let mut conn = self.amounts().await?;
+        let groups = query
+            .map(| row: SqliteRow | StatCategory::from(row))
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(Stat::new(groups))
+    }
+
+    /// Like `get_stat`, but subcategories are counted under their top-level parent
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_grouped(
+        &self,
+        chat_id: ChatId,
+        date_from: Option<DateTime<Utc>>,
+        date_to: Option<DateTime<Utc>>
+    ) -> Result<Stat, DBError> {
+        let mut conn = self.amounts().await?;
+        let groups = sqlx::query("
+            WITH RECURSIVE root(id, root_id) AS (
+                SELECT id, id FROM category WHERE chat_id=? AND parent_id IS NULL
+                UNION ALL
+                SELECT c.id, r.root_id FROM category c JOIN root r ON (c.parent_id = r.id)
+            )
+            SELECT
+                p.alias AS alias,
+                p.name AS name,
+                count(0) AS n,
+                sum(decrypt_amount(s.amount_enc, s.amount_cent)) AS amount
+            FROM spendings s
+            JOIN root r ON (s.category_id = r.id)
+            JOIN category p ON (p.id = r.root_id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND s.dt >= ? AND s.dt < ?
+            GROUP BY p.alias, p.name
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.map(|d| d.timestamp()).unwrap_or(i64::MIN))
+            .bind(date_to.map(|d| d.timestamp()).unwrap_or(i64::MAX))
+            .map(|row: SqliteRow| StatCategory::from(row))
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(Stat::new(groups))
+    }
+
+    /// Every category with its own amount for the period, in list order
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn category_tree(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>
+    ) -> Result<Vec<TreeNode>, DBError> {
+        let mut conn = self.amounts().await?;
+        let rows = sqlx::query("
+            SELECT c.id AS id, c.parent_id AS parent_id, c.name AS name, COALESCE(sum(decrypt_amount(s.amount_enc, s.amount_cent)), 0) AS amount
+            FROM category c
+            LEFT JOIN spendings s ON (s.category_id = c.id AND s.is_deleted=0 AND s.currency IS NULL AND s.dt >= ? AND s.dt < ?)
+            WHERE c.chat_id=?
+            GROUP BY c.id
+            ORDER BY c.sort_order IS NULL, c.sort_order, c.id
+            ")
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .bind(chat_id.0)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.into_iter()
+            .map(|row| (row.get("id"), row.get("parent_id"), row.get("name"), row.get::<i64, _>("amount") as f64 / 100.0))
+            .collect())
+    }
+
+    /// Totals per currency; `None` holds costs logged without one
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn currency_totals(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>
+    ) -> Result<Vec<(Option<String>, f64)>, DBError> {
+        let mut conn = self.amounts().await?;
+        let rows = sqlx::query("
+            SELECT s.currency AS currency, sum(decrypt_amount(s.amount_enc, s.amount_cent)) AS amount
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            GROUP BY s.currency
+            ORDER BY s.currency
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.into_iter()
+            .map(|row| (row.get("currency"), row.get::<i64, _>("amount") as f64 / 100.0))
+            .collect())
+    }
+
+    /// Stat with every cost converted to `base`. `rates` hold the value of one unit of
+    /// each currency in a common unit; costs in currencies without a rate are left out
+    #[instrument(level = "debug", skip(self, rates), err)]
+    pub async fn stat_in_base(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>,
+        base: &str,
+        rates: &HashMap<String, f64>
+    ) -> Result<Stat, DBError> {
+        let mut conn = self.amounts().await?;
+        let rows = sqlx::query("
+            SELECT
+                c.alias AS alias,
+                c.name AS name,
+                s.currency AS currency,
+                count(0) AS n,
+                sum(decrypt_amount(s.amount_enc, s.amount_cent)) AS amount
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            GROUP BY c.alias, c.name, s.currency
+            ORDER BY c.alias
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_all(&mut *conn)
+            .await?;
+
+        let mut items: Vec<StatCategory> = Vec::new();
+        for row in rows {
+            let currency = row.get::<Option<String>, _>("currency").unwrap_or_else(|| base.to_string());
+            let amount = row.get::<i64, _>("amount") as f64 / 100.0;
+            let Some(amount) = convert(amount, &currency, base, rates) else {
+                continue;
+            };
+            let amount = to_cents(amount, Some(base)) as f64 / 100.0;
+            let alias: String = row.get("alias");
+            let n_items: u64 = row.get("n");
+            match items.iter_mut().find(|i| i.category.alias == alias) {
+                Some(item) => {
+                    item.n_items += n_items;
+                    item.amount += amount;
+                },
+                None => items.push(StatCategory {
+                    category: Category::new(alias, row.get("name")),
+                    n_items,
+                    amount
+                })
+            }
+        }
+        Ok(Stat::new(items))
+    }
+
+    /// Day in `tz` with the largest total in the period, the earliest one on a tie
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn peak_day(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>,
+        tz: Tz
+    ) -> Result<Option<(NaiveDate, f64)>, DBError> {
+        // days are bucketed here, sqlite only knows fixed offsets and DST moves them
+        let mut conn = self.amounts().await?;
+        let rows = sqlx::query("
+            SELECT s.dt AS dt, decrypt_amount(s.amount_enc, s.amount_cent) AS amount
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut days: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+        for row in rows {
+            let Some(dt) = DateTime::from_timestamp(row.get("dt"), 0) else {
+                continue;
+            };
+            *days.entry(dt.with_timezone(&tz).date_naive()).or_default() += row.get::<i64, _>("amount");
+        }
+        let peak = days.into_iter().fold(None, |peak: Option<(NaiveDate, i64)>, (day, cents)| match peak {
+            Some((_, max)) if max >= cents => peak,
+            _ => Some((day, cents))
+        });
+        Ok(peak.map(|(day, cents)| (day, cents as f64 / 100.0)))
+    }
+
+    /// Amount spent on every day of the period in `tz`, days without costs included
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn stat_by_day(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>,
+        tz: Tz
+    ) -> Result<Vec<(NaiveDate, f64)>, DBError> {
+        // bucketed here like `peak_day`, sqlite only knows fixed offsets
+        let mut conn = self.amounts().await?;
+        let rows = sqlx::query("
+            SELECT s.dt AS dt, decrypt_amount(s.amount_enc, s.amount_cent) AS amount
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut spent: HashMap<NaiveDate, i64> = HashMap::new();
+        for row in rows {
+            let Some(dt) = DateTime::from_timestamp(row.get("dt"), 0) else {
+                continue;
+            };
+            *spent.entry(dt.with_timezone(&tz).date_naive()).or_default() += row.get::<i64, _>("amount");
+        }
+        let last = (date_to - chrono::Duration::seconds(1)).with_timezone(&tz).date_naive();
+        Ok(date_from.with_timezone(&tz).date_naive()
+            .iter_days()
+            .take_while(|day| *day <= last)
+            .map(|day| (day, spent.get(&day).copied().unwrap_or(0) as f64 / 100.0))
+            .collect())
+    }
+
+    /// Spendings per category and month, months as `YYYYMM` numbers
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn stat_matrix(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>
+    ) -> Result<Vec<(String, u32, f64)>, DBError> {
+        let mut conn = self.amounts().await?;
+        let cells = sqlx::query("
+            SELECT c.name AS name, CAST(strftime('%Y%m', s.dt, 'unixepoch') AS INTEGER) AS month,
+                sum(decrypt_amount(s.amount_enc, s.amount_cent)) AS amount
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            GROUP BY c.id, month
+            ORDER BY c.name, month
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .map(|row: SqliteRow| (
+                row.get::<String, _>("name"),
+                row.get::<i64, _>("month") as u32,
+                row.get::<i64, _>("amount") as f64 / 100.0
+            ))
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(cells)
+    }
+
+    /// Number of costs per amount range split at ascending `edges`: below the first edge,
+    /// between each pair and from the last edge on, so one more count than edges
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn amount_histogram(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>,
+        edges: &[f64]
+    ) -> Result<Vec<u64>, DBError> {
+        let mut conn = self.amounts().await?;
+        let amounts: Vec<i64> = sqlx::query_scalar("
+            SELECT decrypt_amount(s.amount_enc, s.amount_cent)
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND s.currency IS NULL AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut counts = vec![0; edges.len() + 1];
+        for cents in amounts {
+            let amount = cents as f64 / 100.0;
+            counts[edges.iter().take_while(|edge| amount >= **edge).count()] += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Active categories with spendings last month and none so far this month, months as seen in `tz`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn lapsed_categories(&self, chat_id: ChatId, tz: Tz) -> Result<Vec<CategoryRow>, DBError> {
+        let (date_from, date_to) = local_month_bounds(Utc::now(), tz);
+        let (previous_from, _) = local_month_bounds(date_from - chrono::Duration::days(1), tz);
+        let categories = sqlx::query("
+            SELECT id, chat_id, alias, name, is_favorite FROM category c
+            WHERE chat_id=? AND is_archived=0
+                AND EXISTS (
+                    SELECT 1 FROM spendings s
+                    WHERE s.category_id=c.id AND s.is_deleted=0 AND s.dt >= ? AND s.dt < ?
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM spendings s
+                    WHERE s.category_id=c.id AND s.is_deleted=0 AND s.dt >= ? AND s.dt < ?
+                )
+            ORDER BY sort_order IS NULL, sort_order, id
+            ")
+            .bind(chat_id.0)
+            .bind(previous_from.timestamp())
+            .bind(date_from.timestamp())
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .map(|row: SqliteRow| CategoryRow::from(row))
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(categories)
+    }
+
+    /// Category with the most costs in the period and their count, ties go by name
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn most_frequent_category(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>
+    ) -> Result<Option<(CategoryRow, i64)>, DBError> {
+        let row = sqlx::query("
+            SELECT c.id AS id, c.chat_id AS chat_id, c.alias AS alias, c.name AS name, c.is_favorite AS is_favorite, count(0) AS n
+            FROM spendings s
+            JOIN category c ON (s.category_id = c.id)
+            WHERE s.is_deleted=0 AND c.chat_id=? AND s.dt >= ? AND s.dt < ?
+            GROUP BY c.id
+            ORDER BY n DESC, c.name, c.id
+            LIMIT 1
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .fetch_optional(&self.conn)
+            .await?;
+        Ok(row.map(|row| {
+            let n = row.get("n");
+            (CategoryRow::from(row), n)
+        }))
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn clear_costs(&self, chat_id: ChatId) -> Result<u64, DBError> {
+        let deleted = sqlx::query("
+            DELETE FROM spendings
+            WHERE category_id IN (SELECT id FROM category WHERE chat_id=?)
+            ")
+            .bind(chat_id.0)
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(deleted)
+    }
+
+    /// Moves every cost of the chat by `hours`, e.g. to re-read times logged as UTC
+    /// in the local timezone. Returns how many costs were moved
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn shift_dates(&self, chat_id: ChatId, hours: i64) -> Result<u64, DBError> {
+        let mut tx = self.conn.begin().await?;
+        let shifted = sqlx::query("
+            UPDATE spendings SET dt = dt + ?
+            WHERE category_id IN (SELECT id FROM category WHERE chat_id=?)
+            ")
+            .bind(hours * 3600)
+            .bind(chat_id.0)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(shifted)
+    }
+
+    /// Deletes the chat's costs with `from <= dt < to`, returns how many
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn delete_costs_in_range(
+        &self,
+        chat_id: ChatId,
+        date_from: DateTime<Utc>,
+        date_to: DateTime<Utc>
+    ) -> Result<u64, DBError> {
+        let deleted = sqlx::query("
+            DELETE FROM spendings
+            WHERE category_id IN (SELECT id FROM category WHERE chat_id=?) AND dt >= ? AND dt < ?
+            ")
+            .bind(chat_id.0)
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(deleted)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn log_action(&self, chat_id: ChatId, action: &str, dt: DateTime<Utc>) -> Result<(), DBError> {
+        sqlx::query("INSERT INTO action_log (chat_id, dt, action) VALUES (?, ?, ?)")
+            .bind(chat_id.0)
+            .bind(dt.timestamp())
+            .bind(action)
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Last `limit` logged actions, newest first
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_actions(&self, chat_id: ChatId, limit: i64) -> Result<Vec<(DateTime<Utc>, String)>, DBError> {
+        let actions = sqlx::query("SELECT dt, action FROM action_log WHERE chat_id=? ORDER BY dt DESC, id DESC LIMIT ?")
+            .bind(chat_id.0)
+            .bind(limit)
+            .map(|row: SqliteRow| (
+                DateTime::from_timestamp(row.get("dt"), 0).unwrap_or_default(),
+                row.get::<String, _>("action")
+            ))
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(actions)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn global_counts(&self) -> Result<GlobalCounts, DBError> {
+        let row = sqlx::query("
+            SELECT
+                (SELECT count(DISTINCT chat_id) FROM category) AS chats,
+                (SELECT count(0) FROM category) AS categories,
+                (SELECT count(0) FROM spendings WHERE is_deleted=0) AS spendings
+            ")
+            .fetch_one(&self.conn)
+            .await?;
+        Ok(GlobalCounts {
+            chats: row.get("chats"),
+            categories: row.get("categories"),
+            spendings: row.get("spendings")
+        })
+    }
+
+    /// Costs with no category row at all. They can't be attributed to a chat,
+    /// so the count covers every chat
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn orphaned_costs(&self) -> Result<i64, DBError> {
+        let n = sqlx::query("
+            SELECT count(0) AS n FROM spendings
+            WHERE category_id IS NULL OR category_id NOT IN (SELECT id FROM category)
+            ")
+            .fetch_one(&self.conn)
+            .await?
+            .get::<i64, _>("n");
+        Ok(n)
+    }
+
+    /// Problems in the chat's own data, `orphaned_costs` is left out
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn integrity_check(&self, chat_id: ChatId) -> Result<IntegrityReport, DBError> {
+        let duplicate_aliases = sqlx::query("
+            SELECT count(0) AS n FROM (
+                SELECT alias FROM (
+                    SELECT alias FROM category WHERE chat_id=?
+                    UNION ALL
+                    SELECT alias FROM category_alias WHERE chat_id=?
+                )
+                GROUP BY alias HAVING count(0) > 1
+            )
+            ")
+            .bind(chat_id.0)
+            .bind(chat_id.0)
+            .fetch_one(&self.conn)
+            .await?
+            .get::<i64, _>("n");
+        let mut conn = self.amounts().await?;
+        let bad_amounts = sqlx::query("
+            SELECT count(0) AS n
+            FROM spendings s
+            JOIN category c ON (s.category_id=c.id)
+            WHERE c.chat_id=? AND (typeof(decrypt_amount(s.amount_enc, s.amount_cent)) <> 'integer'
+                OR decrypt_amount(s.amount_enc, s.amount_cent) < 0)
+            ")
+            .bind(chat_id.0)
+            .fetch_one(&mut *conn)
+            .await?
+            .get::<i64, _>("n");
+        Ok(IntegrityReport { orphaned_costs: None, duplicate_aliases, bad_amounts })
+    }
+
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn add_recurring(
+        &self,
+        chat_id: ChatId,
+        category_id: i64,
+        amount: f64,
+        frequency: Frequency
+    ) -> Result<i64, DBError> {
+        let (day_of_month, day_of_week) = match frequency {
+            Frequency::Monthly { day } => (Some(day), None),
+            Frequency::Weekly { .. } => (None, Some(frequency.day()))
+        };
+        let id = sqlx::query("
+            INSERT INTO recurring (chat_id, category_id, amount_cent, frequency, day_of_month, day_of_week)
+            VALUES (?, ?, ?, ?, ?, ?) RETURNING id
+            ")
+            .bind(chat_id.0)
+            .bind(category_id)
+            .bind(to_cents(amount, None))
+            .bind(frequency.kind())
+            .bind(day_of_month)
+            .bind(day_of_week)
+            .fetch_one(&self.conn)
+            .await?
+            .get::<i64, _>("id");
+        Ok(id)
+    }
+
+    /// Recurring costs that should be posted on `now`'s date and weren't yet
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn due_recurring(&self, now: DateTime<Utc>) -> Result<Vec<RecurringRow>, DBError> {
+        let today = now.date_naive();
+        let rows = sqlx::query(&format!("{RECURRING_SELECT} ORDER BY r.id"))
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(rows.into_iter()
+            .filter_map(RecurringRow::from_row)
+            .filter(|r| r.frequency.is_due_on(today) && r.last_run != Some(today))
+            .collect())
+    }
+
+    /// Posts every due recurring cost and marks it as done for today.
+    /// Occurrences flagged with `skip_next_recurring` are consumed without posting
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn materialize_recurring(&self, now: DateTime<Utc>) -> Result<Vec<RecurringRow>, DBError> {
+        let due = self.due_recurring(now).await?;
+        let today = now.date_naive().format("%Y-%m-%d").to_string();
+        let mut tx = self.conn.begin().await?;
+        for r in due.iter().filter(|r| !r.skip_next) {
+            self.insert_cost(&mut tx, r.category_id, r.amount, None, now.timestamp(), None).await?;
+        }
+        for r in due.iter() {
+            sqlx::query("UPDATE recurring SET last_run=?, skip_next=0 WHERE id=?")
+                .bind(&today)
+                .bind(r.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(due.into_iter().filter(|r| !r.skip_next).collect())
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn list_recurring(&self, chat_id: ChatId) -> Result<Vec<RecurringRow>, DBError> {
+        let rows = sqlx::query(&format!("{RECURRING_SELECT} WHERE r.chat_id=? ORDER BY r.id"))
+            .bind(chat_id.0)
+            .fetch_all(&self.conn)
+            .await?;
+        Ok(rows.into_iter().filter_map(RecurringRow::from_row).collect())
+    }
+
+    /// What the chat's recurring costs take in an average month
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn recurring_monthly_total(&self, chat_id: ChatId) -> Result<f64, DBError> {
+        let recurring = self.list_recurring(chat_id).await?;
+        Ok(recurring.iter().map(|r| r.amount * r.frequency.per_month()).sum())
+    }
+
+    /// Returns `false` when the chat has no such recurring cost
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn delete_recurring(&self, chat_id: ChatId, recurring_id: i64) -> Result<bool, DBError> {
+        let deleted = sqlx::query("DELETE FROM recurring WHERE id=? AND chat_id=?")
+            .bind(recurring_id)
+            .bind(chat_id.0)
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    /// Returns `false` when the chat has no such recurring cost
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn skip_next_recurring(&self, chat_id: ChatId, recurring_id: i64) -> Result<bool, DBError> {
+        let updated = sqlx::query("UPDATE recurring SET skip_next=1 WHERE id=? AND chat_id=?")
+            .bind(recurring_id)
+            .bind(chat_id.0)
+            .execute(&self.conn)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn set_budget(&self, category_id: i64, amount: f64) -> Result<(), DBError> {
+        sqlx::query("
+            INSERT INTO budget (category_id, amount_cent, percent) VALUES (?, ?, NULL)
+            ON CONFLICT(category_id) DO UPDATE SET amount_cent=excluded.amount_cent, percent=NULL
+            ")
+            .bind(category_id)
+            .bind(to_cents(amount, None))
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Budget as a share of the monthly income, the limit follows income changes
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn set_budget_percent(&self, category_id: i64, percent: f64) -> Result<(), DBError> {
+        sqlx::query("
+            INSERT INTO budget (category_id, amount_cent, percent) VALUES (?, 0, ?)
+            ON CONFLICT(category_id) DO UPDATE SET amount_cent=0, percent=excluded.percent
+            ")
+            .bind(category_id)
+            .bind(percent)
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the chat's savings goal, counting from `started`
+    #[instrument(level = "debug", skip(self, amount), err)]
+    pub async fn set_goal(
+        &self,
+        chat_id: ChatId,
+        amount: f64,
+        started: DateTime<Utc>,
+        deadline: DateTime<Utc>
+    ) -> Result<(), DBError> {
+        sqlx::query("
+            INSERT INTO goal (chat_id, amount_cent, started, deadline) VALUES (?, ?, ?, ?)
+            ON CONFLICT(chat_id) DO UPDATE SET
+                amount_cent=excluded.amount_cent,
+                started=excluded.started,
+                deadline=excluded.deadline
+            ")
+            .bind(chat_id.0)
+            .bind(to_cents(amount, None))
+            .bind(started.timestamp())
+            .bind(deadline.timestamp())
+            .execute(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_goal(&self, chat_id: ChatId) -> Result<Option<GoalRow>, DBError> {
+        let row = sqlx::query("SELECT amount_cent, started, deadline FROM goal WHERE chat_id=?")
+            .bind(chat_id.0)
+            .fetch_optional(&self.conn)
+            .await?;
+        Ok(row.and_then(|row| Some(GoalRow {
+            amount: row.get::<i64, _>("amount_cent") as f64 / 100.0,
+            started: DateTime::from_timestamp(row.get("started"), 0)?,
+            deadline: DateTime::from_timestamp(row.get("deadline"), 0)?
+        })))
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_budgets_this_month(&self, chat_id: ChatId) -> Result<Vec<BudgetRow>, DBError> {
+        let mut conn = self.amounts().await?;
+        let settings = Self::read_settings(&mut conn, chat_id).await?;
+        let (date_from, date_to) = local_cycle_bounds(Utc::now(), settings.fiscal_start_day, settings.timezone);
+        let budgets = sqlx::query("
+            SELECT
+                c.alias AS alias,
+                c.name AS name,
+                b.amount_cent AS limit_cent,
+                b.percent AS percent,
+                COALESCE(sum(decrypt_amount(s.amount_enc, s.amount_cent)), 0) AS spent_cent
+            FROM budget b
+            JOIN category c
+                ON (b.category_id = c.id)
+            LEFT JOIN spendings s
+                ON (s.category_id = c.id AND s.is_deleted=0 AND s.currency IS NULL AND s.dt >= ? AND s.dt < ?)
+            WHERE c.chat_id=?
+            GROUP BY c.id
+            ORDER BY c.id
+            ")
+            .bind(date_from.timestamp())
+            .bind(date_to.timestamp())
+            .bind(chat_id.0)
+            .map(| row: SqliteRow | BudgetRow::from(row))
+            .fetch_all(&mut *conn)
+            .await?;
+        let income = settings.monthly_income();
+        Ok(budgets.into_iter().map(|mut b| {
+            if let Some(percent) = b.percent {
+                b.limit = income * percent / 100.0;
+            }
+            b
+        }).collect())
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_this_month(&self, chat_id: ChatId) -> Result<Stat, DBError> {
+        let (date_from, date_to) = month_bounds(Utc::now());
+        self.get_stat(chat_id, Some(date_from), Some(date_to)).await
+    }
+
+    /// Category total over the last `months` months divided by the days in that window,
+    /// 0 when nothing was spent
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn category_daily_average(&self, chat_id: ChatId, category_id: i64, months: u32) -> Result<f64, DBError> {
+        let date_to = Utc::now();
+        let date_from = date_to.checked_sub_months(Months::new(months)).unwrap_or(date_to);
+        let days = (date_to - date_from).num_days();
+        if days == 0 {
+            return Ok(0.0);
+        }
+        let filter = StatFilter::default().category(category_id);
+        let total = self.get_stat_by(chat_id, Some(date_from), Some(date_to), &filter).await?.amount();
+        Ok(total / days as f64)
+    }
+
+    /// Monthly totals of a category for the last `months` months including this one, oldest first
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn category_trend(&self, chat_id: ChatId, category_id: i64, months: u32) -> Result<Vec<f64>, DBError> {
+        let (this_month, _) = month_bounds(Utc::now());
+        let filter = StatFilter::default().category(category_id);
+        let mut totals = Vec::with_capacity(months as usize);
+        for back in (0..months).rev() {
+            let month = this_month.checked_sub_months(Months::new(back)).unwrap_or(this_month);
+            let (date_from, date_to) = month_bounds(month);
+            totals.push(self.get_stat_by(chat_id, Some(date_from), Some(date_to), &filter).await?.amount());
+        }
+        Ok(totals)
+    }
+
+    /// Average monthly spend over the `window` full months before the month of `now`.
+    /// Months without costs count as zero
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn moving_average_monthly(&self, chat_id: ChatId, window: u32, now: DateTime<Utc>) -> Result<f64, DBError> {
+        if window == 0 {
+            return Ok(0.0);
+        }
+        let (this_month, _) = month_bounds(now);
+        let date_from = this_month.checked_sub_months(Months::new(window)).unwrap_or(this_month);
+        let total = self.total_amount(chat_id, Some(date_from), Some(this_month)).await?;
+        Ok(total / window as f64)
+    }
+
+    /// Stat of the budget cycle containing now in `tz`, see `cycle_bounds`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_this_cycle(&self, chat_id: ChatId, start_day: u32, tz: Tz) -> Result<Stat, DBError> {
+        let (date_from, date_to) = local_cycle_bounds(Utc::now(), start_day, tz);
+        self.get_stat(chat_id, Some(date_from), Some(date_to)).await
+    }
+
+    /// Bounds of the chat's current budget cycle, what "this month" stands for in reports
+    async fn this_cycle(&self, chat_id: ChatId) -> Result<(DateTime<Utc>, DateTime<Utc>), DBError> {
+        let settings = self.get_settings(chat_id).await?;
+        Ok(local_cycle_bounds(Utc::now(), settings.fiscal_start_day, settings.timezone))
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_category_stat_this_month(&self, chat_id: ChatId, category_id: i64) -> Result<Stat, DBError> {
+        let (date_from, date_to) = self.this_cycle(chat_id).await?;
+        let filter = StatFilter::default().category(category_id);
+        self.get_stat_by(chat_id, Some(date_from), Some(date_to), &filter).await
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_previous_month(&self, chat_id: ChatId) -> Result<Stat, DBError> {
+        let (date_from, date_to) = previous_month_bounds(Utc::now());
+        self.get_stat(chat_id, Some(date_from), Some(date_to)).await
+    }
+
+    /// Per-category change from the budget cycle before `now` to the cycle of `now`
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn compare_months(&self, chat_id: ChatId, now: DateTime<Utc>) -> Result<StatDiff, DBError> {
+        let settings = self.get_settings(chat_id).await?;
+        let (date_from, date_to) = local_cycle_bounds(now, settings.fiscal_start_day, settings.timezone);
+        let (previous_from, previous_to) = local_cycle_bounds(
+            date_from - chrono::Duration::seconds(1), settings.fiscal_start_day, settings.timezone
+        );
+        let previous = self.get_stat(chat_id, Some(previous_from), Some(previous_to)).await?;
+        let current = self.get_stat(chat_id, Some(date_from), Some(date_to)).await?;
+        Ok(previous.diff(&current))
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_stat_this_month_except(&self, chat_id: ChatId, category_id: i64) -> Result<Stat, DBError> {
+        let (date_from, date_to) = self.this_cycle(chat_id).await?;
+        let filter = StatFilter::default().exclude_category(category_id);
+        self.get_stat_by(chat_id, Some(date_from), Some(date_to), &filter).await
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    pub async fn get_category_stat_this_week(&self, chat_id: ChatId, category_id: i64) -> Result<Stat, DBError> {
+        let (date_from, date_to) = week_bounds(Utc::now());
+        let filter = StatFilter::default().category(category_id);
+        self.get_stat_by(chat_id, Some(date_from), Some(date_to), &filter).await
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_connect() {
+        let db = DB::from_memory().await;
+        assert!(db.is_ok())
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let db = DB::from_memory().await.unwrap();
+        assert!(db.ping().await.is_ok())
+    }
+
+    #[tokio::test]
+    async fn test_create_category() {
+        let db = DB::from_memory().await.unwrap();
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 0);
+        assert!(db.create_category(ChatId(0), "t".to_string(), "test".to_string()).await.is_ok());
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_category() {
+        let db = DB::from_memory().await.unwrap();
+        let id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await;
+        assert!(id.is_ok());
+    }
+
+    #[tokio::test]
+    #[allow(clippy::assertions_on_constants)]
+    async fn test_get_category_alias() {
+        let db = DB::from_memory().await.unwrap();
+        let _ = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await;
+        let _ = db.create_category(ChatId(0), "t2".to_string(), "test2".to_string()).await;
+
+        if let Some(cat) = db.get_category_by_alias(ChatId(0), "t2".to_string()).await.unwrap() {
+            assert_eq!(cat.category.name, "test2")
+        }
+
+        match db.get_category_by_alias(ChatId(0), "t3".to_string()).await {
+            Ok(None) => assert!(true),
+            Ok(Some(_)) => assert!(false),
+            Err(_) => assert!(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_cost() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        assert!(db.create_cost(cat_id, 123.41, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+
+        let cat_id = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+        
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        assert_eq!(stat.n_items(), 6);
+        assert_eq!(stat.amount(), 1200.0);
+        assert_eq!(stat.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stat_this_month() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+
+        let cat_id = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+        
+        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(stat.n_items(), 6);
+        assert_eq!(stat.amount(), 1200.0);
+        assert_eq!(stat.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stat_float() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 21.5, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 23.3, None).await.is_ok();
+
+        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(stat.n_items(), 2);
+        assert_eq!(stat.amount(), (21.5 + 23.3));
+    }
+
+    #[tokio::test]
+    async fn test_cost_remove() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+
+        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(stat.n_items(), 2);
+        assert!(db.remove_last_cost(ChatId(0)).await.is_ok());
+
+        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(stat.n_items(), 1);
+        assert_eq!(stat.amount(), 100.0);
+        assert!(db.remove_last_cost(ChatId(0)).await.unwrap().is_some());
+        assert!(db.remove_last_cost(ChatId(0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_category_stat_this_week() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 300.0, Some(Utc::now() - chrono::Duration::days(8))).await.is_ok();
+
+        let cat_id = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+
+        let t1 = db.find_category_by_alias(ChatId(0), "t1".to_string()).await.unwrap().unwrap();
+        let stat = db.get_category_stat_this_week(ChatId(0), t1.id).await.unwrap();
+        assert_eq!(stat.n_items(), 2);
+        assert_eq!(stat.amount(), 300.0);
+        assert_eq!(stat.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_budgets_this_month() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 30.0, None).await.is_ok();
+        db.set_budget(cat_id, 100.0).await.unwrap();
+        db.set_budget(cat_id, 250.0).await.unwrap();
+        let _ = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
+
+        let budgets = db.get_budgets_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(budgets.len(), 1);
+        assert_eq!(budgets[0].limit, 250.0);
+        assert_eq!(budgets[0].spent, 130.0);
+    }
+
+    #[tokio::test]
+    async fn test_clear_costs() {
+        let db = DB::from_memory().await.unwrap();
+
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let other_id = db.create_category(ChatId(1), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(other_id, 100.0, None).await.is_ok();
+
+        assert_eq!(db.clear_costs(ChatId(0)).await.unwrap(), 2);
+        assert!(db.get_stat(ChatId(0), None, None).await.unwrap().is_empty());
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 1);
+        assert_eq!(db.get_stat(ChatId(1), None, None).await.unwrap().n_items(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_find_similar_categories() {
+        let db = DB::from_memory().await.unwrap();
+        let _ = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await;
+        let _ = db.create_category(ChatId(0), "r".to_string(), "rent".to_string()).await;
+
+        let similar = db.find_similar_categories(ChatId(0), "Food".to_string()).await.unwrap();
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar[0].category.alias, "f");
+        assert!(db.find_similar_categories(ChatId(1), "Food".to_string()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settings() {
+        let db = DB::from_memory().await.unwrap();
+        assert!(!db.get_settings(ChatId(0)).await.unwrap().auto_uncategorized);
+        db.set_setting(ChatId(0), Setting::AutoUncategorized, "1".to_string()).await.unwrap();
+        assert!(db.get_settings(ChatId(0)).await.unwrap().auto_uncategorized);
+        assert!(!db.get_settings(ChatId(1)).await.unwrap().auto_uncategorized);
+        db.set_setting(ChatId(0), Setting::AutoUncategorized, "0".to_string()).await.unwrap();
+        assert!(!db.get_settings(ChatId(0)).await.unwrap().auto_uncategorized);
+
+        db.set_setting(ChatId(1), Setting::DigestWeekday, "Mon".to_string()).await.unwrap();
+        db.set_setting(ChatId(2), Setting::DigestWeekday, "".to_string()).await.unwrap();
+        assert_eq!(db.chats_with_setting(Setting::DigestWeekday).await.unwrap(), vec![ChatId(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_secondary_alias() {
+        let db = DB::from_memory().await.unwrap();
+        let food_id = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let _ = db.create_category(ChatId(0), "rent".to_string(), "Rent".to_string()).await.unwrap();
+        db.add_alias(ChatId(0), food_id, "eat".to_string()).await.unwrap();
+
+        let cat = db.get_category_by_alias(ChatId(0), "eat".to_string()).await.unwrap().unwrap();
+        assert_eq!(cat.id, food_id);
+        db.create_cost(cat.id, 12.5, None).await.unwrap();
+
+        let filter = StatFilter::default().category(food_id);
+        let stat = db.get_stat_by(ChatId(0), None, None, &filter).await.unwrap();
+        assert_eq!(stat.amount(), 12.5);
+        assert!(db.get_category_by_alias(ChatId(1), "eat".to_string()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_category_stat_by_secondary_alias() {
+        let db = DB::from_memory().await.unwrap();
+        let food_id = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let rent_id = db.create_category(ChatId(0), "rent".to_string(), "Rent".to_string()).await.unwrap();
+        db.add_alias(ChatId(0), food_id, "eat".to_string()).await.unwrap();
+        db.create_cost(food_id, 12.5, None).await.unwrap();
+        db.create_cost(rent_id, 300.0, None).await.unwrap();
+
+        let cat = db.find_category_by_alias(ChatId(0), "eat".to_string()).await.unwrap().unwrap();
+        let stat = db.get_category_stat_this_month(ChatId(0), cat.id).await.unwrap();
+        assert_eq!(stat.amount(), 12.5);
+        let stat = db.get_stat_this_month_except(ChatId(0), cat.id).await.unwrap();
+        assert_eq!(stat.amount(), 300.0);
+    }
+
+    #[tokio::test]
+    async fn test_integrity_check() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        assert!(db.integrity_check(ChatId(0)).await.unwrap().is_ok());
+
+        sqlx::query("INSERT INTO spendings (dt, category_id, amount_cent) VALUES (0, 999, 100)")
+            .execute(&db.conn)
+            .await
+            .unwrap();
+        assert_eq!(db.orphaned_costs().await.unwrap(), 1);
+        let report = db.integrity_check(ChatId(0)).await.unwrap();
+        assert_eq!(report.orphaned_costs, None);
+        assert_eq!(report.duplicate_aliases, 0);
+        assert_eq!(report.bad_amounts, 0);
+        assert!(!report.to_string().contains("Orphaned"));
+    }
+
+    #[tokio::test]
+    async fn test_stat_diff() {
+        let db = DB::from_memory().await.unwrap();
+        let day = |d: u32| Some(Utc.with_ymd_and_hms(2025, 1, d, 12, 0, 0).unwrap());
+
+        let food = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let rent = db.create_category(ChatId(0), "rent".to_string(), "Rent".to_string()).await.unwrap();
+        let fun = db.create_category(ChatId(0), "fun".to_string(), "Fun".to_string()).await.unwrap();
+        db.create_cost(food, 100.0, day(1)).await.unwrap();
+        db.create_cost(rent, 500.0, day(2)).await.unwrap();
+        db.create_cost(food, 150.0, day(11)).await.unwrap();
+        db.create_cost(fun, 30.0, day(12)).await.unwrap();
+
+        let first = db.get_stat(ChatId(0), day(1), day(10)).await.unwrap();
+        let second = db.get_stat(ChatId(0), day(10), day(20)).await.unwrap();
+        let diff = first.diff(&second);
+
+        assert_eq!(diff.delta(), 180.0 - 600.0);
+        let by_alias = |alias: &str| diff.categories.iter().find(|c| c.category.alias == alias).unwrap();
+        assert_eq!(by_alias("food").delta(), 50.0);
+        assert_eq!((by_alias("rent").before, by_alias("rent").after), (Some(500.0), None));
+        assert_eq!((by_alias("fun").before, by_alias("fun").after), (None, Some(30.0)));
+    }
+
+    #[tokio::test]
+    async fn test_copy_categories() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let _ = db.create_category(ChatId(0), "rent".to_string(), "Rent".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+        let _ = db.create_category(ChatId(1), "rent".to_string(), "Flat".to_string()).await.unwrap();
+        let _ = db.create_category(ChatId(0), "old".to_string(), "Old".to_string()).await.unwrap();
+        db.archive_category(ChatId(0), "old".to_string()).await.unwrap();
+
+        assert_eq!(db.copy_categories(ChatId(0), ChatId(1)).await.unwrap(), 1);
+        let cats = db.get_categories(ChatId(1)).await.unwrap();
+        assert_eq!(cats.len(), 2);
+        assert_eq!(cats[0].category.name, "Flat");
+        assert!(db.get_stat(ChatId(1), None, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_archive_category() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let _ = db.create_category(ChatId(0), "t2".to_string(), "test2".to_string()).await.unwrap();
+        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
+
+        assert!(db.archive_category(ChatId(0), "t1".to_string()).await.unwrap());
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 1);
+        assert!(db.get_category_by_alias(ChatId(0), "t1".to_string()).await.unwrap().is_none());
+        assert!(db.find_category_by_alias(ChatId(0), "t1".to_string()).await.unwrap().is_some());
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().amount(), 100.0);
+
+        assert!(db.unarchive_category(ChatId(0), "t1".to_string()).await.unwrap());
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 2);
+        assert!(!db.archive_category(ChatId(0), "t3".to_string()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stat_exclude_alias() {
+        let db = DB::from_memory().await.unwrap();
+        let rent = db.create_category(ChatId(0), "rent".to_string(), "Rent".to_string()).await.unwrap();
+        let food = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let _ = db.create_cost(rent, 1000.0, None).await.is_ok();
+        let _ = db.create_cost(food, 100.0, None).await.is_ok();
+        let _ = db.create_cost(food, 50.0, None).await.is_ok();
+
+        let stat = db.get_stat_this_month_except(ChatId(0), rent).await.unwrap();
+        assert_eq!(stat.len(), 1);
+        assert_eq!(stat.n_items(), 2);
+        assert_eq!(stat.amount(), 150.0);
+    }
+
+    #[tokio::test]
+    async fn test_retire_category() {
+        let db = DB::from_memory().await.unwrap();
+        let eat = db.create_category(ChatId(0), "eat".to_string(), "Eat".to_string()).await.unwrap();
+        let food = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        let _ = db.create_cost(eat, 100.0, None).await.is_ok();
+        let _ = db.create_cost(eat, 50.0, None).await.is_ok();
+        let _ = db.create_cost(food, 10.0, None).await.is_ok();
+
+        assert_eq!(db.retire_category(eat, food).await.unwrap(), 2);
+        assert!(db.find_category_by_alias(ChatId(0), "eat".to_string()).await.unwrap().is_none());
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        assert_eq!(stat.len(), 1);
+        assert_eq!(stat.n_items(), 3);
+        assert_eq!(stat.amount(), 160.0);
+    }
+
+    #[tokio::test]
+    async fn test_retire_category_moves_recurring() {
+        let db = DB::from_memory().await.unwrap();
+        let eat = db.create_category(ChatId(0), "eat".to_string(), "Eat".to_string()).await.unwrap();
+        let food = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        db.add_recurring(ChatId(0), eat, 20.0, Frequency::Monthly { day: 5 }).await.unwrap();
+
+        db.retire_category(eat, food).await.unwrap();
+        let recurring = db.list_recurring(ChatId(0)).await.unwrap();
+        assert_eq!(recurring.len(), 1);
+        assert_eq!(recurring[0].category_id, food);
+
+        let march = Utc.with_ymd_and_hms(2025, 3, 5, 9, 0, 0).unwrap();
+        assert_eq!(db.materialize_recurring(march).await.unwrap().len(), 1);
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().amount(), 20.0);
+    }
+
+    #[tokio::test]
+    async fn test_weekly_recurring() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let weekly = Frequency::parse("weekly", 3).unwrap();
+        db.add_recurring(ChatId(0), cat_id, 20.0, weekly).await.unwrap();
+
+        // 2025-02-05 is a Wednesday
+        let tuesday = Utc.with_ymd_and_hms(2025, 2, 4, 9, 0, 0).unwrap();
+        let wednesday = Utc.with_ymd_and_hms(2025, 2, 5, 9, 0, 0).unwrap();
+        assert!(db.due_recurring(tuesday).await.unwrap().is_empty());
+        assert_eq!(db.materialize_recurring(wednesday).await.unwrap().len(), 1);
+        assert!(db.materialize_recurring(wednesday).await.unwrap().is_empty());
+
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        assert_eq!(stat.n_items(), 1);
+        assert_eq!(stat.amount(), 20.0);
+    }
+
+    #[tokio::test]
+    async fn test_skip_next_recurring() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let id = db.add_recurring(ChatId(0), cat_id, 9.99, Frequency::Monthly { day: 5 }).await.unwrap();
+
+        assert!(!db.skip_next_recurring(ChatId(1), id).await.unwrap());
+        assert!(db.skip_next_recurring(ChatId(0), id).await.unwrap());
+
+        let february = Utc.with_ymd_and_hms(2025, 2, 5, 9, 0, 0).unwrap();
+        let march = Utc.with_ymd_and_hms(2025, 3, 5, 9, 0, 0).unwrap();
+        assert!(db.materialize_recurring(february).await.unwrap().is_empty());
+        assert!(db.get_stat(ChatId(0), None, None).await.unwrap().is_empty());
+        assert_eq!(db.materialize_recurring(march).await.unwrap().len(), 1);
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().amount(), 9.99);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete_recurring() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let rent = db.add_recurring(ChatId(0), cat_id, 500.0, Frequency::Monthly { day: 1 }).await.unwrap();
+        let gym = db.add_recurring(ChatId(0), cat_id, 15.0, Frequency::Weekly { weekday: chrono::Weekday::Tue }).await.unwrap();
+
+        let listed = db.list_recurring(ChatId(0)).await.unwrap();
+        assert_eq!(listed.iter().map(|r| r.id).collect::<Vec<_>>(), vec![rent, gym]);
+        assert!(db.list_recurring(ChatId(1)).await.unwrap().is_empty());
+
+        assert!(!db.delete_recurring(ChatId(1), rent).await.unwrap());
+        assert!(db.delete_recurring(ChatId(0), rent).await.unwrap());
+        let listed = db.list_recurring(ChatId(0)).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, gym);
+        assert_eq!(listed[0].frequency, Frequency::Weekly { weekday: chrono::Weekday::Tue });
+    }
+
+    #[tokio::test]
+    async fn test_stat_in_base() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_cost_in(cat_id, 10.0, Some("USD"), None).await.unwrap();
+        db.create_cost_in(cat_id, 20.0, Some("EUR"), None).await.unwrap();
+        db.create_cost_in(cat_id, 5.0, Some("GBP"), None).await.unwrap();
+
+        let rates = HashMap::from([("USD".to_string(), 1.0), ("EUR".to_string(), 1.25)]);
+        let (df, dt) = month_bounds(Utc::now());
+        let stat = db.stat_in_base(ChatId(0), df, dt, "USD", &rates).await.unwrap();
+        assert_eq!(stat.len(), 1);
+        assert_eq!(stat.n_items(), 2);
+        assert_eq!(stat.amount(), 35.0);
+
+        let totals = db.currency_totals(ChatId(0), df, dt).await.unwrap();
+        assert_eq!(totals, vec![
+            (Some("EUR".to_string()), 20.0),
+            (Some("GBP".to_string()), 5.0),
+            (Some("USD".to_string()), 10.0)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_stat_skips_other_currencies() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_cost(cat_id, 7.0, None).await.unwrap();
+        db.create_cost_in(cat_id, 20.0, Some("EUR"), None).await.unwrap();
+
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        assert_eq!(stat.n_items(), 1);
+        assert_eq!(stat.amount(), 7.0);
+        assert_eq!(db.total_amount(ChatId(0), None, None).await.unwrap(), 7.0);
+    }
+
+    #[tokio::test]
+    async fn test_goal() {
+        let db = DB::from_memory().await.unwrap();
+        assert!(db.get_goal(ChatId(0)).await.unwrap().is_none());
+
+        let started = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let deadline = Utc.with_ymd_and_hms(2025, 7, 1, 0, 0, 0).unwrap();
+        db.set_goal(ChatId(0), 3000.0, started, deadline).await.unwrap();
+        db.set_goal(ChatId(0), 5000.0, started, deadline).await.unwrap();
+
+        let goal = db.get_goal(ChatId(0)).await.unwrap().unwrap();
+        assert_eq!(goal.amount, 5000.0);
+        assert_eq!(goal.started, started);
+        assert_eq!(goal.deadline, deadline);
+    }
+
+    #[tokio::test]
+    async fn test_from_pool() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        let db = DB::from_pool(pool.clone());
+        db.migrate().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 1);
+        db.create_cost(cat_id, 12.5, None).await.unwrap();
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().amount(), 12.5);
+        assert_eq!(db.total_amount(ChatId(0), None, None).await.unwrap(), 12.5);
+
+        let n: i64 = sqlx::query_scalar("SELECT count(0) FROM category").fetch_one(&pool).await.unwrap();
+        assert_eq!(n, 1);
+    }
+
+    #[tokio::test]
+    async fn test_standalone_db() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(42), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_cost(cat_id, 12.5, None).await.unwrap();
+        let stat = db.get_stat_this_month(ChatId(42)).await.unwrap();
+        assert_eq!(stat.amount(), 12.5);
+        assert!(db.get_stat_this_month(ChatId(0)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chat_id_round_trip() {
+        let db = DB::from_memory().await.unwrap();
+        let chat_id = ChatId(-1001234567890);
+        let cat_id = db.create_category(chat_id, "t1".to_string(), "test".to_string()).await.unwrap();
+        db.add_recurring(chat_id, cat_id, 5.0, Frequency::Monthly { day: 1 }).await.unwrap();
+
+        let cats = db.get_categories(chat_id).await.unwrap();
+        assert_eq!(cats[0].chat_id, chat_id);
+        assert_eq!(db.list_recurring(chat_id).await.unwrap()[0].chat_id, chat_id);
+    }
+
+    #[tokio::test]
+    async fn test_most_frequent_category() {
+        let db = DB::from_memory().await.unwrap();
+        let (df, dt) = month_bounds(Utc::now());
+        assert!(db.most_frequent_category(ChatId(0), df, dt).await.unwrap().is_none());
+
+        let coffee = db.create_category(ChatId(0), "c".to_string(), "coffee".to_string()).await.unwrap();
+        let rent = db.create_category(ChatId(0), "r".to_string(), "rent".to_string()).await.unwrap();
+        for _ in 0..3 {
+            db.create_cost(coffee, 3.5, None).await.unwrap();
+        }
+        db.create_cost(rent, 900.0, None).await.unwrap();
+
+        let (cat, n) = db.most_frequent_category(ChatId(0), df, dt).await.unwrap().unwrap();
+        assert_eq!(cat.id, coffee);
+        assert_eq!(n, 3);
+    }
+
+    #[tokio::test]
+    async fn test_stat_by_day() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let day = |d| Utc.with_ymd_and_hms(2025, 4, d, 12, 0, 0).unwrap();
+        db.create_cost(cat_id, 10.0, Some(day(2))).await.unwrap();
+        db.create_cost(cat_id, 5.0, Some(day(2))).await.unwrap();
+        db.create_cost(cat_id, 7.0, Some(day(30))).await.unwrap();
+
+        let (df, dt) = month_bounds(day(1));
+        let days = db.stat_by_day(ChatId(0), df, dt, Tz::UTC).await.unwrap();
+        assert_eq!(days.len(), 30);
+        assert_eq!(days[0], (NaiveDate::from_ymd_opt(2025, 4, 1).unwrap(), 0.0));
+        assert_eq!(days[1].1, 15.0);
+        assert_eq!(days[29].1, 7.0);
 
-        let groups = sqlx::query(&q)
-            .bind(chat_id.0)
-            .map(| row: SqliteRow | StatCategory::from(row))
-            .fetch_all(&self.conn)
-            .await?;
+        // 2 April 20:00 UTC is already 3 April in Tokyo
+        db.create_cost(cat_id, 3.0, Some(day(2) + chrono::Duration::hours(8))).await.unwrap();
+        let (df, dt) = local_month_bounds(day(1), chrono_tz::Asia::Tokyo);
+        let days = db.stat_by_day(ChatId(0), df, dt, chrono_tz::Asia::Tokyo).await.unwrap();
+        assert_eq!(days.len(), 30);
+        assert_eq!(days[0].0, NaiveDate::from_ymd_opt(2025, 4, 1).unwrap());
+        assert_eq!(days[1].1, 15.0);
+        assert_eq!(days[2].1, 3.0);
+    }
 
-        Ok(Stat::new(groups))
+    #[tokio::test]
+    async fn test_remove_last_n_costs() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let other = db.create_category(ChatId(1), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_cost(cat_id, 1.0, None).await.unwrap();
+        db.create_cost(cat_id, 2.0, None).await.unwrap();
+        db.create_cost(other, 3.0, None).await.unwrap();
+
+        assert_eq!(db.remove_last_n_costs(ChatId(0), 3).await.unwrap(), 2);
+        assert!(db.get_stat(ChatId(0), None, None).await.unwrap().is_empty());
+        assert_eq!(db.get_stat(ChatId(1), None, None).await.unwrap().amount(), 3.0);
+        assert_eq!(db.remove_last_n_costs(ChatId(0), 1).await.unwrap(), 0);
     }
 
-    pub async fn get_stat_this_month(&self, chat_id: ChatId) -> Result<Stat, DBError> {
+    #[tokio::test]
+    async fn test_category_daily_average() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        assert_eq!(db.category_daily_average(ChatId(0), cat_id, 3).await.unwrap(), 0.0);
+
         let now = Utc::now();
-        let date_from = Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).unwrap();
+        let window = now.checked_sub_months(Months::new(3)).unwrap();
+        let days = (now - window).num_days() as f64;
+        db.create_cost(cat_id, 60.0, Some(now - chrono::Duration::days(40))).await.unwrap();
+        db.create_cost(cat_id, 30.0, Some(now - chrono::Duration::days(1))).await.unwrap();
+        db.create_cost(cat_id, 1000.0, Some(now - chrono::Duration::days(200))).await.unwrap();
+
+        let avg = db.category_daily_average(ChatId(0), cat_id, 3).await.unwrap();
+        assert!((avg - 90.0 / days).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_category_exists() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.add_alias(ChatId(0), cat_id, "extra".to_string()).await.unwrap();
+
+        assert!(db.category_exists(ChatId(0), "t1".to_string()).await.unwrap());
+        assert!(db.category_exists(ChatId(0), "extra".to_string()).await.unwrap());
+        assert!(!db.category_exists(ChatId(0), "t2".to_string()).await.unwrap());
+        assert!(!db.category_exists(ChatId(1), "t1".to_string()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_global_counts() {
+        let db = DB::from_memory().await.unwrap();
+        assert_eq!(db.global_counts().await.unwrap(), GlobalCounts::default());
+
+        let first = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
+        let second = db.create_category(ChatId(1), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.create_cost(first, 1.0, None).await.unwrap();
+        db.create_cost(second, 2.0, None).await.unwrap();
+        db.create_cost(second, 3.0, None).await.unwrap();
+        db.remove_last_cost(ChatId(1)).await.unwrap();
+
+        let counts = db.global_counts().await.unwrap();
+        assert_eq!(counts, GlobalCounts { chats: 2, categories: 3, spendings: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_get_costs() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let day = |d| Utc.with_ymd_and_hms(2025, 3, d, 12, 0, 0).unwrap();
+        db.create_cost(cat_id, 2.0, Some(day(2))).await.unwrap();
+        db.create_cost_in(cat_id, 1.0, Some("EUR"), Some(day(1))).await.unwrap();
+        db.create_cost(cat_id, 3.0, Some(day(3))).await.unwrap();
+
+        let costs = db.get_costs(ChatId(0), None, Some(day(3))).await.unwrap();
+        assert_eq!(costs.iter().map(|c| c.amount).collect::<Vec<_>>(), vec![1.0, 2.0]);
+        assert_eq!(costs[0].dt, day(1));
+        assert_eq!(costs[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(costs[1].category.alias, "t1");
+    }
+
+    #[tokio::test]
+    async fn test_get_stat_since() {
+        let db = DB::from_memory().await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        let payday = Utc.with_ymd_and_hms(2025, 3, 25, 0, 0, 0).unwrap();
+        db.create_cost(cat_id, 10.0, Some(payday - chrono::Duration::seconds(1))).await.unwrap();
+        db.create_cost(cat_id, 20.0, Some(payday)).await.unwrap();
+        db.create_cost(cat_id, 30.0, None).await.unwrap();
+
+        let stat = db.get_stat(ChatId(0), Some(payday), None).await.unwrap();
+        assert_eq!(stat.n_items(), 2);
+        assert_eq!(stat.amount(), 50.0);
+    }
+
+    #[tokio::test]
+    async fn test_category_order() {
+        let db = DB::from_memory().await.unwrap();
+        for alias in ["a", "b", "c"] {
+            db.create_category(ChatId(0), alias.to_string(), alias.to_string()).await.unwrap();
+        }
+        let aliases = |cats: Vec<CategoryRow>| cats.into_iter().map(|c| c.category.alias).collect::<Vec<_>>();
+
+        assert!(db.set_category_position(ChatId(0), "c".to_string(), 1).await.unwrap());
+        assert!(db.set_category_position(ChatId(0), "a".to_string(), 10).await.unwrap());
+        assert!(!db.set_category_position(ChatId(0), "x".to_string(), 1).await.unwrap());
+        db.create_category(ChatId(0), "d".to_string(), "d".to_string()).await.unwrap();
+
+        let cats = db.get_categories(ChatId(0)).await.unwrap();
+        assert_eq!(aliases(cats), vec!["c", "b", "a", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_set_favorite() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        assert!(db.set_favorite(ChatId(0), "t1".to_string(), true).await.unwrap());
+        assert!(!db.set_favorite(ChatId(0), "t2".to_string(), true).await.unwrap());
+        assert!(db.get_categories(ChatId(0)).await.unwrap()[0].is_favorite);
+    }
+
+    #[tokio::test]
+    async fn test_readonly_error() {
+        let path = std::env::temp_dir().join(format!("tracker_readonly_{}.db", std::process::id()));
+        let url = format!("sqlite:{}?mode=rwc", path.display());
+        let db = DB::new(&url).await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.close().await;
+
+        let options = url.parse::<sqlx::sqlite::SqliteConnectOptions>().unwrap().read_only(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await.unwrap();
+        let readonly = DB::from_pool(pool);
+        let result = readonly.create_cost(cat_id, 1.0, None).await;
+        readonly.close().await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(DBError::ReadOnly)));
+        assert!(result.unwrap_err().is_storage_unavailable());
+    }
 
-        let next_month = if now.month() == 12 {
-            (now.year() + 1, 1)
-        } else {
-            (now.year(), now.month() + 1)
+    #[test]
+    fn test_receipt() {
+        let item = |name: &str, amount| StatCategory {
+            category: Category::new(name.to_string(), name.to_string()),
+            n_items: 1,
+            amount
         };
+        let stat = Stat::new(vec![
+            item("Food", 12.5),
+            item("A very long category name that gets cut", 900.0)
+        ]);
+        let receipt = stat.receipt("March 2025");
+        let lines = receipt.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().all(|l| l.chars().count() == RECEIPT_WIDTH));
+        assert_eq!(lines[0].trim(), "March 2025");
+        assert_eq!(lines[2], "Food                       12.50");
+        assert_eq!(lines[3], "A very long category name 900.00");
+        assert_eq!(lines[5], "TOTAL                     912.50");
+    }
 
-        let date_to = Utc.with_ymd_and_hms(next_month.0, next_month.1, 1, 0, 0, 0).unwrap();
-        self.get_stat(chat_id, Some(date_from), Some(date_to)).await
+    #[test]
+    fn test_shares() {
+        let item = |name: &str, amount| StatCategory {
+            category: Category::new(name.to_string(), name.to_string()),
+            n_items: 1,
+            amount
+        };
+        let stat = Stat::new(vec![item("Rent", 35.0), item("Food", 40.0), item("Taxi", 25.0)]);
+        let shares = stat.shares();
+        assert_eq!(shares.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), vec!["Food", "Rent", "Taxi"]);
+        assert!((shares[0].1 - 40.0).abs() < 1e-9);
+        assert!((shares.iter().map(|(_, p)| p).sum::<f64>() - 100.0).abs() < 1e-9);
+        assert!(Stat::new(vec![item("Food", 0.0)]).shares().is_empty());
+        assert!(Stat::new(Vec::new()).shares().is_empty());
     }
 
-}
+    #[test]
+    fn test_round_display() {
+        let stat = || Stat::new(vec![StatCategory {
+            category: Category::new("f".to_string(), "Food".to_string()),
+            n_items: 3,
+            amount: 1234.56
+        }]);
+        let text = stat().round_display(true).to_string();
+        assert!(text.contains("amount=1235"));
+        assert!(text.ends_with("Amount: 1235"));
+        assert!(stat().round_display(true).receipt("").ends_with(" 1235"));
 
+        let text = stat().to_string();
+        assert!(text.contains("amount=1234.56"));
+        assert!(text.ends_with("Amount: 1234.56"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_lapsed_categories() {
+        let db = DB::from_memory().await.unwrap();
+        let gym = db.create_category(ChatId(0), "g".to_string(), "gym".to_string()).await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        db.create_category(ChatId(0), "n".to_string(), "never".to_string()).await.unwrap();
+
+        let (last_month, _) = previous_month_bounds(Utc::now());
+        db.create_cost(gym, 30.0, Some(last_month + chrono::Duration::days(2))).await.unwrap();
+        db.create_cost(food, 10.0, Some(last_month + chrono::Duration::days(2))).await.unwrap();
+        db.create_cost(food, 10.0, None).await.unwrap();
+
+        let lapsed = db.lapsed_categories(ChatId(0), chrono_tz::UTC).await.unwrap();
+        assert_eq!(lapsed.iter().map(|c| c.id).collect::<Vec<_>>(), vec![gym]);
+    }
 
     #[tokio::test]
-    async fn test_connect() {
-        let db = DB::from_memory().await;
-        assert!(db.is_ok())
+    async fn test_schema_version() {
+        let db = DB::from_memory().await.unwrap();
+        let version = db.schema_version().await.unwrap();
+        assert!(version > 0);
+        // running migrations again is a no-op
+        db.migrate().await.unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), version);
     }
 
     #[tokio::test]
-    async fn test_create_category() {
+    async fn test_category_trend() {
         let db = DB::from_memory().await.unwrap();
-        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 0);
-        assert!(db.create_category(ChatId(0), "t".to_string(), "test".to_string()).await.is_ok());
-        assert_eq!(db.get_categories(ChatId(0)).await.unwrap().len(), 1);
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        let (this_month, _) = month_bounds(Utc::now());
+        let two_back = this_month.checked_sub_months(Months::new(2)).unwrap();
+        db.create_cost(food, 30.0, Some(two_back + chrono::Duration::days(1))).await.unwrap();
+        db.create_cost(food, 10.0, None).await.unwrap();
+
+        let trend = db.category_trend(ChatId(0), food, 3).await.unwrap();
+        assert_eq!(trend, vec![30.0, 0.0, 10.0]);
     }
 
     #[tokio::test]
-    async fn test_get_category() {
+    async fn test_attach_photo() {
         let db = DB::from_memory().await.unwrap();
-        let id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await;
-        assert!(id.is_ok());
+        assert_eq!(db.attach_photo(ChatId(0), 10, "photo".to_string()).await.unwrap(), None);
+
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        let id = db.create_cost(food, 12.5, None).await.unwrap();
+        db.set_cost_message(id, 10).await.unwrap();
+        let later = db.create_cost(food, 5.0, None).await.unwrap();
+        db.set_cost_message(later, 11).await.unwrap();
+        assert_eq!(db.attach_photo(ChatId(1), 10, "AgACAgIAAxk".to_string()).await.unwrap(), None);
+        assert_eq!(db.attach_photo(ChatId(0), 12, "AgACAgIAAxk".to_string()).await.unwrap(), None);
+        assert_eq!(db.attach_photo(ChatId(0), 10, "AgACAgIAAxk".to_string()).await.unwrap(), Some(id));
+
+        let costs = db.get_costs(ChatId(0), None, None).await.unwrap();
+        assert_eq!(costs[0].photo_file_id.as_deref(), Some("AgACAgIAAxk"));
+        assert_eq!(costs[1].photo_file_id, None);
+        assert!(costs[0].display(&Settings::default()).ends_with("12.50 📎"));
     }
 
     #[tokio::test]
-    async fn test_get_category_alias() {
+    async fn test_stat_matrix() {
         let db = DB::from_memory().await.unwrap();
-        let _ = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await;
-        let _ = db.create_category(ChatId(0), "t2".to_string(), "test2".to_string()).await;
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        let taxi = db.create_category(ChatId(0), "t".to_string(), "taxi".to_string()).await.unwrap();
+        db.create_cost(food, 10.0, Some(Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(food, 2.5, Some(Utc.with_ymd_and_hms(2025, 1, 20, 0, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(food, 4.0, Some(Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(taxi, 7.0, Some(Utc.with_ymd_and_hms(2025, 2, 28, 23, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(taxi, 99.0, Some(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap())).await.unwrap();
 
-        if let Some(cat) = db.get_category_by_alias(ChatId(0), "t2".to_string()).await.unwrap() {
-            assert_eq!(cat.category.name, "test2")
+        let matrix = db.stat_matrix(
+            ChatId(0),
+            Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap()
+        ).await.unwrap();
+        assert_eq!(matrix, vec![
+            ("food".to_string(), 202501, 12.5),
+            ("food".to_string(), 202502, 4.0),
+            ("taxi".to_string(), 202502, 7.0)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_stat_by_user() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(-100), "f".to_string(), "food".to_string()).await.unwrap();
+        db.create_cost_by(food, 10.0, None, None, Some(1)).await.unwrap();
+        db.create_cost_by(food, 2.5, None, None, Some(1)).await.unwrap();
+        db.create_cost_by(food, 4.0, None, None, Some(2)).await.unwrap();
+        db.create_cost(food, 1.0, None).await.unwrap();
+
+        let mine = db.get_stat_by(ChatId(-100), None, None, &StatFilter::default().user(1)).await.unwrap();
+        assert_eq!(mine.amount(), 12.5);
+        let theirs = db.get_stat_by(ChatId(-100), None, None, &StatFilter::default().user(2)).await.unwrap();
+        assert_eq!(theirs.amount(), 4.0);
+        assert_eq!(db.get_stat(ChatId(-100), None, None).await.unwrap().amount(), 17.5);
+    }
+
+    #[tokio::test]
+    async fn test_first_cost() {
+        let db = DB::from_memory().await.unwrap();
+        assert!(db.first_cost(ChatId(0)).await.unwrap().is_none());
+
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        db.create_cost(food, 1.0, Some(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap())).await.unwrap();
+        let first = db.create_cost(food, 2.0, Some(Utc.with_ymd_and_hms(2024, 11, 2, 9, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(food, 3.0, None).await.unwrap();
+        let removed = db.create_cost(food, 4.0, Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())).await.unwrap();
+        db.remove_last_cost(ChatId(0)).await.unwrap();
+
+        let cost = db.first_cost(ChatId(0)).await.unwrap().unwrap();
+        assert_ne!(cost.id, removed);
+        assert_eq!(cost.id, first);
+        assert_eq!(cost.dt, Utc.with_ymd_and_hms(2024, 11, 2, 9, 0, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_total_amount() {
+        let db = DB::from_memory().await.unwrap();
+        assert_eq!(db.total_amount(ChatId(0), None, None).await.unwrap(), 0.0);
+
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        let taxi = db.create_category(ChatId(0), "t".to_string(), "taxi".to_string()).await.unwrap();
+        db.create_cost(food, 10.25, Some(Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(taxi, 7.5, Some(Utc.with_ymd_and_hms(2025, 2, 5, 0, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(taxi, 3.0, None).await.unwrap();
+        db.create_cost(food, 100.0, None).await.unwrap();
+        db.remove_last_cost(ChatId(0)).await.unwrap();
+
+        let from = Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap());
+        let to = Some(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap());
+        for (date_from, date_to) in [(None, None), (from, to), (to, None)] {
+            assert_eq!(
+                db.total_amount(ChatId(0), date_from, date_to).await.unwrap(),
+                db.get_stat(ChatId(0), date_from, date_to).await.unwrap().amount()
+            );
         }
+        assert_eq!(db.total_amount(ChatId(0), from, to).await.unwrap(), 17.75);
+    }
 
-        match db.get_category_by_alias(ChatId(0), "t3".to_string()).await {
-            Ok(None) => assert!(true),
-            Ok(Some(_)) => assert!(false),
-            Err(_) => assert!(false)
+    #[tokio::test]
+    async fn test_categories_json_round_trip() {
+        let db = DB::from_memory().await.unwrap();
+        db.create_category(ChatId(1), "f".to_string(), "Food \"fresh\"".to_string()).await.unwrap();
+        db.create_category(ChatId(1), "t".to_string(), "Taxi".to_string()).await.unwrap();
+        db.create_category(ChatId(1), "old".to_string(), "Old".to_string()).await.unwrap();
+        db.archive_category(ChatId(1), "old".to_string()).await.unwrap();
+        let json = db.export_categories_json(ChatId(1)).await.unwrap();
+
+        assert_eq!(db.import_categories_json(ChatId(2), &json).await.unwrap(), Some(2));
+        let names = |cats: Vec<CategoryRow>| cats.into_iter()
+            .map(|c| (c.category.alias, c.category.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names(db.get_categories(ChatId(2)).await.unwrap()),
+            names(db.get_categories(ChatId(1)).await.unwrap())
+        );
+
+        // collisions are skipped
+        db.create_category(ChatId(3), "t".to_string(), "Train".to_string()).await.unwrap();
+        assert_eq!(db.import_categories_json(ChatId(3), &json).await.unwrap(), Some(1));
+        let train = db.get_category_by_alias(ChatId(3), "t".to_string()).await.unwrap().unwrap();
+        assert_eq!(train.category.name, "Train");
+
+        assert_eq!(db.import_categories_json(ChatId(4), "not json").await.unwrap(), None);
+        assert_eq!(db.import_categories_json(ChatId(4), r#"[{"alias": "x"}]"#).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_costs_in_range() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        let other = db.create_category(ChatId(1), "f".to_string(), "food".to_string()).await.unwrap();
+        let day = |d| Some(Utc.with_ymd_and_hms(2025, 3, d, 12, 0, 0).unwrap());
+        db.create_cost(food, 1.0, day(1)).await.unwrap();
+        db.create_cost(food, 2.0, day(10)).await.unwrap();
+        db.create_cost(food, 4.0, day(15)).await.unwrap();
+        db.create_cost(food, 8.0, day(20)).await.unwrap();
+        db.create_cost(other, 16.0, day(10)).await.unwrap();
+
+        let deleted = db.delete_costs_in_range(
+            ChatId(0),
+            Utc.with_ymd_and_hms(2025, 3, 10, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2025, 3, 20, 0, 0, 0).unwrap()
+        ).await.unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(db.total_amount(ChatId(0), None, None).await.unwrap(), 9.0);
+        assert_eq!(db.total_amount(ChatId(1), None, None).await.unwrap(), 16.0);
+    }
+
+    #[tokio::test]
+    async fn test_amount_histogram() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        for amount in [0.5, 9.99, 10.0, 49.0, 75.0, 100.0, 250.0, 1000.0] {
+            db.create_cost(food, amount, None).await.unwrap();
         }
+        db.create_cost_in(food, 20.0, Some("USD"), None).await.unwrap();
+        let (date_from, date_to) = month_bounds(Utc::now());
+        let counts = db.amount_histogram(ChatId(0), date_from, date_to, &[10.0, 50.0, 100.0]).await.unwrap();
+        assert_eq!(counts, vec![2, 2, 1, 3]);
+        let counts = db.amount_histogram(ChatId(0), date_from, date_to, &[]).await.unwrap();
+        assert_eq!(counts, vec![8]);
     }
 
     #[tokio::test]
-    async fn test_new_cost() {
+    async fn test_percent_budget() {
         let db = DB::from_memory().await.unwrap();
-        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
-        assert!(db.create_cost(cat_id, 123.41, None).await.is_ok());
+        let food = db.create_category(ChatId(0), "f".to_string(), "food".to_string()).await.unwrap();
+        db.set_setting(ChatId(0), Setting::MonthlyIncome, "200000".to_string()).await.unwrap();
+        db.set_budget_percent(food, 15.0).await.unwrap();
+        db.create_cost(food, 250.0, None).await.unwrap();
+
+        let budgets = db.get_budgets_this_month(ChatId(0)).await.unwrap();
+        assert_eq!(budgets[0].limit, 300.0);
+        assert_eq!(budgets[0].percent, Some(15.0));
+        assert!(!budgets[0].is_over());
+
+        db.create_cost(food, 60.0, None).await.unwrap();
+        assert!(db.get_budgets_this_month(ChatId(0)).await.unwrap()[0].is_over());
+
+        // a fixed amount replaces the percent
+        db.set_budget(food, 500.0).await.unwrap();
+        let budgets = db.get_budgets_this_month(ChatId(0)).await.unwrap();
+        assert_eq!((budgets[0].limit, budgets[0].percent), (500.0, None));
     }
 
     #[tokio::test]
-    async fn test_stat() {
+    async fn test_actions_newest_first() {
         let db = DB::from_memory().await.unwrap();
+        let at = |h| Utc.with_ymd_and_hms(2025, 3, 1, h, 0, 0).unwrap();
+        db.log_action(ChatId(0), "/stm", at(9)).await.unwrap();
+        db.log_action(ChatId(0), "/cost", at(11)).await.unwrap();
+        db.log_action(ChatId(0), "/rm", at(11)).await.unwrap();
+        db.log_action(ChatId(0), "/help", at(10)).await.unwrap();
+        db.log_action(ChatId(1), "/start", at(12)).await.unwrap();
 
-        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+        let actions = db.get_actions(ChatId(0), 3).await.unwrap();
+        assert_eq!(actions, vec![
+            (at(11), "/rm".to_string()),
+            (at(11), "/cost".to_string()),
+            (at(10), "/help".to_string())
+        ]);
+    }
 
-        let cat_id = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
-        
-        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
-        assert_eq!(stat.n_items(), 6);
-        assert_eq!(stat.amount(), 1200.0);
+    #[tokio::test]
+    async fn test_set_parent() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let cafe = db.create_category(ChatId(0), "cafe".to_string(), "Cafe".to_string()).await.unwrap();
+        let coffee = db.create_category(ChatId(0), "cof".to_string(), "Coffee".to_string()).await.unwrap();
+        let dt = Utc.with_ymd_and_hms(2025, 3, 1, 10, 0, 0).unwrap();
+        db.create_cost(food, 10.0, Some(dt)).await.unwrap();
+        db.create_cost(cafe, 5.0, Some(dt)).await.unwrap();
+        db.create_cost(coffee, 2.5, Some(dt)).await.unwrap();
+
+        let set = |child: &str, parent: &str| db.set_parent(ChatId(0), child.to_string(), Some(parent.to_string()));
+        assert_eq!(set("cafe", "f").await.unwrap(), ParentUpdate::Done);
+        assert_eq!(set("cof", "cafe").await.unwrap(), ParentUpdate::Done);
+        assert_eq!(set("f", "f").await.unwrap(), ParentUpdate::Cycle);
+        assert_eq!(set("f", "cof").await.unwrap(), ParentUpdate::Cycle);
+        assert_eq!(set("f", "nope").await.unwrap(), ParentUpdate::NotFound);
+
+        let stat = db.get_stat_grouped(ChatId(0), None, None).await.unwrap();
+        assert_eq!(stat.len(), 1);
+        assert_eq!(stat.n_items(), 3);
+        assert_eq!(stat.amount(), 17.5);
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().len(), 3);
+
+        assert_eq!(db.set_parent(ChatId(0), "cafe".to_string(), None).await.unwrap(), ParentUpdate::Done);
+        assert_eq!(db.get_stat_grouped(ChatId(0), None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retire_parent_category() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let cafe = db.create_category(ChatId(0), "cafe".to_string(), "Cafe".to_string()).await.unwrap();
+        let misc = db.create_category(ChatId(0), "m".to_string(), "Misc".to_string()).await.unwrap();
+        db.create_cost(food, 10.0, None).await.unwrap();
+        db.create_cost(cafe, 5.0, None).await.unwrap();
+        db.set_parent(ChatId(0), "cafe".to_string(), Some("f".to_string())).await.unwrap();
+
+        db.retire_category(food, misc).await.unwrap();
+        let stat = db.get_stat_grouped(ChatId(0), None, None).await.unwrap();
         assert_eq!(stat.len(), 2);
+        assert_eq!(stat.amount(), 15.0);
+        let (df, dt) = month_bounds(Utc::now());
+        let tree = db.category_tree(ChatId(0), df, dt).await.unwrap();
+        assert!(tree.iter().all(|node| node.1.is_none()));
     }
 
     #[tokio::test]
-    async fn test_stat_this_month() {
+    async fn test_spare_change() {
         let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        db.create_cost(food, 1.25, None).await.unwrap();
+        db.set_setting(ChatId(0), Setting::RoundupSavings, "1".to_string()).await.unwrap();
+        db.create_cost(food, 3.40, None).await.unwrap();
+        db.create_cost(food, 2.10, None).await.unwrap();
+        db.create_cost(food, 5.0, None).await.unwrap();
+        assert!((db.spare_change(ChatId(0)).await.unwrap() - 1.5).abs() < 1e-9);
+        assert_eq!(db.spare_change(ChatId(1)).await.unwrap(), 0.0);
+    }
 
-        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
+    #[tokio::test]
+    async fn test_stat_category_json() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        db.create_cost(food, 12.5, None).await.unwrap();
+        db.create_cost(food, 2.5, None).await.unwrap();
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        let item = &stat.items()[0];
+        assert_eq!(item.category().alias, "f");
+        assert_eq!(item.n_items(), 2);
+        assert_eq!(item.amount(), 15.0);
+        assert_eq!(
+            serde_json::to_value(item).unwrap(),
+            serde_json::json!({"category": {"alias": "f", "name": "Food"}, "n_items": 2, "amount": 15.0})
+        );
+    }
 
-        let cat_id = db.create_category(ChatId(0), "t2".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 300.0, None).await.is_ok();
-        
-        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
-        assert_eq!(stat.n_items(), 6);
-        assert_eq!(stat.amount(), 1200.0);
-        assert_eq!(stat.len(), 2);
+    #[tokio::test]
+    async fn test_find_duplicate_costs() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let taxi = db.create_category(ChatId(0), "t".to_string(), "Taxi".to_string()).await.unwrap();
+        let dt = Utc.with_ymd_and_hms(2025, 3, 1, 10, 0, 0).unwrap();
+        let first = db.create_cost(food, 12.5, Some(dt)).await.unwrap();
+        let second = db.create_cost(food, 12.5, Some(dt + chrono::Duration::seconds(30))).await.unwrap();
+        db.create_cost(taxi, 12.5, Some(dt + chrono::Duration::seconds(40))).await.unwrap();
+        db.create_cost(food, 7.0, Some(dt + chrono::Duration::seconds(50))).await.unwrap();
+        db.create_cost(food, 12.5, Some(dt + chrono::Duration::hours(5))).await.unwrap();
+        let other = db.create_category(ChatId(1), "f".to_string(), "Food".to_string()).await.unwrap();
+        db.create_cost(other, 12.5, Some(dt + chrono::Duration::seconds(10))).await.unwrap();
+
+        let dupes = db.find_duplicate_costs(ChatId(0), 120).await.unwrap();
+        assert_eq!(dupes.len(), 1);
+        assert_eq!((dupes[0].0.id, dupes[0].1.id), (first, second));
+
+        assert!(db.remove_cost(ChatId(0), second).await.unwrap());
+        assert!(!db.remove_cost(ChatId(1), first).await.unwrap());
+        assert!(db.find_duplicate_costs(ChatId(0), 120).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_stat_float() {
+    async fn test_shift_dates() {
         let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let other = db.create_category(ChatId(1), "f".to_string(), "Food".to_string()).await.unwrap();
+        let evening = Utc.with_ymd_and_hms(2025, 3, 31, 20, 0, 0).unwrap();
+        db.create_cost(food, 12.5, Some(evening)).await.unwrap();
+        db.create_cost(other, 7.0, Some(evening)).await.unwrap();
+
+        assert_eq!(db.shift_dates(ChatId(0), 9).await.unwrap(), 1);
+        let cost = db.first_cost(ChatId(0)).await.unwrap().unwrap();
+        assert_eq!(cost.dt, Utc.with_ymd_and_hms(2025, 4, 1, 5, 0, 0).unwrap());
+        assert_eq!(db.first_cost(ChatId(1)).await.unwrap().unwrap().dt, evening);
+    }
 
+    #[tokio::test]
+    async fn test_recurring_monthly_total() {
+        let db = DB::from_memory().await.unwrap();
         let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 21.5, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 23.3, None).await.is_ok();
+        assert_eq!(db.recurring_monthly_total(ChatId(0)).await.unwrap(), 0.0);
+        db.add_recurring(ChatId(0), cat_id, 12.0, Frequency::parse("weekly", 1).unwrap()).await.unwrap();
+        db.add_recurring(ChatId(0), cat_id, 500.0, Frequency::Monthly { day: 1 }).await.unwrap();
+        let total = db.recurring_monthly_total(ChatId(0)).await.unwrap();
+        assert!((total - 552.0).abs() < 1e-9);
+        assert_eq!(db.recurring_monthly_total(ChatId(1)).await.unwrap(), 0.0);
+    }
 
-        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
-        assert_eq!(stat.n_items(), 2);
-        assert_eq!(stat.amount(), (21.5 + 23.3));
+    #[tokio::test]
+    async fn test_category_description() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        assert_eq!(db.get_description(food).await.unwrap(), None);
+        let note = "includes dining out".to_string();
+        assert!(db.set_description(ChatId(0), "f".to_string(), Some(note.clone())).await.unwrap());
+        assert_eq!(db.get_description(food).await.unwrap(), Some(note));
+        assert!(!db.set_description(ChatId(1), "f".to_string(), None).await.unwrap());
+        assert!(db.set_description(ChatId(0), "f".to_string(), None).await.unwrap());
+        assert_eq!(db.get_description(food).await.unwrap(), None);
     }
 
     #[tokio::test]
-    async fn test_cost_remove() {
+    async fn test_peak_day() {
         let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let (from, to) = month_bounds(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap());
+        assert_eq!(db.peak_day(ChatId(0), from, to, Tz::UTC).await.unwrap(), None);
 
-        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
-        let _ = db.create_cost(cat_id, 100.0, None).await.is_ok();
-        let _ = db.create_cost(cat_id, 200.0, None).await.is_ok();
+        let day = |d, h| Some(Utc.with_ymd_and_hms(2025, 3, d, h, 0, 0).unwrap());
+        db.create_cost(food, 30.0, day(2, 9)).await.unwrap();
+        db.create_cost(food, 50.0, day(5, 9)).await.unwrap();
+        db.create_cost(food, 40.0, day(9, 9)).await.unwrap();
+        db.create_cost(food, 40.0, day(9, 23)).await.unwrap();
+        db.create_cost(food, 80.0, day(12, 9)).await.unwrap();
+        db.create_cost(food, 500.0, Some(Utc.with_ymd_and_hms(2025, 4, 1, 9, 0, 0).unwrap())).await.unwrap();
 
-        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
+        let peak = db.peak_day(ChatId(0), from, to, Tz::UTC).await.unwrap();
+        assert_eq!(peak, Some((NaiveDate::from_ymd_opt(2025, 3, 9).unwrap(), 80.0)));
+        // the 9th 23:00 UTC is already the 10th in Tokyo
+        let peak = db.peak_day(ChatId(0), from, to, chrono_tz::Asia::Tokyo).await.unwrap();
+        assert_eq!(peak, Some((NaiveDate::from_ymd_opt(2025, 3, 12).unwrap(), 80.0)));
+    }
+
+    #[tokio::test]
+    async fn test_moving_average_monthly() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let month = |m| Some(Utc.with_ymd_and_hms(2025, m, 10, 0, 0, 0).unwrap());
+        db.create_cost(food, 100.0, month(1)).await.unwrap();
+        db.create_cost(food, 300.0, month(2)).await.unwrap();
+        // March has nothing, April is the current month and is left out
+        db.create_cost(food, 1000.0, month(4)).await.unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 4, 20, 0, 0, 0).unwrap();
+
+        assert!((db.moving_average_monthly(ChatId(0), 3, now).await.unwrap() - 400.0 / 3.0).abs() < 1e-9);
+        assert_eq!(db.moving_average_monthly(ChatId(0), 1, now).await.unwrap(), 0.0);
+        assert_eq!(db.moving_average_monthly(ChatId(0), 0, now).await.unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_create_split_costs() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        let home = db.create_category(ChatId(0), "h".to_string(), "Home".to_string()).await.unwrap();
+        let foreign = db.create_category(ChatId(1), "x".to_string(), "Other".to_string()).await.unwrap();
+
+        assert!(!db.create_split_costs(ChatId(0), 50.0, &[(food, 30.0), (home, 15.0)], None).await.unwrap());
+        assert!(!db.create_split_costs(ChatId(0), 0.0, &[], None).await.unwrap());
+        // the first portion is valid, but nothing is kept once the second fails
+        assert!(!db.create_split_costs(ChatId(0), 50.0, &[(food, 30.0), (foreign, 20.0)], None).await.unwrap());
+        assert!(db.get_stat(ChatId(0), None, None).await.unwrap().is_empty());
+        assert!(db.get_stat(ChatId(1), None, None).await.unwrap().is_empty());
+
+        db.set_setting(ChatId(0), Setting::RoundupSavings, "1".to_string()).await.unwrap();
+        assert!(db.create_split_costs(ChatId(0), 50.1, &[(food, 30.05), (home, 20.05)], None).await.unwrap());
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
         assert_eq!(stat.n_items(), 2);
-        assert!(db.remove_last_cost(ChatId(0)).await.is_ok());
+        assert!((stat.amount() - 50.1).abs() < 1e-9);
+        assert!((db.spare_change(ChatId(0)).await.unwrap() - 1.9).abs() < 1e-9);
+    }
 
-        let stat = db.get_stat_this_month(ChatId(0)).await.unwrap();
-        assert_eq!(stat.n_items(), 1);
-        assert_eq!(stat.amount(), 100.0);
-        assert!(db.remove_last_cost(ChatId(0)).await.unwrap().is_some());
-        assert!(db.remove_last_cost(ChatId(0)).await.unwrap().is_none());
+    #[tokio::test]
+    async fn test_encrypted_amounts() {
+        let options = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+        let db = DB::connect(":memory:", options, Some(AmountCipher::new(&[7; 32]))).await.unwrap();
+        let cat_id = db.create_category(ChatId(0), "t1".to_string(), "test".to_string()).await.unwrap();
+        db.set_setting(ChatId(0), Setting::RoundupSavings, "1".to_string()).await.unwrap();
+        db.create_cost(cat_id, 12.5, None).await.unwrap();
+
+        let (cents, blob, spare): (Option<i64>, Option<Vec<u8>>, i64) = sqlx::query_as("SELECT amount_cent, amount_enc, spare_cent FROM spendings")
+            .fetch_one(&db.conn)
+            .await
+            .unwrap();
+        assert_eq!(cents, None);
+        assert_eq!(spare, 0);
+        assert_eq!(AmountCipher::new(&[7; 32]).decrypt(&blob.unwrap()), Some(1250));
+        assert_eq!(db.spare_change(ChatId(0)).await.unwrap(), 0.5);
+        assert_eq!(db.get_costs(ChatId(0), None, None).await.unwrap()[0].amount, 12.5);
+
+        // rows written before privacy mode was turned on
+        sqlx::query("INSERT INTO spendings (dt, category_id, amount_cent) VALUES (?, ?, 100)")
+            .bind(Utc::now().timestamp())
+            .bind(cat_id)
+            .execute(&db.conn)
+            .await
+            .unwrap();
+        assert_eq!(db.get_stat(ChatId(0), None, None).await.unwrap().amount(), 13.5);
+        assert_eq!(db.integrity_check(ChatId(0)).await.unwrap().bad_amounts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_compare_months_movers() {
+        let db = DB::from_memory().await.unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 3, 15, 12, 0, 0).unwrap();
+        let feb = Some(Utc.with_ymd_and_hms(2025, 2, 10, 12, 0, 0).unwrap());
+        let mar = Some(Utc.with_ymd_and_hms(2025, 3, 10, 12, 0, 0).unwrap());
+        let mut ids = HashMap::new();
+        for alias in ["food", "taxi", "rent", "fun", "gym"] {
+            ids.insert(alias, db.create_category(ChatId(0), alias.to_string(), alias.to_string()).await.unwrap());
+        }
+        for (alias, before, after) in [("food", 100.0, 130.0), ("taxi", 20.0, 90.0), ("rent", 500.0, 450.0), ("gym", 40.0, 0.0)] {
+            db.create_cost(ids[alias], before, feb).await.unwrap();
+            if after > 0.0 {
+                db.create_cost(ids[alias], after, mar).await.unwrap();
+            }
+        }
+        db.create_cost(ids["fun"], 10.0, mar).await.unwrap();
+
+        let diff = db.compare_months(ChatId(0), now).await.unwrap();
+        let (up, down) = diff.movers(2);
+        let aliases = |deltas: &[&CategoryDelta]| deltas.iter().map(|c| c.category.alias.clone()).collect::<Vec<_>>();
+        assert_eq!(aliases(&up), vec!["taxi", "food"]);
+        assert_eq!(aliases(&down), vec!["rent", "gym"]);
+        assert_eq!(down[1].to_string(), "- gym: gone (was 40.00)");
+        assert_eq!(diff.movers(5).0.last().unwrap().to_string(), "+ fun: new 10.00");
+
+        // from the 12th the March costs fall into the previous cycle and nothing into this one
+        db.set_setting(ChatId(0), Setting::FiscalStartDay, "12".to_string()).await.unwrap();
+        let diff = db.compare_months(ChatId(0), now).await.unwrap();
+        let (up, down) = diff.movers(5);
+        assert!(up.is_empty());
+        assert_eq!(aliases(&down), vec!["rent", "food", "taxi", "fun"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_categories() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "food".to_string(), "Food".to_string()).await.unwrap();
+        db.add_alias(ChatId(0), food, "eat".to_string()).await.unwrap();
+        let _ = db.create_category(ChatId(1), "rent".to_string(), "Flat".to_string()).await.unwrap();
+
+        let pairs = [("food", "Groceries"), ("rent", "Rent"), ("eat", "Eating out"), ("fun", "Entertainment"), ("rent", "Again")]
+            .map(|(alias, name)| (alias.to_string(), name.to_string()));
+        let (created, skipped) = db.create_categories(ChatId(0), &pairs).await.unwrap();
+        assert_eq!(created, 2);
+        assert_eq!(skipped, vec!["food", "eat", "rent"]);
+
+        let names = db.get_categories(ChatId(0)).await.unwrap()
+            .into_iter()
+            .map(|c| c.category.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Food", "Rent", "Entertainment"]);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        db.create_cost(food, 10.1, Some(Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(food, 20.2, None).await.unwrap();
+        let removed = db.create_cost(food, 99.0, None).await.unwrap();
+        assert!(db.remove_cost(ChatId(0), removed).await.unwrap());
+
+        assert_eq!(db.reconcile(ChatId(0), 30.3).await.unwrap(), 0.0);
+        assert_eq!(db.reconcile(ChatId(0), 25.0).await.unwrap(), 5.3);
+        assert_eq!(db.reconcile(ChatId(0), 40.0).await.unwrap(), -9.7);
+        assert_eq!(db.reconcile(ChatId(1), 0.0).await.unwrap(), 0.0);
     }
 }
@@ -0,0 +1,145 @@
+use chrono_tz::Tz;
+use crate::db::CostRow;
+
+
+/// Field delimiter and decimal separator of exported CSV
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CsvFormat {
+    pub delimiter: char,
+    pub decimal: char
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self { delimiter: ',', decimal: '.' }
+    }
+}
+
+impl CsvFormat {
+    /// Reads `[delimiter] [decimal]` like `; ,`, missing parts keep the defaults.
+    /// The delimiter may also be written as `tab`
+    pub fn parse(args: &str) -> Option<Self> {
+        let mut format = Self::default();
+        let mut parts = args.split_whitespace();
+        if let Some(delimiter) = parts.next() {
+            format.delimiter = match delimiter {
+                "tab" => '\t',
+                d if d.chars().count() == 1 => d.chars().next()?,
+                _ => return None
+            };
+        }
+        if let Some(decimal) = parts.next() {
+            format.decimal = match decimal {
+                "." | "," => decimal.chars().next()?,
+                _ => return None
+            };
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(format)
+        }
+    }
+
+    fn escape(&self, field: &str) -> String {
+        if field.contains([self.delimiter, '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    fn amount(&self, amount: f64) -> String {
+        format!("{:.2}", amount).replace('.', &self.decimal.to_string())
+    }
+}
+
+/// One row per cost with a header, dates in the chat's timezone `tz`
+pub fn export_csv(costs: &[CostRow], format: &CsvFormat, tz: Tz) -> String {
+    let delimiter = format.delimiter.to_string();
+    let mut lines = vec![["date", "alias", "category", "amount", "currency"].join(&delimiter)];
+    lines.extend(costs.iter().map(|cost| {
+        [
+            cost.dt.with_timezone(&tz).format("%Y-%m-%d %H:%M:%S").to_string(),
+            format.escape(&cost.category.alias),
+            format.escape(&cost.category.name),
+            format.escape(&format.amount(cost.amount)),
+            format.escape(cost.currency.as_deref().unwrap_or_default())
+        ].join(&delimiter)
+    }));
+    lines.join("\n") + "\n"
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use crate::item::Category;
+
+    /// Minimal RFC 4180 reader for checking the output
+    fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        for line in text.lines() {
+            let mut fields = Vec::new();
+            let mut field = String::new();
+            let mut quoted = false;
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if quoted && chars.peek() == Some(&'"') => {
+                        field.push('"');
+                        chars.next();
+                    },
+                    '"' => quoted = !quoted,
+                    c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+                    c => field.push(c)
+                }
+            }
+            fields.push(field);
+            rows.push(fields);
+        }
+        rows
+    }
+
+    #[test]
+    fn test_export_csv_locale() {
+        let costs = vec![
+            CostRow {
+                id: 1,
+                dt: Utc.with_ymd_and_hms(2025, 3, 1, 10, 30, 0).unwrap(),
+                category: Category::new("f".to_string(), "Food; \"fresh\"".to_string()),
+                amount: 1234.5,
+                currency: None,
+                photo_file_id: None
+            },
+            CostRow {
+                id: 2,
+                dt: Utc.with_ymd_and_hms(2025, 3, 2, 8, 0, 0).unwrap(),
+                category: Category::new("t".to_string(), "Taxi".to_string()),
+                amount: 7.0,
+                currency: Some("EUR".to_string()),
+                photo_file_id: None
+            }
+        ];
+        let format = CsvFormat::parse("; ,").unwrap();
+        let csv = export_csv(&costs, &format, Tz::UTC);
+        let rows = parse_csv(&csv, ';');
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], vec!["date", "alias", "category", "amount", "currency"]);
+        assert_eq!(rows[1], vec!["2025-03-01 10:30:00", "f", "Food; \"fresh\"", "1234,50", ""]);
+        assert_eq!(rows[2], vec!["2025-03-02 08:00:00", "t", "Taxi", "7,00", "EUR"]);
+
+        let csv = export_csv(&costs[1..], &CsvFormat::default(), Tz::UTC);
+        assert_eq!(csv.lines().nth(1), Some("2025-03-02 08:00:00,t,Taxi,7.00,EUR"));
+        let csv = export_csv(&costs[1..], &CsvFormat::default(), chrono_tz::Asia::Tokyo);
+        assert_eq!(csv.lines().nth(1), Some("2025-03-02 17:00:00,t,Taxi,7.00,EUR"));
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(CsvFormat::parse(""), Some(CsvFormat::default()));
+        assert_eq!(CsvFormat::parse("tab"), Some(CsvFormat { delimiter: '\t', decimal: '.' }));
+        assert_eq!(CsvFormat::parse("; x"), None);
+        assert_eq!(CsvFormat::parse(";; ,"), None);
+    }
+}
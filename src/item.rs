@@ -1,7 +1,8 @@
 use chrono::{DateTime, Datelike, Utc};
+use serde::Serialize;
 
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct Category {
     pub alias: String,
     pub name: String
@@ -16,6 +17,7 @@ impl Category {
 pub struct Item {
     date: DateTime<Utc>,
     category: Category,
+    #[allow(dead_code)]
     amount: f64,
 }
 
@@ -34,9 +36,11 @@ pub struct ItemCollectionFilter<'a> {
 }
 
 pub struct ItemCollectionStat {
+    #[allow(dead_code)]
     n_items: usize
 }
 
+#[allow(clippy::new_without_default, clippy::len_without_is_empty)]
 impl ItemCollection {
     pub fn new() -> Self {
         Self { items: Vec::new() }
@@ -54,7 +58,7 @@ impl ItemCollection {
         self.items.len()
     }
 
-    pub fn select(&self) -> ItemCollectionFilter {
+    pub fn select(&self) -> ItemCollectionFilter<'_> {
         ItemCollectionFilter {
             items: self.items.iter().collect()
         }
@@ -62,6 +66,7 @@ impl ItemCollection {
 
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl<'a> ItemCollectionFilter<'a> {
     pub fn by_category_alias(&mut self, alias: String) -> &mut Self {
         self.items.retain(|item| item.category.alias == alias);
@@ -1,3 +1,18 @@
 pub mod db;
 pub mod item;
+#[cfg(feature = "bot")]
 pub mod bot;
+pub mod period;
+pub mod report;
+pub mod settings;
+pub mod metrics;
+pub mod ratelimit;
+pub mod recurring;
+pub mod currency;
+pub mod rates;
+pub mod export;
+pub mod crypto;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(feature = "pdf")]
+pub mod pdf;
@@ -1,16 +1,35 @@
 use tg_spending_tracker::bot::run_bot;
+use tg_spending_tracker::crypto::AmountCipher;
 use tg_spending_tracker::db::DB;
 use anyhow::Result;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
     let db_path = std::env::var("DATABASE_URL")
         .unwrap_or_else(|_| "./data/data.db".to_string());
     if !std::fs::exists(&db_path).expect("err") {
         std::fs::File::create(&db_path).expect("DB not created");
     }
-    let db = DB::new(&format!("sqlite:{}", &db_path)).await?;
+    let url = format!("sqlite:{}", &db_path);
+    let db = match AmountCipher::from_env().map_err(anyhow::Error::msg)? {
+        Some(cipher) => DB::encrypted(&url, cipher).await?,
+        None => DB::new(&url).await?
+    };
+    #[cfg(feature = "web")]
+    if let Ok(addr) = std::env::var("WEB_ADDR") {
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(error) = tg_spending_tracker::web::serve(db, &addr).await {
+                tracing::error!(%error, "web api stopped");
+            }
+        });
+    }
     run_bot(db).await?;
     Ok(())
 }
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+/// Process-wide counters, shared between handlers through `Arc`
+#[derive(Default)]
+pub struct Metrics {
+    commands: Mutex<BTreeMap<String, u64>>,
+    db_errors: AtomicU64,
+    costs_created: AtomicU64
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub commands: BTreeMap<String, u64>,
+    pub db_errors: u64,
+    pub costs_created: u64
+}
+
+impl Metrics {
+    pub fn command(&self, name: &str) {
+        let mut commands = self.commands.lock().unwrap();
+        *commands.entry(name.to_string()).or_default() += 1;
+    }
+
+    pub fn db_error(&self) {
+        self.db_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cost_created(&self) {
+        self.costs_created(1);
+    }
+
+    /// Several costs stored at once, like the portions of a split purchase
+    pub fn costs_created(&self, n: u64) {
+        self.costs_created.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            commands: self.commands.lock().unwrap().clone(),
+            db_errors: self.db_errors.load(Ordering::Relaxed),
+            costs_created: self.costs_created.load(Ordering::Relaxed)
+        }
+    }
+}
+
+impl Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let commands = self.commands.iter()
+            .map(|(name, n)| format!("-> {name}: {n}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(
+            f,
+            "Commands \n{}\nDB errors: {}\nCosts created: {}",
+            commands, self.db_errors, self.costs_created
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = Metrics::default();
+        metrics.command("/stm");
+        metrics.command("/stm");
+        metrics.command("/lc");
+        metrics.db_error();
+        metrics.cost_created();
+        metrics.cost_created();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.commands.get("/stm"), Some(&2));
+        assert_eq!(snapshot.commands.get("/lc"), Some(&1));
+        assert_eq!(snapshot.db_errors, 1);
+        assert_eq!(snapshot.costs_created, 2);
+    }
+}
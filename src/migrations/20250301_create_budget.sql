@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS budget (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    category_id INTEGER,
+    amount_cent INTEGER,
+    UNIQUE(category_id)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS setting (
+    chat_id INTEGER,
+    key TEXT,
+    value TEXT,
+    PRIMARY KEY(chat_id, key)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS category_alias (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER,
+    category_id INTEGER,
+    alias TEXT,
+    UNIQUE(chat_id, alias)
+);
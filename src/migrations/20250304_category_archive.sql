@@ -0,0 +1 @@
+ALTER TABLE category ADD COLUMN is_archived INTEGER DEFAULT 0;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS recurring (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER,
+    category_id INTEGER,
+    amount_cent INTEGER,
+    frequency TEXT DEFAULT 'monthly',
+    day_of_month INTEGER,
+    day_of_week INTEGER,
+    last_run TEXT
+);
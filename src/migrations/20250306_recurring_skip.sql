@@ -0,0 +1 @@
+ALTER TABLE recurring ADD COLUMN skip_next INTEGER DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS goal (
+    chat_id INTEGER PRIMARY KEY,
+    amount_cent INTEGER,
+    started INTEGER,
+    deadline INTEGER
+);
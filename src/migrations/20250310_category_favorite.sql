@@ -0,0 +1 @@
+ALTER TABLE category ADD COLUMN is_favorite INTEGER DEFAULT 0;
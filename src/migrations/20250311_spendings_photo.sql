@@ -0,0 +1 @@
+ALTER TABLE spendings ADD COLUMN photo_file_id TEXT;
@@ -0,0 +1 @@
+ALTER TABLE spendings ADD COLUMN user_id INTEGER;
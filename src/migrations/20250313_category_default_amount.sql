@@ -0,0 +1 @@
+ALTER TABLE category ADD COLUMN default_amount_cent INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE budget ADD COLUMN percent REAL;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS action_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    dt INTEGER NOT NULL,
+    action TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS action_log_chat ON action_log (chat_id, dt);
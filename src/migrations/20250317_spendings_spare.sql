@@ -0,0 +1 @@
+ALTER TABLE spendings ADD COLUMN spare_cent INTEGER NOT NULL DEFAULT 0;
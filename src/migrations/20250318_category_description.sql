@@ -0,0 +1 @@
+ALTER TABLE category ADD COLUMN description TEXT;
@@ -0,0 +1 @@
+ALTER TABLE spendings ADD COLUMN amount_enc BLOB;
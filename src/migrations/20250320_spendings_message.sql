@@ -0,0 +1 @@
+ALTER TABLE spendings ADD COLUMN message_id INTEGER;
@@ -0,0 +1,88 @@
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use crate::db::Stat;
+
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const LINE_HEIGHT: f32 = 16.0;
+const ROWS_PER_PAGE: usize = 44;
+const FONT: Name = Name(b"F1");
+
+/// Helvetica only knows WinAnsi, characters outside Latin-1 become `?`
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+fn show(content: &mut Content, x: f32, y: f32, size: f32, text: &str) {
+    content.begin_text();
+    content.set_font(FONT, size);
+    content.next_line(x, y);
+    content.show(Str(&latin1(text)));
+    content.end_text();
+}
+
+/// A4 document with a title, one line per category and the total,
+/// long stats continue on further pages
+pub fn stat_pdf(title: &str, stat: &Stat) -> Vec<u8> {
+    let mut lines = stat.items().iter()
+        .map(|i| (i.category().name.clone(), i.n_items().to_string(), format!("{:.2}", i.amount())))
+        .collect::<Vec<_>>();
+    lines.push(("Total".to_string(), stat.n_items().to_string(), format!("{:.2}", stat.amount())));
+    let pages = lines.chunks(ROWS_PER_PAGE).collect::<Vec<_>>();
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    // every page takes two ids: the page and its content stream
+    let page_ids = (0..pages.len()).map(|i| Ref::new(4 + 2 * i as i32)).collect::<Vec<_>>();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(pages.len() as i32);
+    pdf.type1_font(font_id).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+
+    for (n, (rows, page_id)) in pages.iter().zip(&page_ids).enumerate() {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut page = pdf.page(*page_id);
+        page.parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .contents(content_id);
+        page.resources().fonts().pair(FONT, font_id);
+        page.finish();
+
+        let mut content = Content::new();
+        let mut y = PAGE_HEIGHT - MARGIN;
+        if n == 0 {
+            show(&mut content, MARGIN, y, 16.0, title);
+            y -= 2.0 * LINE_HEIGHT;
+        }
+        for (name, count, amount) in rows.iter() {
+            show(&mut content, MARGIN, y, 11.0, name);
+            show(&mut content, 350.0, y, 11.0, count);
+            show(&mut content, 450.0, y, 11.0, amount);
+            y -= LINE_HEIGHT;
+        }
+        pdf.stream(content_id, &content.finish());
+    }
+    pdf.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ChatId, DB};
+
+    #[tokio::test]
+    async fn test_stat_pdf() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Еда".to_string()).await.unwrap();
+        db.create_cost(food, 12.5, None).await.unwrap();
+        let stat = db.get_stat(ChatId(0), None, None).await.unwrap();
+        let bytes = stat_pdf("2025-03", &stat);
+        assert!(bytes.starts_with(b"%PDF"));
+        assert!(bytes.len() > 100);
+        assert!(stat_pdf("empty", &db.get_stat(ChatId(1), None, None).await.unwrap()).starts_with(b"%PDF"));
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use thiserror::Error;
+
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PeriodError {
+    #[error("Provide date from in YYYY-MM-DD format")]
+    DateFrom,
+    #[error("Provide date to in YYYY-MM-DD format")]
+    DateTo,
+    #[error("Start date must be before end date")]
+    Reversed
+}
+
+
+pub fn month_bounds(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let date_from = Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).unwrap();
+
+    let next_month = if now.month() == 12 {
+        (now.year() + 1, 1)
+    } else {
+        (now.year(), now.month() + 1)
+    };
+
+    let date_to = Utc.with_ymd_and_hms(next_month.0, next_month.1, 1, 0, 0, 0).unwrap();
+    (date_from, date_to)
+}
+
+pub fn previous_month_bounds(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (date_from, _) = month_bounds(now);
+    month_bounds(date_from - Duration::days(1))
+}
+
+/// Calendar month of `now` as seen in `tz`, as UTC instants
+pub fn local_month_bounds(now: DateTime<Utc>, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    local_cycle_bounds(now, 1, tz)
+}
+
+/// `cycle_bounds` with the cycle starting at local midnight in `tz`, as UTC instants
+pub fn local_cycle_bounds(now: DateTime<Utc>, start_day: u32, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    let local = now.with_timezone(&tz).naive_local();
+    let (date_from, date_to) = cycle_bounds(Utc.from_utc_datetime(&local), start_day);
+    let to_utc = |dt: DateTime<Utc>| {
+        tz.from_local_datetime(&dt.naive_utc())
+            .earliest()
+            .map(|d| d.with_timezone(&Utc))
+            .unwrap_or(dt)
+    };
+    (to_utc(date_from), to_utc(date_to))
+}
+
+/// Part of the period from `date_from` to `date_to` already passed at `now`, in (0, 1]
+pub fn elapsed_fraction(now: DateTime<Utc>, date_from: DateTime<Utc>, date_to: DateTime<Utc>) -> f64 {
+    let total = (date_to - date_from).num_seconds() as f64;
+    let elapsed = (now - date_from).num_seconds().max(1) as f64;
+    elapsed / total
+}
+
+/// Midnight UTC of a `YYYY-MM-DD` date
+pub fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+}
+
+pub fn parse_period(date_from: &str, date_to: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), PeriodError> {
+    let df = parse_date(date_from).ok_or(PeriodError::DateFrom)?;
+    let dt = parse_date(date_to).ok_or(PeriodError::DateTo)?;
+    if df > dt {
+        return Err(PeriodError::Reversed);
+    }
+    Ok((df, dt))
+}
+
+/// Budget cycle starting on `start_day` of every month. In months shorter than
+/// `start_day` the cycle starts on their last day; 0 and 1 both mean calendar months
+pub fn cycle_bounds(now: DateTime<Utc>, start_day: u32) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (month_start, _) = month_bounds(now);
+    let (previous_start, _) = previous_month_bounds(now);
+    let (next_start, _) = month_bounds(month_start + Duration::days(31));
+    let start_in = |month: DateTime<Utc>| {
+        let (from, to) = month_bounds(month);
+        let days = (to - from).num_days() as u32;
+        from + Duration::days((start_day.clamp(1, days) - 1) as i64)
+    };
+    let this_start = start_in(month_start);
+    if now >= this_start {
+        (this_start, start_in(next_start))
+    } else {
+        (start_in(previous_start), this_start)
+    }
+}
+
+/// Week starts on Monday
+pub fn week_bounds(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let days_from_monday = now.weekday().num_days_from_monday() as i64;
+    let monday = now.date_naive() - Duration::days(days_from_monday);
+    let date_from = Utc.from_utc_datetime(&monday.and_hms_opt(0, 0, 0).unwrap());
+    (date_from, date_from + Duration::days(7))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn parse_dt(str: &str) -> DateTime<Utc> {
+        let dt = NaiveDateTime::parse_from_str(str, "%Y-%m-%d %H:%M:%S").unwrap();
+        DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc)
+    }
+
+    #[test]
+    fn test_month_bounds() {
+        let (df, dt) = month_bounds(parse_dt("2025-12-15 10:00:00"));
+        assert_eq!(df, parse_dt("2025-12-01 00:00:00"));
+        assert_eq!(dt, parse_dt("2026-01-01 00:00:00"));
+    }
+
+    #[test]
+    fn test_previous_month_bounds() {
+        let (df, dt) = previous_month_bounds(parse_dt("2025-01-15 10:00:00"));
+        assert_eq!(df, parse_dt("2024-12-01 00:00:00"));
+        assert_eq!(dt, parse_dt("2025-01-01 00:00:00"));
+    }
+
+    #[test]
+    fn test_parse_period() {
+        let (df, dt) = parse_period("2025-01-01", "2025-02-01").unwrap();
+        assert_eq!(df, parse_dt("2025-01-01 00:00:00"));
+        assert_eq!(dt, parse_dt("2025-02-01 00:00:00"));
+        assert_eq!(parse_period("2025-02-01", "2025-01-01"), Err(PeriodError::Reversed));
+        assert_eq!(parse_period("01.01.2025", "2025-01-01"), Err(PeriodError::DateFrom));
+        assert_eq!(parse_period("2025-01-01", "tomorrow"), Err(PeriodError::DateTo));
+    }
+
+    #[test]
+    fn test_cycle_bounds() {
+        // a cost on the 24th still belongs to the cycle started last month
+        let (df, dt) = cycle_bounds(parse_dt("2025-03-24 23:00:00"), 25);
+        assert_eq!(df, parse_dt("2025-02-25 00:00:00"));
+        assert_eq!(dt, parse_dt("2025-03-25 00:00:00"));
+
+        let (df, dt) = cycle_bounds(parse_dt("2025-12-25 00:00:00"), 25);
+        assert_eq!(df, parse_dt("2025-12-25 00:00:00"));
+        assert_eq!(dt, parse_dt("2026-01-25 00:00:00"));
+
+        // February has no 31st, its cycle starts on the 28th
+        let (df, dt) = cycle_bounds(parse_dt("2025-03-10 00:00:00"), 31);
+        assert_eq!(df, parse_dt("2025-02-28 00:00:00"));
+        assert_eq!(dt, parse_dt("2025-03-31 00:00:00"));
+
+        assert_eq!(cycle_bounds(parse_dt("2025-03-10 00:00:00"), 1), month_bounds(parse_dt("2025-03-10 00:00:00")));
+        assert_eq!(cycle_bounds(parse_dt("2025-03-10 00:00:00"), 0), month_bounds(parse_dt("2025-03-10 00:00:00")));
+    }
+
+    #[test]
+    fn test_local_month_bounds() {
+        // already March in Tokyo
+        let (df, dt) = local_month_bounds(parse_dt("2025-02-28 20:00:00"), chrono_tz::Asia::Tokyo);
+        assert_eq!(df, parse_dt("2025-02-28 15:00:00"));
+        assert_eq!(dt, parse_dt("2025-03-31 15:00:00"));
+        let utc = parse_dt("2025-02-28 20:00:00");
+        assert_eq!(local_month_bounds(utc, chrono_tz::UTC), month_bounds(utc));
+
+        let (df, dt) = local_cycle_bounds(parse_dt("2025-03-24 16:00:00"), 25, chrono_tz::Asia::Tokyo);
+        assert_eq!(df, parse_dt("2025-03-24 15:00:00"));
+        assert_eq!(dt, parse_dt("2025-04-24 15:00:00"));
+    }
+
+    #[test]
+    fn test_week_bounds() {
+        // 2025-02-05 is a Wednesday
+        let (df, dt) = week_bounds(parse_dt("2025-02-05 10:00:00"));
+        assert_eq!(df, parse_dt("2025-02-03 00:00:00"));
+        assert_eq!(dt, parse_dt("2025-02-10 00:00:00"));
+    }
+}
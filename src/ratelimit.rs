@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+const DEFAULT_BURST: f64 = 30.0;
+const DEFAULT_PER_MINUTE: f64 = 60.0;
+
+
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last: Instant
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_sec: f64, now: Instant) -> Self {
+        Self { capacity, tokens: capacity, refill_per_sec, last: now }
+    }
+
+    /// Refills for the time passed since the last call and takes one token if available
+    pub fn try_consume(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Refilled to capacity by `now`, so a fresh bucket would behave the same
+    fn is_full_at(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens + elapsed * self.refill_per_sec >= self.capacity
+    }
+}
+
+/// Token bucket per chat
+pub struct RateLimiter {
+    burst: f64,
+    per_minute: f64,
+    buckets: Mutex<HashMap<i64, TokenBucket>>
+}
+
+impl RateLimiter {
+    pub fn new(burst: f64, per_minute: f64) -> Self {
+        Self { burst, per_minute, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Reads `RATE_LIMIT_BURST` and `RATE_LIMIT_PER_MINUTE`, falling back to generous defaults
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: f64| std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default);
+        Self::new(
+            read("RATE_LIMIT_BURST", DEFAULT_BURST),
+            read("RATE_LIMIT_PER_MINUTE", DEFAULT_PER_MINUTE)
+        )
+    }
+
+    pub fn check(&self, key: i64) -> bool {
+        self.check_at(key, Instant::now())
+    }
+
+    /// New keys first drop the buckets that have refilled, so idle chats don't pile up
+    fn check_at(&self, key: i64, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| !bucket.is_full_at(now));
+        }
+        buckets.entry(key)
+            .or_insert_with(|| TokenBucket::new(self.burst, self.per_minute / 60.0, now))
+            .try_consume(now)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3.0, 1.0, start);
+        assert!(bucket.try_consume(start));
+        assert!(bucket.try_consume(start));
+        assert!(bucket.try_consume(start));
+        assert!(!bucket.try_consume(start));
+
+        let later = start + Duration::from_millis(1500);
+        assert!(bucket.try_consume(later));
+        assert!(!bucket.try_consume(later));
+
+        // refill never exceeds capacity
+        let much_later = later + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_consume(much_later)));
+        assert!(!bucket.try_consume(much_later));
+    }
+
+    #[test]
+    fn test_limiter_keys() {
+        let limiter = RateLimiter::new(1.0, 1.0);
+        assert!(limiter.check(1));
+        assert!(!limiter.check(1));
+        assert!(limiter.check(2));
+    }
+
+    #[test]
+    fn test_limiter_prunes_idle() {
+        let limiter = RateLimiter::new(2.0, 60.0);
+        let start = Instant::now();
+        assert!(limiter.check_at(1, start));
+        assert!(limiter.check_at(2, start));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+
+        // both have refilled after a second
+        assert!(limiter.check_at(3, start + Duration::from_secs(1)));
+        assert_eq!(limiter.buckets.lock().unwrap().keys().collect::<Vec<_>>(), vec![&3]);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::currency::{convert, static_rates};
+
+pub const RATE_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_FX_API_URL: &str = "https://open.er-api.com/v6/latest";
+
+
+#[derive(Error, Debug)]
+pub enum RateError {
+    #[error("no rate from {0} to {1}")]
+    Unknown(String, String),
+    #[error("failed to fetch rates: {0}")]
+    Fetch(#[from] reqwest::Error)
+}
+
+/// Source of exchange rates: how many `to` units one `from` unit is worth
+#[async_trait]
+pub trait RateProvider: Send + Sync {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, RateError>;
+}
+
+/// Fixed rate table, given as the value of one unit in a common unit
+pub struct StaticRateProvider {
+    rates: HashMap<String, f64>
+}
+
+impl StaticRateProvider {
+    pub fn new(rates: HashMap<String, f64>) -> Self {
+        Self { rates }
+    }
+}
+
+impl Default for StaticRateProvider {
+    fn default() -> Self {
+        Self::new(static_rates())
+    }
+}
+
+#[async_trait]
+impl RateProvider for StaticRateProvider {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, RateError> {
+        convert(1.0, from, to, &self.rates).ok_or_else(|| RateError::Unknown(from.to_string(), to.to_string()))
+    }
+}
+
+/// Latest rates from an open.er-api.com compatible endpoint
+pub struct HttpRateProvider {
+    client: reqwest::Client,
+    url: String
+}
+
+impl HttpRateProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { client: reqwest::Client::new(), url: url.into() }
+    }
+
+    /// Reads the endpoint from `FX_API_URL`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("FX_API_URL").unwrap_or_else(|_| DEFAULT_FX_API_URL.to_string()))
+    }
+}
+
+#[async_trait]
+impl RateProvider for HttpRateProvider {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, RateError> {
+        let body: serde_json::Value = self.client
+            .get(format!("{}/{}", self.url, from))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        body["rates"][to]
+            .as_f64()
+            .ok_or_else(|| RateError::Unknown(from.to_string(), to.to_string()))
+    }
+}
+
+/// Remembers rates of the inner provider for `ttl`
+pub struct CachedRateProvider<P> {
+    inner: P,
+    ttl: Duration,
+    cache: Mutex<HashMap<(String, String), (f64, Instant)>>
+}
+
+impl<P: RateProvider> CachedRateProvider<P> {
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self { inner, ttl, cache: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl<P: RateProvider> RateProvider for CachedRateProvider<P> {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, RateError> {
+        let key = (from.to_string(), to.to_string());
+        if let Some((rate, fetched)) = self.cache.lock().unwrap().get(&key) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(*rate);
+            }
+        }
+        let rate = self.inner.rate(from, to).await?;
+        self.cache.lock().unwrap().insert(key, (rate, Instant::now()));
+        Ok(rate)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct CountingProvider {
+        calls: AtomicU32
+    }
+
+    #[async_trait]
+    impl RateProvider for CountingProvider {
+        async fn rate(&self, _from: &str, _to: &str) -> Result<f64, RateError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(2.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_provider_converts() {
+        let provider = StaticRateProvider::new(HashMap::from([
+            ("USD".to_string(), 1.0),
+            ("EUR".to_string(), 1.25)
+        ]));
+        let rate = provider.rate("EUR", "USD").await.unwrap();
+        assert_eq!(40.0 * rate, 50.0);
+        assert!(matches!(provider.rate("EUR", "GBP").await, Err(RateError::Unknown(_, _))));
+    }
+
+    #[tokio::test]
+    async fn test_cached_provider() {
+        let cached = CachedRateProvider::new(CountingProvider { calls: AtomicU32::new(0) }, RATE_CACHE_TTL);
+        assert_eq!(cached.rate("EUR", "USD").await.unwrap(), 2.0);
+        assert_eq!(cached.rate("EUR", "USD").await.unwrap(), 2.0);
+        assert_eq!(cached.inner.calls.load(Ordering::Relaxed), 1);
+
+        let expired = CachedRateProvider::new(CountingProvider { calls: AtomicU32::new(0) }, Duration::ZERO);
+        expired.rate("EUR", "USD").await.unwrap();
+        expired.rate("EUR", "USD").await.unwrap();
+        assert_eq!(expired.inner.calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+    /// Day of month 1..=31, fires on the last day of shorter months
+    Monthly { day: u32 },
+    Weekly { weekday: Weekday }
+}
+
+impl Frequency {
+    /// `kind` is `monthly` (day 1..=31) or `weekly` (day 1..=7, Monday first)
+    pub fn parse(kind: &str, day: u32) -> Option<Self> {
+        match kind {
+            "monthly" if (1..=31).contains(&day) => Some(Frequency::Monthly { day }),
+            "weekly" if (1..=7).contains(&day) => Some(Frequency::Weekly {
+                weekday: Weekday::try_from(day as u8 - 1).ok()?
+            }),
+            _ => None
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Frequency::Monthly { .. } => "monthly",
+            Frequency::Weekly { .. } => "weekly"
+        }
+    }
+
+    /// Day as accepted by `parse`
+    pub fn day(&self) -> u32 {
+        match self {
+            Frequency::Monthly { day } => *day,
+            Frequency::Weekly { weekday } => weekday.number_from_monday()
+        }
+    }
+
+    /// Average number of occurrences in a month
+    pub fn per_month(&self) -> f64 {
+        match self {
+            Frequency::Monthly { .. } => 1.0,
+            Frequency::Weekly { .. } => 52.0 / 12.0
+        }
+    }
+
+    pub fn is_due_on(&self, date: NaiveDate) -> bool {
+        match self {
+            Frequency::Monthly { day } => {
+                let last_day = last_day_of_month(date);
+                date.day() == (*day).min(last_day)
+            },
+            Frequency::Weekly { weekday } => date.weekday() == *weekday
+        }
+    }
+}
+
+fn last_day_of_month(date: NaiveDate) -> u32 {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        m => (date.year(), m + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap().pred_opt().unwrap().day()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_monthly() {
+        let freq = Frequency::parse("monthly", 31).unwrap();
+        assert!(freq.is_due_on(date("2025-01-31")));
+        assert!(freq.is_due_on(date("2025-02-28")));
+        assert!(!freq.is_due_on(date("2025-03-30")));
+        assert!(Frequency::parse("monthly", 32).is_none());
+    }
+
+    #[test]
+    fn test_weekly() {
+        let freq = Frequency::parse("weekly", 3).unwrap();
+        assert_eq!(freq, Frequency::Weekly { weekday: Weekday::Wed });
+        // 2025-02-05 is a Wednesday
+        assert!(freq.is_due_on(date("2025-02-05")));
+        assert!(!freq.is_due_on(date("2025-02-06")));
+        assert!(freq.is_due_on(date("2025-02-12")));
+        assert!(Frequency::parse("weekly", 8).is_none());
+        assert_eq!(Frequency::parse(freq.kind(), freq.day()), Some(freq));
+    }
+}
@@ -0,0 +1,441 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+use crate::settings::Lang;
+
+
+/// Text progress bar like `[■■■■□□□□] 52%`.
+/// The bar is capped at full width, the percentage is not
+pub fn progress_bar(spent: f64, limit: f64, width: usize) -> String {
+    let ratio = if limit > 0.0 { spent / limit } else { 0.0 };
+    let filled = ((ratio * width as f64).round() as usize).min(width);
+    format!(
+        "[{}{}] {:.0}%",
+        "■".repeat(filled),
+        "□".repeat(width - filled),
+        ratio * 100.0
+    )
+}
+
+/// Calendar date of `dt` in the user's timezone
+pub fn format_date(dt: DateTime<Utc>, tz: Tz, lang: Lang) -> String {
+    let local = dt.with_timezone(&tz);
+    let fmt = match lang {
+        Lang::Iso => "%Y-%m-%d",
+        Lang::En => "%-d %b %Y",
+        Lang::Ru => "%d.%m.%Y"
+    };
+    local.format(fmt).to_string()
+}
+
+/// Share of income left after spending, in percent.
+/// `None` when there is no income to compare with
+pub fn savings_rate(income: f64, spent: f64) -> Option<f64> {
+    match income > 0.0 {
+        true => Some((income - spent) / income * 100.0),
+        false => None
+    }
+}
+
+/// Change from `previous` to `current` in percent, `None` when there is no base
+pub fn percent_change(previous: f64, current: f64) -> Option<f64> {
+    match previous > 0.0 {
+        true => Some((current - previous) / previous * 100.0),
+        false => None
+    }
+}
+
+/// Today's spend against the daily average, `None` without history
+pub fn compare_to_average(today: f64, average: f64) -> Option<String> {
+    let change = percent_change(average, today)?;
+    Some(match change.round() {
+        c if c > 0.0 => format!("Today is {c:.0}% above your daily average"),
+        c if c < 0.0 => format!("Today is {:.0}% below your daily average", -c),
+        _ => "Today is right at your daily average".to_string()
+    })
+}
+
+/// Absolute and relative change between two period totals, "new" when
+/// the first period had nothing to compare with
+pub fn describe_delta(before: f64, after: f64) -> String {
+    match percent_change(before, after) {
+        Some(change) => format!("{before:.2} -> {after:.2}: {:+.2} ({change:+.0}%)", after - before),
+        None if after > 0.0 => format!("0.00 -> {after:.2}: new"),
+        None => "No spendings in either period".to_string()
+    }
+}
+
+/// Percent of a savings goal reached and whether it keeps up with the time passed.
+/// `None` for an empty goal or period
+pub fn goal_progress(target: f64, saved: f64, elapsed_days: i64, total_days: i64) -> Option<(f64, bool)> {
+    if target <= 0.0 || total_days <= 0 {
+        return None;
+    }
+    let progress = saved / target;
+    let expected = (elapsed_days as f64 / total_days as f64).clamp(0.0, 1.0);
+    Some((progress * 100.0, progress >= expected))
+}
+
+/// Calendar grid of consecutive days, a week per row starting on Monday.
+/// Days without spendings are `·`, the rest get darker towards the biggest day
+pub fn render_heatmap(days: &[(NaiveDate, f64)]) -> String {
+    const LEVELS: [char; 4] = ['░', '▒', '▓', '█'];
+    let max = days.iter().map(|(_, amount)| *amount).fold(0.0, f64::max);
+    let offset = days.first().map(|(day, _)| day.weekday().num_days_from_monday() as usize).unwrap_or(0);
+
+    let mut cells = vec![' '; offset];
+    cells.extend(days.iter().map(|(_, amount)| match *amount > 0.0 {
+        true => {
+            let level = (amount / max * LEVELS.len() as f64).ceil() as usize;
+            LEVELS[level.clamp(1, LEVELS.len()) - 1]
+        },
+        false => '·'
+    }));
+    cells.resize(cells.len().div_ceil(7) * 7, ' ');
+
+    let mut lines = vec!["Mo Tu We Th Fr Sa Su".to_string()];
+    lines.extend(cells.chunks(7).map(|week| {
+        week.iter().map(|c| format!("{c}{c}")).collect::<Vec<_>>().join(" ")
+    }));
+    lines.join("\n")
+}
+
+/// Even shares of `amount` in cents, the first person covers the leftover cents
+/// so the shares always add up. Empty for nobody to split with
+pub fn split_evenly(amount: f64, people: u32) -> Vec<f64> {
+    if people == 0 {
+        return Vec::new();
+    }
+    let cents = (amount * 100.0).round() as i64;
+    let share = cents / people as i64;
+    let remainder = cents - share * people as i64;
+    (0..people)
+        .map(|i| match i {
+            0 => (share + remainder) as f64 / 100.0,
+            _ => share as f64 / 100.0
+        })
+        .collect()
+}
+
+/// Category × month table out of `(category, YYYYMM, amount)` cells,
+/// months missing for a category show `-`
+pub fn render_pivot(cells: &[(String, u32, f64)]) -> String {
+    let mut months = cells.iter().map(|(_, month, _)| *month).collect::<Vec<_>>();
+    months.sort_unstable();
+    months.dedup();
+    let mut categories = cells.iter().map(|(name, _, _)| name.as_str()).collect::<Vec<_>>();
+    categories.dedup();
+    let width = categories.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+
+    let mut lines = vec![format!(
+        "{:width$}{}",
+        "",
+        months.iter().map(|m| format!(" {:>9}", format!("{}-{:02}", m / 100, m % 100))).collect::<String>()
+    )];
+    for name in categories {
+        let row = months.iter().map(|month| {
+            match cells.iter().find(|(n, m, _)| n == name && m == month) {
+                Some((_, _, amount)) => format!(" {amount:>9.2}"),
+                None => format!(" {:>9}", "-")
+            }
+        }).collect::<String>();
+        lines.push(format!("{name:width$}{row}"));
+    }
+    lines.join("\n")
+}
+
+/// `(id, parent_id, name, own amount)` of a category
+pub type TreeNode = (i64, Option<i64>, String, f64);
+
+/// Own amount of a category plus everything under it
+pub fn subtree_total(nodes: &[TreeNode], id: i64) -> f64 {
+    let own = nodes.iter().find(|(i, ..)| *i == id).map(|(.., amount)| *amount).unwrap_or(0.0);
+    own + nodes.iter()
+        .filter(|(_, parent, ..)| *parent == Some(id))
+        .map(|(child, ..)| subtree_total(nodes, *child))
+        .sum::<f64>()
+}
+
+/// Indented category tree with subtotals, branches without spendings are left out
+pub fn render_tree(nodes: &[TreeNode]) -> String {
+    fn walk(nodes: &[TreeNode], parent: Option<i64>, depth: usize, lines: &mut Vec<String>) {
+        for (id, _, name, _) in nodes.iter().filter(|(_, p, ..)| *p == parent) {
+            let total = subtree_total(nodes, *id);
+            if total == 0.0 {
+                continue;
+            }
+            lines.push(format!("{}{name} {total:.2}", "  ".repeat(depth)));
+            walk(nodes, Some(*id), depth + 1, lines);
+        }
+    }
+    let mut lines = Vec::new();
+    walk(nodes, None, 0, &mut lines);
+    lines.join("\n")
+}
+
+/// Amount ranges with their cost counts, ranges cut at ascending `edges`
+pub fn render_histogram(edges: &[f64], counts: &[u64]) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0);
+    let mut bounds = vec![0.0];
+    bounds.extend_from_slice(edges);
+    bounds.iter().enumerate().zip(counts).map(|((i, from), count)| {
+        let range = match edges.get(i) {
+            Some(to) => format!("{from}–{to}"),
+            None => format!("{from}+")
+        };
+        let bar = match max {
+            0 => String::new(),
+            _ => "■".repeat((*count as f64 / max as f64 * 10.0).round() as usize)
+        };
+        format!("{range}: {count} {bar}").trim_end().to_string()
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// One bar per value scaled to the biggest one, an all-zero series stays flat
+pub fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 5] = ['▁', '▂', '▃', '▅', '▇'];
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values.iter().map(|v| match max > 0.0 {
+        true => BARS[((v / max) * (BARS.len() - 1) as f64).round().clamp(0.0, (BARS.len() - 1) as f64) as usize],
+        false => BARS[0]
+    }).collect()
+}
+
+/// When a monthly budget runs out at the pace of the month so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Runway {
+    /// Already spent
+    Exhausted,
+    /// Lasts until the month ends
+    Lasts,
+    /// Day of the month it's hit on
+    Day(u32)
+}
+
+/// `elapsed_days` is the part of the month passed, fractions included
+pub fn budget_runway(limit: f64, spent: f64, elapsed_days: f64, month_days: u32) -> Runway {
+    if spent >= limit {
+        return Runway::Exhausted;
+    }
+    if spent <= 0.0 || elapsed_days <= 0.0 {
+        return Runway::Lasts;
+    }
+    let daily = spent / elapsed_days;
+    let day = (limit / daily).ceil() as u32;
+    match day > month_days {
+        true => Runway::Lasts,
+        false => Runway::Day(day)
+    }
+}
+
+/// `22` as `22nd`
+pub fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th"
+    };
+    format!("{n}{suffix}")
+}
+
+/// How much a month-end projection can be trusted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Confidence {
+    Rough,
+    Fair,
+    Likely
+}
+
+impl Confidence {
+    /// Under a third of the month is rough, from two thirds on it's likely
+    pub fn from_elapsed(fraction: f64) -> Self {
+        if fraction < 1.0 / 3.0 {
+            Confidence::Rough
+        } else if fraction < 2.0 / 3.0 {
+            Confidence::Fair
+        } else {
+            Confidence::Likely
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Confidence::Rough => "rough estimate",
+            Confidence::Fair => "fair estimate",
+            Confidence::Likely => "likely accurate"
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        let dt = DateTime::from_timestamp(1738798200, 0).unwrap(); // 2025-02-05 23:30:00 UTC
+        assert_eq!(format_date(dt, chrono_tz::UTC, Lang::Iso), "2025-02-05");
+        assert_eq!(format_date(dt, chrono_tz::Asia::Tokyo, Lang::Iso), "2025-02-06");
+        assert_eq!(format_date(dt, chrono_tz::UTC, Lang::En), "5 Feb 2025");
+        assert_eq!(format_date(dt, chrono_tz::Asia::Tokyo, Lang::Ru), "06.02.2025");
+    }
+
+    #[test]
+    fn test_savings_rate() {
+        assert_eq!(savings_rate(2000.0, 1500.0), Some(25.0));
+        assert_eq!(savings_rate(2000.0, 2500.0), Some(-25.0));
+        assert_eq!(savings_rate(0.0, 100.0), None);
+    }
+
+    #[test]
+    fn test_percent_change() {
+        assert_eq!(percent_change(1000.0, 1200.0), Some(20.0));
+        assert_eq!(percent_change(1000.0, 750.0), Some(-25.0));
+        assert_eq!(percent_change(0.0, 100.0), None);
+    }
+
+    #[test]
+    fn test_goal_progress() {
+        // a quarter of the way with 40% saved is ahead
+        assert_eq!(goal_progress(1000.0, 400.0, 30, 120), Some((40.0, true)));
+        // halfway with a quarter saved is behind
+        assert_eq!(goal_progress(1000.0, 250.0, 60, 120), Some((25.0, false)));
+        // past the deadline only the full amount is on pace
+        assert_eq!(goal_progress(1000.0, 1000.0, 200, 120), Some((100.0, true)));
+        assert_eq!(goal_progress(0.0, 10.0, 1, 10), None);
+        assert_eq!(goal_progress(100.0, 10.0, 1, 0), None);
+    }
+
+    #[test]
+    fn test_render_heatmap() {
+        // April 2025 has 30 days and starts on a Tuesday
+        let first = NaiveDate::from_ymd_opt(2025, 4, 1).unwrap();
+        let days = first.iter_days()
+            .take(30)
+            .map(|d| (d, if d.day() == 10 { 40.0 } else if d.day().is_multiple_of(2) { 10.0 } else { 0.0 }))
+            .collect::<Vec<_>>();
+        let grid = render_heatmap(&days);
+        let lines = grid.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().all(|l| l.chars().count() == 20));
+        assert_eq!(lines[1], "   ·· ░░ ·· ░░ ·· ░░");
+        assert_eq!(lines[2], "·· ░░ ·· ██ ·· ░░ ··");
+    }
+
+    #[test]
+    fn test_budget_runway() {
+        // 300 of 600 in 10 days is 30 a day, the rest lasts another 10 days
+        assert_eq!(budget_runway(600.0, 300.0, 10.0, 31), Runway::Day(20));
+        assert_eq!(budget_runway(600.0, 100.0, 10.0, 31), Runway::Lasts);
+        assert_eq!(budget_runway(600.0, 650.0, 10.0, 31), Runway::Exhausted);
+        assert_eq!(budget_runway(600.0, 0.0, 10.0, 31), Runway::Lasts);
+        // half a day in with a fifth spent runs out on the 3rd
+        assert_eq!(budget_runway(500.0, 100.0, 0.5, 30), Runway::Day(3));
+        assert_eq!(ordinal(22), "22nd");
+        assert_eq!(ordinal(11), "11th");
+        assert_eq!(ordinal(31), "31st");
+        assert_eq!(ordinal(3), "3rd");
+    }
+
+    #[test]
+    fn test_confidence_boundaries() {
+        assert_eq!(Confidence::from_elapsed(0.1), Confidence::Rough);
+        assert_eq!(Confidence::from_elapsed(0.33), Confidence::Rough);
+        assert_eq!(Confidence::from_elapsed(1.0 / 3.0), Confidence::Fair);
+        assert_eq!(Confidence::from_elapsed(0.66), Confidence::Fair);
+        assert_eq!(Confidence::from_elapsed(2.0 / 3.0), Confidence::Likely);
+        assert_eq!(Confidence::from_elapsed(1.0), Confidence::Likely);
+    }
+
+    #[test]
+    fn test_progress_bar_empty() {
+        assert_eq!(progress_bar(0.0, 100.0, 8), "[□□□□□□□□] 0%");
+    }
+
+    #[test]
+    fn test_progress_bar_half() {
+        assert_eq!(progress_bar(52.0, 100.0, 8), "[■■■■□□□□] 52%");
+    }
+
+    #[test]
+    fn test_progress_bar_over() {
+        assert_eq!(progress_bar(120.0, 100.0, 8), "[■■■■■■■■] 120%");
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0.0, 25.0, 50.0, 75.0, 100.0]), "▁▂▃▅▇");
+        assert_eq!(sparkline(&[10.0, 40.0, 0.0]), "▂▇▁");
+        assert_eq!(sparkline(&[0.0, 0.0]), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_split_evenly() {
+        let shares = split_evenly(100.0, 3);
+        assert_eq!(shares, vec![33.34, 33.33, 33.33]);
+        let cents: i64 = shares.iter().map(|s| (s * 100.0).round() as i64).sum();
+        assert_eq!(cents, 10000);
+
+        assert_eq!(split_evenly(10.0, 4), vec![2.5, 2.5, 2.5, 2.5]);
+        assert_eq!(split_evenly(0.05, 2), vec![0.03, 0.02]);
+        assert!(split_evenly(10.0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_render_pivot() {
+        let cells = vec![
+            ("food".to_string(), 202412, 12.5),
+            ("food".to_string(), 202501, 4.0),
+            ("taxi".to_string(), 202501, 7.0)
+        ];
+        assert_eq!(render_pivot(&cells), [
+            "       2024-12   2025-01",
+            "food     12.50      4.00",
+            "taxi         -      7.00"
+        ].join("\n"));
+        assert_eq!(render_pivot(&[]), "");
+    }
+
+    #[test]
+    fn test_compare_to_average() {
+        assert_eq!(compare_to_average(70.0, 50.0).unwrap(), "Today is 40% above your daily average");
+        assert_eq!(compare_to_average(12.5, 50.0).unwrap(), "Today is 75% below your daily average");
+        assert_eq!(compare_to_average(50.0, 50.0).unwrap(), "Today is right at your daily average");
+        assert_eq!(compare_to_average(10.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        assert_eq!(render_histogram(&[10.0, 50.0], &[4, 2, 0]), [
+            "0–10: 4 ■■■■■■■■■■",
+            "10–50: 2 ■■■■■",
+            "50+: 0"
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_describe_delta() {
+        assert_eq!(describe_delta(40.0, 50.0), "40.00 -> 50.00: +10.00 (+25%)");
+        assert_eq!(describe_delta(50.0, 12.5), "50.00 -> 12.50: -37.50 (-75%)");
+        assert_eq!(describe_delta(0.0, 12.5), "0.00 -> 12.50: new");
+        assert_eq!(describe_delta(0.0, 0.0), "No spendings in either period");
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let nodes = vec![
+            (1, None, "Food".to_string(), 10.0),
+            (2, Some(1), "Cafe".to_string(), 5.0),
+            (3, Some(2), "Coffee".to_string(), 2.5),
+            (4, None, "Rent".to_string(), 0.0),
+            (5, None, "Taxi".to_string(), 7.0)
+        ];
+        assert_eq!(subtree_total(&nodes, 1), 17.5);
+        assert_eq!(subtree_total(&nodes, 2), 7.5);
+        assert_eq!(render_tree(&nodes), "Food 17.50\n  Cafe 7.50\n    Coffee 2.50\nTaxi 7.00");
+    }
+}
@@ -0,0 +1,287 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+
+/// Date display language
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum Lang {
+    #[default]
+    Iso,
+    En,
+    Ru
+}
+
+impl Lang {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lang::Iso => "iso",
+            Lang::En => "en",
+            Lang::Ru => "ru"
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "iso" => Ok(Lang::Iso),
+            "en" => Ok(Lang::En),
+            "ru" => Ok(Lang::Ru),
+            _ => Err(format!("unknown language: {s}"))
+        }
+    }
+}
+
+/// Per-chat settings stored as key/value rows in `setting`
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub auto_uncategorized: bool,
+    pub timezone: Tz,
+    pub lang: Lang,
+    pub monthly_income_cent: i64,
+    /// Costs above it need a confirmation, 0 means no cap
+    pub max_transaction_cent: i64,
+    /// Day of month the budget cycle starts on, 0 means the 1st
+    pub fiscal_start_day: u32,
+    /// Plain messages are not logged, commands still work
+    pub muted: bool,
+    /// Successful adds are not confirmed, errors still are
+    pub quiet: bool,
+    /// Commands are recorded in `action_log`, off unless the chat opts in
+    pub audit: bool,
+    /// Summaries show whole amounts, storage and exports keep cents
+    pub round_display: bool,
+    /// Every cost is rounded up to a whole unit and the difference is put aside
+    pub roundup_savings: bool,
+    /// Bearer token for the JSON stats endpoint, none means no access
+    pub api_token: Option<String>,
+    /// Bare integers typed as amounts are cents: `1250` is 12.50, but `12` is 0.12 too.
+    /// Anything with a decimal point keeps its value, so `12.0` still means 12
+    pub integer_cents_input: bool,
+    /// Amounts without a known alias are refused with the category list
+    /// instead of asking for one, so no dialogue is started
+    pub strict_categories: bool,
+    /// Weekday the last week's summary is sent on, none means no digest
+    pub digest_weekday: Option<Weekday>,
+    /// Local date the digest was last sent on
+    pub digest_sent: Option<NaiveDate>,
+    /// Local time of the daily "did you log" nudge, none means no reminder
+    pub remind_at: Option<NaiveTime>,
+    /// Local date the reminder was last sent on
+    pub remind_sent: Option<NaiveDate>
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Setting {
+    AutoUncategorized,
+    Timezone,
+    Lang,
+    MonthlyIncome,
+    MaxTransaction,
+    FiscalStartDay,
+    Muted,
+    Quiet,
+    Audit,
+    RoundDisplay,
+    RoundupSavings,
+    ApiToken,
+    IntegerCentsInput,
+    StrictCategories,
+    DigestWeekday,
+    DigestSent,
+    RemindAt,
+    RemindSent
+}
+
+impl Setting {
+    pub fn key(&self) -> &'static str {
+        match self {
+            Setting::AutoUncategorized => "auto_uncategorized",
+            Setting::Timezone => "timezone",
+            Setting::Lang => "lang",
+            Setting::MonthlyIncome => "monthly_income_cent",
+            Setting::MaxTransaction => "max_transaction_cent",
+            Setting::FiscalStartDay => "fiscal_start_day",
+            Setting::Muted => "muted",
+            Setting::Quiet => "quiet",
+            Setting::Audit => "audit",
+            Setting::RoundDisplay => "round_display",
+            Setting::RoundupSavings => "roundup_savings",
+            Setting::ApiToken => "api_token",
+            Setting::IntegerCentsInput => "integer_cents_input",
+            Setting::StrictCategories => "strict_categories",
+            Setting::DigestWeekday => "digest_weekday",
+            Setting::DigestSent => "digest_sent",
+            Setting::RemindAt => "remind_at",
+            Setting::RemindSent => "remind_sent"
+        }
+    }
+}
+
+impl Settings {
+    pub fn monthly_income(&self) -> f64 {
+        self.monthly_income_cent as f64 / 100.0
+    }
+
+    pub fn max_transaction(&self) -> f64 {
+        self.max_transaction_cent as f64 / 100.0
+    }
+
+    pub fn exceeds_cap(&self, amount: f64) -> bool {
+        self.max_transaction_cent > 0 && (amount * 100.0).round() as i64 > self.max_transaction_cent
+    }
+
+    /// Applies a stored key/value pair, unknown keys and bad values are ignored
+    pub fn apply(&mut self, key: &str, value: &str) {
+        match key {
+            k if k == Setting::AutoUncategorized.key() => self.auto_uncategorized = parse_flag(value),
+            k if k == Setting::Timezone.key() => {
+                if let Ok(tz) = value.parse() {
+                    self.timezone = tz
+                }
+            },
+            k if k == Setting::Lang.key() => {
+                if let Ok(lang) = value.parse() {
+                    self.lang = lang
+                }
+            },
+            k if k == Setting::MonthlyIncome.key() => {
+                if let Ok(cents) = value.parse() {
+                    self.monthly_income_cent = cents
+                }
+            },
+            k if k == Setting::MaxTransaction.key() => {
+                if let Ok(cents) = value.parse() {
+                    self.max_transaction_cent = cents
+                }
+            },
+            k if k == Setting::FiscalStartDay.key() => {
+                if let Ok(day) = value.parse() {
+                    self.fiscal_start_day = day
+                }
+            },
+            k if k == Setting::Muted.key() => self.muted = parse_flag(value),
+            k if k == Setting::Quiet.key() => self.quiet = parse_flag(value),
+            k if k == Setting::Audit.key() => self.audit = parse_flag(value),
+            k if k == Setting::RoundDisplay.key() => self.round_display = parse_flag(value),
+            k if k == Setting::RoundupSavings.key() => self.roundup_savings = parse_flag(value),
+            k if k == Setting::IntegerCentsInput.key() => self.integer_cents_input = parse_flag(value),
+            k if k == Setting::StrictCategories.key() => self.strict_categories = parse_flag(value),
+            k if k == Setting::ApiToken.key() => self.api_token = Some(value.to_string()).filter(|t| !t.is_empty()),
+            k if k == Setting::DigestWeekday.key() => self.digest_weekday = value.parse().ok(),
+            k if k == Setting::DigestSent.key() => {
+                self.digest_sent = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+            },
+            k if k == Setting::RemindAt.key() => self.remind_at = NaiveTime::parse_from_str(value, "%H:%M").ok(),
+            k if k == Setting::RemindSent.key() => {
+                self.remind_sent = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+            },
+            _ => {}
+        }
+    }
+
+    /// Today in the chat's timezone
+    pub fn local_today(&self, now: DateTime<Utc>) -> NaiveDate {
+        now.with_timezone(&self.timezone).date_naive()
+    }
+
+    /// Start of today in the chat's timezone, as a UTC instant
+    pub fn local_midnight(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.timezone
+            .from_local_datetime(&self.local_today(now).and_hms_opt(0, 0, 0).unwrap())
+            .earliest()
+            .map(|d| d.with_timezone(&Utc))
+            .unwrap_or(now)
+    }
+
+    /// Whether the digest should go out now: it's the chosen weekday
+    /// locally and nothing was sent earlier today
+    pub fn digest_due(&self, now: DateTime<Utc>) -> bool {
+        let today = self.local_today(now);
+        self.digest_weekday == Some(today.weekday()) && self.digest_sent != Some(today)
+    }
+
+    /// Whether to nudge now: the reminder time has passed locally, nothing
+    /// was logged today and no reminder went out earlier today
+    pub fn should_remind(&self, now: DateTime<Utc>, logged_today: bool) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        match self.remind_at {
+            Some(at) => !logged_today && local.time() >= at && self.remind_sent != Some(local.date_naive()),
+            None => false
+        }
+    }
+}
+
+pub fn parse_flag(value: &str) -> bool {
+    matches!(value, "1" | "true")
+}
+
+pub fn flag(value: bool) -> String {
+    (if value { "1" } else { "0" }).to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut settings = Settings::default();
+        settings.apply("unknown", "1");
+        assert!(!settings.auto_uncategorized);
+        settings.apply("auto_uncategorized", "1");
+        assert!(settings.auto_uncategorized);
+        settings.apply("timezone", "Asia/Tokyo");
+        assert_eq!(settings.timezone, chrono_tz::Asia::Tokyo);
+        settings.apply("lang", "xx");
+        assert_eq!(settings.lang, Lang::Iso);
+    }
+
+    #[test]
+    fn test_local_midnight() {
+        let now = Utc.with_ymd_and_hms(2025, 3, 3, 20, 0, 0).unwrap();
+        let mut settings = Settings::default();
+        assert_eq!(settings.local_midnight(now), Utc.with_ymd_and_hms(2025, 3, 3, 0, 0, 0).unwrap());
+        // already the 4th in Tokyo
+        settings.apply("timezone", "Asia/Tokyo");
+        assert_eq!(settings.local_midnight(now), Utc.with_ymd_and_hms(2025, 3, 3, 15, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_digest_due() {
+        use chrono::TimeZone;
+        // 2025-03-03 is a Monday
+        let monday = Utc.with_ymd_and_hms(2025, 3, 3, 9, 0, 0).unwrap();
+        let mut settings = Settings::default();
+        assert!(!settings.digest_due(monday));
+        settings.apply("digest_weekday", "mon");
+        assert!(settings.digest_due(monday));
+        assert!(!settings.digest_due(monday + chrono::Duration::days(1)));
+        settings.apply("digest_sent", "2025-03-03");
+        assert!(!settings.digest_due(monday));
+        assert!(settings.digest_due(monday + chrono::Duration::days(7)));
+        // already Tuesday in Tokyo
+        settings.apply("timezone", "Asia/Tokyo");
+        assert!(!settings.digest_due(Utc.with_ymd_and_hms(2025, 3, 10, 20, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_should_remind() {
+        use chrono::TimeZone;
+        let evening = Utc.with_ymd_and_hms(2025, 3, 3, 21, 5, 0).unwrap();
+        let mut settings = Settings::default();
+        assert!(!settings.should_remind(evening, false));
+        settings.apply("remind_at", "21:00");
+        assert!(settings.should_remind(evening, false));
+        assert!(!settings.should_remind(evening, true));
+        assert!(!settings.should_remind(Utc.with_ymd_and_hms(2025, 3, 3, 20, 55, 0).unwrap(), false));
+        settings.apply("remind_sent", "2025-03-03");
+        assert!(!settings.should_remind(evening, false));
+        assert!(settings.should_remind(evening + chrono::Duration::days(1), false));
+    }
+}
@@ -0,0 +1,137 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router
+};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tokio::net::TcpListener;
+use crate::db::{ChatId, DBError, Stat, DB};
+use crate::period::parse_date;
+
+
+#[derive(Error, Debug)]
+pub enum WebError {
+    #[error("missing or wrong token")]
+    Unauthorized,
+    #[error("dates must be in YYYY-MM-DD format")]
+    BadDate,
+    #[error("db error: {0}")]
+    DB(#[from] DBError)
+}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            WebError::Unauthorized => StatusCode::UNAUTHORIZED,
+            WebError::BadDate => StatusCode::BAD_REQUEST,
+            WebError::DB(error) => {
+                // the details are for the operator, not for whoever sent the request
+                tracing::error!(%error, "stats request failed");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "internal error").into_response();
+            }
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// `GET /stats` parameters, dates are `YYYY-MM-DD` and the end is excluded
+#[derive(Deserialize, Debug)]
+pub struct StatsQuery {
+    pub chat_id: i64,
+    pub from: Option<String>,
+    pub to: Option<String>
+}
+
+/// Stat of a chat for a dashboard. The chat's `/apitoken` goes in `Authorization: Bearer`
+pub async fn stats(
+    State(db): State<DB>,
+    headers: HeaderMap,
+    Query(query): Query<StatsQuery>
+) -> Result<Json<Stat>, WebError> {
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let settings = db.get_settings(ChatId(query.chat_id)).await?;
+    let authorized = match (token, settings.api_token.as_deref()) {
+        (Some(token), Some(expected)) => bool::from(token.as_bytes().ct_eq(expected.as_bytes())),
+        _ => false
+    };
+    if !authorized {
+        return Err(WebError::Unauthorized);
+    }
+    let parse = |date: Option<String>| match date {
+        Some(date) => parse_date(&date).map(Some).ok_or(WebError::BadDate),
+        None => Ok(None)
+    };
+    let stat = db.get_stat(ChatId(query.chat_id), parse(query.from)?, parse(query.to)?).await?;
+    Ok(Json(stat))
+}
+
+pub fn router(db: DB) -> Router {
+    Router::new()
+        .route("/stats", get(stats))
+        .with_state(db)
+}
+
+/// Serves the JSON API until the process stops
+pub async fn serve(db: DB, addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(addr, "web api listening");
+    axum::serve(listener, router(db)).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use crate::settings::Setting;
+
+    fn query(from: Option<&str>) -> Query<StatsQuery> {
+        Query(StatsQuery { chat_id: 0, from: from.map(String::from), to: None })
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let db = DB::from_memory().await.unwrap();
+        let food = db.create_category(ChatId(0), "f".to_string(), "Food".to_string()).await.unwrap();
+        db.create_cost(food, 12.5, Some(Utc.with_ymd_and_hms(2025, 3, 1, 10, 0, 0).unwrap())).await.unwrap();
+        db.create_cost(food, 2.5, Some(Utc.with_ymd_and_hms(2025, 2, 1, 10, 0, 0).unwrap())).await.unwrap();
+
+        let denied = stats(State(db.clone()), bearer("secret"), query(None)).await;
+        assert!(matches!(denied, Err(WebError::Unauthorized)));
+        db.set_setting(ChatId(0), Setting::ApiToken, "secret".to_string()).await.unwrap();
+        let denied = stats(State(db.clone()), HeaderMap::new(), query(None)).await;
+        assert!(matches!(denied, Err(WebError::Unauthorized)));
+
+        let Json(stat) = stats(State(db.clone()), bearer("secret"), query(Some("2025-03-01"))).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&stat).unwrap(),
+            serde_json::json!({
+                "items": [
+                    {"category": {"alias": "f", "name": "Food"}, "n_items": 1, "amount": 12.5}
+                ]
+            })
+        );
+        let bad = stats(State(db), bearer("secret"), query(Some("March"))).await;
+        assert!(matches!(bad, Err(WebError::BadDate)));
+    }
+
+    #[tokio::test]
+    async fn test_db_error_is_hidden() {
+        let response = WebError::DB(DBError::ReadOnly).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"internal error");
+    }
+}